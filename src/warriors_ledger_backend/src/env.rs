@@ -0,0 +1,50 @@
+use candid::Principal;
+
+//clock and caller of the message being executed. The system API only exists inside a canister, so
+//unit tests read a clock and caller that they set themselves
+#[cfg(not(test))]
+pub fn time() -> u64 {
+    ic_cdk::api::time()
+}
+
+#[cfg(not(test))]
+pub fn caller() -> Principal {
+    ic_cdk::caller()
+}
+
+#[cfg(not(test))]
+pub fn is_controller(principal: &Principal) -> bool {
+    ic_cdk::api::is_controller(principal)
+}
+
+#[cfg(test)]
+thread_local! {
+    static TIME: std::cell::Cell<u64> = const { std::cell::Cell::new(1) };
+    static CALLER: std::cell::Cell<Principal> = const { std::cell::Cell::new(Principal::anonymous()) };
+}
+
+#[cfg(test)]
+pub fn time() -> u64 {
+    TIME.with(|time| time.get())
+}
+
+#[cfg(test)]
+pub fn caller() -> Principal {
+    CALLER.with(|caller| caller.get())
+}
+
+// tests have no controllers, roles are granted through the role storage instead
+#[cfg(test)]
+pub fn is_controller(_principal: &Principal) -> bool {
+    false
+}
+
+#[cfg(test)]
+pub fn set_time(now: u64) {
+    TIME.with(|time| time.set(now));
+}
+
+#[cfg(test)]
+pub fn set_caller(principal: Principal) {
+    CALLER.with(|caller| caller.set(principal));
+}
@@ -0,0 +1,193 @@
+use candid::{CandidType, Nat, Principal};
+
+//ICRC-1 account, the default subaccount is used when subaccount is None
+#[derive(CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+//arguments of icrc2_transfer_from as defined by the ICRC-2 standard
+#[derive(CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct TransferFromArgs {
+    pub spender_subaccount: Option<Vec<u8>>,
+    pub from: Account,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+//errors returned by icrc2_transfer_from as defined by the ICRC-2 standard
+#[derive(CandidType, Clone, Debug, Serialize, Deserialize)]
+pub enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+//arguments of icrc1_transfer as defined by the ICRC-1 standard
+#[derive(CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+//errors returned by icrc1_transfer as defined by the ICRC-1 standard
+#[derive(CandidType, Clone, Debug, Serialize, Deserialize)]
+pub enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+//why a ledger transfer did not go through
+#[derive(Clone, Debug, PartialEq)]
+pub enum LedgerError {
+    // the ledger refused the transfer. It checks for duplicates first, so an earlier transfer with the
+    // same created_at_time and memo was not executed either
+    Rejected(String),
+    // the transfer may or may not have been executed, sending it again with the same created_at_time
+    // and memo tells which
+    Unknown(String),
+}
+
+impl LedgerError {
+    pub fn message(self) -> String {
+        match self {
+            LedgerError::Rejected(msg) => msg,
+            LedgerError::Unknown(msg) => format!("the ledger did not confirm the transfer: {}", msg),
+        }
+    }
+}
+
+//token ledger that purchases are paid on, tests replace the ICRC ledger canister with a stand-in.
+//Transfers carry a created_at_time and memo so the ledger executes a transfer that is sent again at
+//most once, and answers the repeat with the block of the first one
+pub trait Ledger {
+    // principal of the ledger canister
+    fn id(&self) -> Principal;
+
+    // moves amount from the from account to the to account using the allowance given to this canister,
+    // returns the index of the ledger block that records the transfer
+    async fn transfer_from(
+        &self,
+        from: Account,
+        to: Account,
+        amount: u64,
+        created_at_time: u64,
+        memo: Vec<u8>,
+    ) -> Result<Nat, LedgerError>;
+
+    // fee charged by the ledger for a transfer
+    async fn fee(&self) -> Result<u64, String>;
+
+    // moves amount from this canister's account to the to account, paying the given fee on top
+    async fn transfer(
+        &self,
+        to: Account,
+        amount: u64,
+        fee: u64,
+        created_at_time: u64,
+        memo: Vec<u8>,
+    ) -> Result<Nat, LedgerError>;
+}
+
+//ICRC-1 ledger canister that supports ICRC-2
+pub struct IcrcLedger(pub Principal);
+
+// a rejected call may have reached the ledger, so its outcome is unknown
+fn call_error((code, msg): (ic_cdk::api::call::RejectionCode, String)) -> LedgerError {
+    LedgerError::Unknown(format!("ledger call rejected with {:?}: {}", code, msg))
+}
+
+impl Ledger for IcrcLedger {
+    fn id(&self) -> Principal {
+        self.0
+    }
+
+    async fn transfer_from(
+        &self,
+        from: Account,
+        to: Account,
+        amount: u64,
+        created_at_time: u64,
+        memo: Vec<u8>,
+    ) -> Result<Nat, LedgerError> {
+        let args = TransferFromArgs {
+            spender_subaccount: None,
+            from,
+            to,
+            amount: Nat::from(amount),
+            fee: None,
+            memo: Some(memo),
+            created_at_time: Some(created_at_time),
+        };
+        let (result,): (Result<Nat, TransferFromError>,) =
+            ic_cdk::call(self.0, "icrc2_transfer_from", (args,)).await.map_err(call_error)?;
+        match result {
+            Ok(block_index) | Err(TransferFromError::Duplicate { duplicate_of: block_index }) => Ok(block_index),
+            // these are checked before duplicates, an earlier transfer may still have been executed
+            Err(
+                error @ (TransferFromError::TooOld
+                | TransferFromError::TemporarilyUnavailable
+                | TransferFromError::GenericError { .. }),
+            ) => Err(LedgerError::Unknown(format!("ledger rejected the transfer: {:?}", error))),
+            Err(error) => Err(LedgerError::Rejected(format!("ledger rejected the transfer: {:?}", error))),
+        }
+    }
+
+    async fn fee(&self) -> Result<u64, String> {
+        let (fee,): (Nat,) = ic_cdk::call(self.0, "icrc1_fee", ())
+            .await
+            .map_err(|(code, msg)| format!("ledger call rejected with {:?}: {}", code, msg))?;
+        u64::try_from(&fee.0).map_err(|_| format!("ledger fee {} does not fit in 64 bits", fee))
+    }
+
+    async fn transfer(
+        &self,
+        to: Account,
+        amount: u64,
+        fee: u64,
+        created_at_time: u64,
+        memo: Vec<u8>,
+    ) -> Result<Nat, LedgerError> {
+        let args = TransferArg {
+            from_subaccount: None,
+            to,
+            amount: Nat::from(amount),
+            fee: Some(Nat::from(fee)),
+            memo: Some(memo),
+            created_at_time: Some(created_at_time),
+        };
+        let (result,): (Result<Nat, TransferError>,) =
+            ic_cdk::call(self.0, "icrc1_transfer", (args,)).await.map_err(call_error)?;
+        match result {
+            Ok(block_index) | Err(TransferError::Duplicate { duplicate_of: block_index }) => Ok(block_index),
+            // these are checked before duplicates, an earlier transfer may still have been executed
+            Err(
+                error @ (TransferError::BadFee { .. }
+                | TransferError::TooOld
+                | TransferError::TemporarilyUnavailable
+                | TransferError::GenericError { .. }),
+            ) => Err(LedgerError::Unknown(format!("ledger rejected the transfer: {:?}", error))),
+            Err(error) => Err(LedgerError::Rejected(format!("ledger rejected the transfer: {:?}", error))),
+        }
+    }
+}
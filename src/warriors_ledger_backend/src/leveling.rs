@@ -0,0 +1,108 @@
+use crate::types::{LevelingConfig, MatchOutcome, Participant};
+
+//levels start at 1, the first threshold is the XP needed to reach level 2
+pub const FIRST_LEVEL: u64 = 1;
+
+// default curve, reaching level n takes 50 * (n - 1) * n XP, up to level 100
+pub fn default_level_thresholds() -> Vec<u64> {
+    (2..=100u64).map(|level| 50 * (level - 1) * level).collect()
+}
+
+// check that a leveling config describes a curve players can climb
+pub fn validate_config(config: &LevelingConfig) -> Result<(), String> {
+    if config.level_thresholds.is_empty() {
+        return Err("the level curve needs at least one threshold".to_string());
+    }
+    if config.level_thresholds[0] == 0 {
+        return Err("reaching level 2 must require some XP".to_string());
+    }
+    if config.level_thresholds.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return Err("level thresholds must be strictly increasing".to_string());
+    }
+    Ok(())
+}
+
+// XP earned by one participant of a completed match, the match time is in minutes
+pub fn match_xp(config: &LevelingConfig, participant: &Participant, time: u64) -> u64 {
+    let outcome_xp = match participant.outcome {
+        MatchOutcome::Win => config.xp_per_win,
+        MatchOutcome::Draw => config.xp_per_draw,
+        MatchOutcome::Loss => config.xp_per_loss,
+    };
+    participant
+        .score
+        .saturating_mul(config.xp_per_score)
+        .saturating_add(outcome_xp)
+        .saturating_add(time.saturating_mul(config.xp_per_minute))
+}
+
+// level reached with the given total XP
+pub fn level_for_xp(config: &LevelingConfig, xp: u64) -> u64 {
+    let levels_gained = config
+        .level_thresholds
+        .iter()
+        .take_while(|threshold| **threshold <= xp)
+        .count() as u64;
+    FIRST_LEVEL + levels_gained
+}
+
+// total XP needed to reach a level, levels past the top of the curve need the top threshold
+pub fn xp_for_level(config: &LevelingConfig, level: u64) -> u64 {
+    if level <= FIRST_LEVEL {
+        return 0;
+    }
+    let index = ((level - FIRST_LEVEL - 1) as usize).min(config.level_thresholds.len() - 1);
+    config.level_thresholds[index]
+}
+
+// total XP needed for the level after the given one, None at the top of the curve
+pub fn next_level_xp(config: &LevelingConfig, level: u64) -> Option<u64> {
+    let index = level.checked_sub(FIRST_LEVEL)? as usize;
+    config.level_thresholds.get(index).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(level_thresholds: Vec<u64>) -> LevelingConfig {
+        LevelingConfig {
+            level_thresholds,
+            ..LevelingConfig::default()
+        }
+    }
+
+    #[test]
+    fn levels_follow_the_thresholds() {
+        let config = config(vec![100, 300, 600]);
+        assert_eq!(level_for_xp(&config, 0), 1);
+        assert_eq!(level_for_xp(&config, 99), 1);
+        assert_eq!(level_for_xp(&config, 100), 2);
+        assert_eq!(level_for_xp(&config, 300), 3);
+        assert_eq!(level_for_xp(&config, 600), 4);
+        assert_eq!(level_for_xp(&config, u64::MAX), 4);
+    }
+
+    #[test]
+    fn xp_needed_for_levels() {
+        let config = config(vec![100, 300, 600]);
+        assert_eq!(xp_for_level(&config, 0), 0);
+        assert_eq!(xp_for_level(&config, 1), 0);
+        assert_eq!(xp_for_level(&config, 2), 100);
+        assert_eq!(xp_for_level(&config, 4), 600);
+        assert_eq!(xp_for_level(&config, 9), 600);
+        assert_eq!(next_level_xp(&config, 1), Some(100));
+        assert_eq!(next_level_xp(&config, 3), Some(600));
+        assert_eq!(next_level_xp(&config, 4), None);
+        assert_eq!(next_level_xp(&config, 0), None);
+    }
+
+    #[test]
+    fn curves_must_be_climbable() {
+        assert!(validate_config(&LevelingConfig::default()).is_ok());
+        assert!(validate_config(&config(vec![])).is_err());
+        assert!(validate_config(&config(vec![0, 100])).is_err());
+        assert!(validate_config(&config(vec![100, 100])).is_err());
+        assert!(validate_config(&config(vec![100, 50])).is_err());
+    }
+}
@@ -1,12 +1,81 @@
 #[macro_use]
 extern crate serde;
+use candid::Principal;
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
-use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap};
+use ic_stable_structures::{Cell, DefaultMemoryImpl, StableBTreeMap, Storable};
 use std::cell::RefCell;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
 
+mod env;
+mod icrc;
+mod leveling;
+mod rating;
+mod stats;
 mod types;
 use types::*;
 
+//schema version of the records written by this build of the canister
+const CURRENT_SCHEMA_VERSION: u64 = 17;
+
+//largest number of records returned by one page of a paginated query
+const MAX_PAGE_SIZE: u64 = 100;
+
+//longest time a trade offer can stay open
+const MAX_TRADE_OFFER_SECONDS: u64 = 7 * 24 * 60 * 60;
+
+//delay before retrying a quest rotation that failed
+const QUEST_ROTATION_RETRY: Duration = Duration::from_secs(60);
+
+//delay before retrying to end a season that failed to end
+const SEASON_END_RETRY: Duration = Duration::from_secs(60);
+
+//largest number of standings archived or ratings reset by one timer tick while a season ends
+const SEASON_END_BATCH: usize = 500;
+
+//longest error message kept in stable memory for a failed timer
+const MAX_TIMER_ERROR_LEN: usize = 512;
+
+//largest number of tiers of a battle pass
+const MAX_BATTLE_PASS_TIERS: usize = 100;
+
+//largest number of loadouts of a player
+const MAX_LOADOUTS_PER_PLAYER: usize = 10;
+
+//longest dispute reason or resolution note of a match, in bytes
+const MAX_DISPUTE_NOTE_LEN: usize = 256;
+
+//largest number of times a match can be disputed, each round adds two transitions to the match
+const MAX_DISPUTE_ROUNDS: usize = 3;
+
+//name of the loadout every player starts with
+const DEFAULT_LOADOUT_NAME: &str = "Default";
+
+//rank of new players, ranks gate weapon purchases so only moderators can change them
+const FIRST_RANK: u64 = 1;
+
+//migrations run by post_upgrade, the entry at index i upgrades schema version i to i + 1
+const MIGRATIONS: &[fn()] = &[
+    migrate_v0_to_v1,
+    migrate_v1_to_v2,
+    migrate_v2_to_v3,
+    migrate_v3_to_v4,
+    migrate_v4_to_v5,
+    migrate_v5_to_v6,
+    migrate_v6_to_v7,
+    migrate_v7_to_v8,
+    migrate_v8_to_v9,
+    migrate_v9_to_v10,
+    migrate_v10_to_v11,
+    migrate_v11_to_v12,
+    migrate_v12_to_v13,
+    migrate_v13_to_v14,
+    migrate_v14_to_v15,
+    migrate_v15_to_v16,
+    migrate_v16_to_v17,
+];
+
 //Declare thread local variables
 thread_local! {
     static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
@@ -28,20 +97,700 @@ thread_local! {
         IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3))), 0)
             .expect("Cannot create a counter")
     );
-    static PLAYER_PROFILE_STORAGE: RefCell<StableBTreeMap<u64, PlayerProfile, Memory>> = RefCell::new(
+    static PLAYER_PROFILE_STORAGE: RefCell<StableBTreeMap<u64, Stored<PlayerProfile>, Memory>> = RefCell::new(
         StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4))))
     );
-    static WEAPON_PROFILE_STORAGE: RefCell<StableBTreeMap<u64, Weapon, Memory>> = RefCell::new(
+    static WEAPON_PROFILE_STORAGE: RefCell<StableBTreeMap<u64, Stored<Weapon>, Memory>> = RefCell::new(
         StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5))))
     );
-    static MATCH_PROFILE_STORAGE: RefCell<StableBTreeMap<u64, Match, Memory>> = RefCell::new(
+    static MATCH_PROFILE_STORAGE: RefCell<StableBTreeMap<u64, Stored<Match>, Memory>> = RefCell::new(
         StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6))))
     );
-    static LEADERBOARD_STORAGE: RefCell<StableBTreeMap<u64, Leaderboard, Memory>> = RefCell::new(
+    static LEADERBOARD_STORAGE: RefCell<StableBTreeMap<u64, Stored<Leaderboard>, Memory>> = RefCell::new(
         StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7))))
     );
+    static ROLE_STORAGE: RefCell<StableBTreeMap<StorablePrincipal, Stored<RoleAssignment>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8))))
+    );
+    static SCHEMA_VERSION: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(9))), 0)
+            .expect("Cannot create the schema version cell")
+    );
+    //(player_id, weapon_id) pairs for every weapon owned by a player
+    static PLAYER_WEAPON_STORAGE: RefCell<RelationMap<()>> = RefCell::new(
+        RelationMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10))),
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(74))),
+        )
+    );
+    //(player_id, match_id) pairs for every match played by a player
+    static PLAYER_MATCH_STORAGE: RefCell<RelationMap<()>> = RefCell::new(
+        RelationMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11))),
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(75))),
+        )
+    );
+    static STANDING_STORAGE: RefCell<StableBTreeMap<u64, Stored<PlayerStanding>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12))))
+    );
+    //standings in leaderboard order, the position of a player is its index in this map
+    static STANDING_INDEX: RefCell<StableBTreeMap<StandingKey, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13))))
+    );
+    static RATING_STORAGE: RefCell<StableBTreeMap<u64, Stored<PlayerRating>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(14))))
+    );
+    //rating changes keyed by (player_id, match_id)
+    static RATING_HISTORY_STORAGE: RefCell<StableBTreeMap<(u64, u64), Stored<RatingChange>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15))))
+    );
+    static RATING_CONFIG: RefCell<Cell<Stored<RatingConfig>, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(16))),
+            Stored::new(&RatingConfig::default()).expect("cannot encode the default rating config"),
+        )
+        .expect("Cannot create the rating config cell")
+    );
+    //soft-currency balance of every player that has been credited
+    static BALANCE_STORAGE: RefCell<StableBTreeMap<u64, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17))))
+    );
+    static TRANSACTION_ID_COUNTER: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(18))), 0)
+            .expect("Cannot create a counter")
+    );
+    static TRANSACTION_STORAGE: RefCell<StableBTreeMap<u64, Stored<Transaction>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19))))
+    );
+    //(player_id, transaction_id) pairs for every transaction of a player
+    static PLAYER_TRANSACTION_STORAGE: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20))))
+    );
+    static PAYMENT_CONFIG: RefCell<Cell<Stored<PaymentConfig>, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(21))),
+            Stored::new(&PaymentConfig::default()).expect("cannot encode the default payment config"),
+        )
+        .expect("Cannot create the payment config cell")
+    );
+    //price in ledger tokens of every weapon that can be bought with tokens
+    static WEAPON_TOKEN_PRICE_STORAGE: RefCell<StableBTreeMap<u64, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(22))))
+    );
+    static TOKEN_PURCHASE_ID_COUNTER: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(23))), 0)
+            .expect("Cannot create a counter")
+    );
+    static TOKEN_PURCHASE_STORAGE: RefCell<StableBTreeMap<u64, Stored<TokenPurchase>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(24))))
+    );
+    //(player_id, token_purchase_id) pairs for every token purchase of a player
+    static PLAYER_TOKEN_PURCHASE_STORAGE: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(25))))
+    );
+    static TRADE_ID_COUNTER: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(26))), 0)
+            .expect("Cannot create a counter")
+    );
+    static TRADE_STORAGE: RefCell<StableBTreeMap<u64, Stored<TradeOffer>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(27))))
+    );
+    //(player_id, trade_id) pairs for every trade offer made or received by a player
+    static PLAYER_TRADE_STORAGE: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(28))))
+    );
+    //(player_id, weapon_id) pairs held in escrow, mapped to the open trade offer holding them
+    static ESCROWED_WEAPON_STORAGE: RefCell<StableBTreeMap<(u64, u64), u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(29))))
+    );
+    static LISTING_ID_COUNTER: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(30))), 0)
+            .expect("Cannot create a counter")
+    );
+    static LISTING_STORAGE: RefCell<StableBTreeMap<u64, Stored<Listing>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(31))))
+    );
+    //(player_id, listing_id) pairs for every listing created by a player
+    static PLAYER_LISTING_STORAGE: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(32))))
+    );
+    //(player_id, weapon_id) pairs listed on the marketplace, mapped to the active listing
+    static LISTED_WEAPON_STORAGE: RefCell<StableBTreeMap<(u64, u64), u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(33))))
+    );
+    static MARKET_CONFIG: RefCell<Cell<Stored<MarketConfig>, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(34))),
+            Stored::new(&MarketConfig::default()).expect("cannot encode the default market config"),
+        )
+        .expect("Cannot create the market config cell")
+    );
+    static MARKET_FEES_COLLECTED: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(35))), 0)
+            .expect("Cannot create the market fees cell")
+    );
+    //total XP earned by every player
+    static XP_STORAGE: RefCell<StableBTreeMap<u64, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(36))))
+    );
+    static LEVELING_CONFIG: RefCell<Cell<Stored<LevelingConfig>, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(37))),
+            Stored::new(&LevelingConfig::default()).expect("cannot encode the default leveling config"),
+        )
+        .expect("Cannot create the leveling config cell")
+    );
+    static ACHIEVEMENT_ID_COUNTER: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(38))), 0)
+            .expect("Cannot create a counter")
+    );
+    static ACHIEVEMENT_STORAGE: RefCell<StableBTreeMap<u64, Stored<Achievement>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(39))))
+    );
+    //progress of (player_id, achievement_id) pairs towards achievements that are not unlocked yet
+    static ACHIEVEMENT_PROGRESS_STORAGE: RefCell<RelationMap<u64>> = RefCell::new(
+        RelationMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(40))),
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(76))),
+        )
+    );
+    //(player_id, achievement_id) pairs of unlocked achievements, mapped to the unlock time
+    static PLAYER_ACHIEVEMENT_STORAGE: RefCell<RelationMap<u64>> = RefCell::new(
+        RelationMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(41))),
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(77))),
+        )
+    );
+    static QUEST_ID_COUNTER: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(42))), 0)
+            .expect("Cannot create a counter")
+    );
+    static QUEST_TEMPLATE_STORAGE: RefCell<StableBTreeMap<u64, Stored<QuestTemplate>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(43))))
+    );
+    //current rotation of every quest cadence, keyed by QuestCadence::key
+    static QUEST_ROTATION_STORAGE: RefCell<StableBTreeMap<u64, Stored<QuestRotation>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(44))))
+    );
+    //progress of (player_id, quest_id) pairs, only the entries of the current period count
+    static QUEST_PROGRESS_STORAGE: RefCell<RelationMap<Stored<QuestProgress>>> = RefCell::new(
+        RelationMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(45))),
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(78))),
+        )
+    );
+    static SEASON_ID_COUNTER: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(46))), 0)
+            .expect("Cannot create a counter")
+    );
+    static SEASON_STORAGE: RefCell<StableBTreeMap<u64, Stored<Season>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(47))))
+    );
+    //standings of (season_id, player_id) pairs for the seasons that have not ended
+    static SEASON_STANDING_STORAGE: RefCell<StableBTreeMap<(u64, u64), Stored<PlayerStanding>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(48))))
+    );
+    //season standings ordered by season, then like STANDING_INDEX
+    static SEASON_STANDING_INDEX: RefCell<StableBTreeMap<(u64, StandingKey), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(49))))
+    );
+    //final standings of ended seasons, keyed by (season_id, position)
+    static SEASON_ARCHIVE_STORAGE: RefCell<StableBTreeMap<(u64, u64), Stored<LeaderboardEntry>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(50))))
+    );
+    static SEASON_CONFIG: RefCell<Cell<Stored<SeasonConfig>, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(51))),
+            Stored::new(&SeasonConfig::default()).expect("cannot encode the default season config"),
+        )
+        .expect("Cannot create the season config cell")
+    );
+    //battle pass of every season that has one, keyed by season_id
+    static BATTLE_PASS_STORAGE: RefCell<StableBTreeMap<u64, Stored<BattlePass>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(52))))
+    );
+    //battle pass progress of (season_id, player_id) pairs
+    static BATTLE_PASS_PROGRESS_STORAGE: RefCell<RelationMap<Stored<BattlePassProgress>>> = RefCell::new(
+        RelationMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(53))),
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(79))),
+        )
+    );
+    static LOADOUT_ID_COUNTER: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(54))), 0)
+            .expect("Cannot create a counter")
+    );
+    static LOADOUT_STORAGE: RefCell<StableBTreeMap<u64, Stored<Loadout>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(55))))
+    );
+    //(player_id, loadout_id) pairs
+    static PLAYER_LOADOUT_STORAGE: RefCell<StableBTreeMap<(u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(56))))
+    );
+    //active loadout of every player that has a loadout, keyed by player_id
+    static ACTIVE_LOADOUT_STORAGE: RefCell<StableBTreeMap<u64, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(57))))
+    );
+    static ATTACHMENT_ID_COUNTER: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(58))), 0)
+            .expect("Cannot create a counter")
+    );
+    static ATTACHMENT_STORAGE: RefCell<StableBTreeMap<u64, Stored<Attachment>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(59))))
+    );
+    //(player_id, attachment_id) pairs of owned attachments
+    static PLAYER_ATTACHMENT_STORAGE: RefCell<RelationMap<()>> = RefCell::new(
+        RelationMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(60))),
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(80))),
+        )
+    );
+    //(player_id, attachment_id) pairs of attached attachments, mapped to the weapon they are attached to
+    static ATTACHED_STORAGE: RefCell<RelationMap<u64>> = RefCell::new(
+        RelationMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(61))),
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(81))),
+        )
+    );
+    static WEAPON_ANALYTICS_CONFIG: RefCell<Cell<Stored<WeaponAnalyticsConfig>, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(62))),
+            Stored::new(&WeaponAnalyticsConfig::default()).expect("cannot encode the default weapon analytics config"),
+        )
+        .expect("Cannot create the weapon analytics config cell")
+    );
+    static WEAPON_TELEMETRY_STORAGE: RefCell<StableBTreeMap<u64, Stored<WeaponTelemetry>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(63))))
+    );
+    static TELEMETRY_CONFIG: RefCell<Cell<Stored<TelemetryConfig>, Memory>> = RefCell::new(
+        Cell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(64))),
+            Stored::new(&TelemetryConfig::default()).expect("cannot encode the default telemetry config"),
+        )
+        .expect("Cannot create the telemetry config cell")
+    );
+    //(weapon_id, revision) pairs of weapon revisions
+    static WEAPON_REVISION_STORAGE: RefCell<StableBTreeMap<(u64, u64), Stored<WeaponRevision>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(65))))
+    );
+    static BALANCE_PATCH_ID_COUNTER: RefCell<IdCell> = RefCell::new(
+        IdCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(66))), 0)
+            .expect("Cannot create a counter")
+    );
+    static BALANCE_PATCH_STORAGE: RefCell<StableBTreeMap<u64, Stored<BalancePatch>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(67))))
+    );
+    //(patch_id, weapon_id, revision) triples of the weapon revisions in each balance patch
+    static PATCH_REVISION_STORAGE: RefCell<StableBTreeMap<(u64, u64, u64), (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(68))))
+    );
+    //ids of the trade offers that are still open
+    static OPEN_TRADE_STORAGE: RefCell<StableBTreeMap<u64, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(69))))
+    );
+    //progress of the seasons that are being ended
+    static SEASON_END_STORAGE: RefCell<StableBTreeMap<u64, Stored<SeasonEnd>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(70))))
+    );
+    //(season_id, player_id) to the archived position of the player in the season
+    static SEASON_POSITION_STORAGE: RefCell<StableBTreeMap<(u64, u64), u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(71))))
+    );
+    //cadence key to the last failure of its quest rotation timer
+    static QUEST_ROTATION_FAILURE_STORAGE: RefCell<StableBTreeMap<u64, Stored<QuestRotationFailure>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(72))))
+    );
+    //ids of the listings that are still active
+    static ACTIVE_LISTING_STORAGE: RefCell<StableBTreeMap<u64, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(73))))
+    );
+    //(player_id, weapon_id) pairs of token purchases waiting for the ledger, kept on the heap because
+    //a canister is only upgraded once every outstanding call has returned
+    static PENDING_TOKEN_PURCHASES: RefCell<BTreeSet<(u64, u64)>> = const { RefCell::new(BTreeSet::new()) };
+    //ids of token purchases whose refund is waiting for the ledger
+    static PENDING_TOKEN_REFUNDS: RefCell<BTreeSet<u64>> = const { RefCell::new(BTreeSet::new()) };
+
+
+}
+
+//seed the first admin, defaulting to the principal that installs the canister
+#[ic_cdk::init]
+fn init(admin: Option<Principal>) {
+    let admin = admin.unwrap_or_else(env::caller);
+    do_insert_roles(&RoleAssignment {
+        principal: admin,
+        roles: vec![Role::Admin],
+    })
+    .expect("cannot store the first admin");
+    set_schema_version(CURRENT_SCHEMA_VERSION);
+    for cadence in QuestCadence::ALL {
+        schedule_quest_rotation(cadence);
+    }
+}
+
+//bring stable memory written by an older build up to the current schema version
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    let stored_version = SCHEMA_VERSION.with(|cell| *cell.borrow().get());
+    if stored_version > CURRENT_SCHEMA_VERSION {
+        ic_cdk::trap(&format!(
+            "stable memory has schema version {} but this build only supports up to {}",
+            stored_version, CURRENT_SCHEMA_VERSION
+        ));
+    }
+
+    for version in stored_version..CURRENT_SCHEMA_VERSION {
+        MIGRATIONS[version as usize]();
+        set_schema_version(version + 1);
+    }
+    //timers do not survive upgrades, the rotations stored in stable memory tell when to fire again
+    for cadence in QuestCadence::ALL {
+        schedule_quest_rotation(cadence);
+    }
+    let seasons: Vec<Season> = SEASON_STORAGE
+        .with(|service| service.borrow().records())
+        .expect("cannot decode seasons");
+    let season_ends: Vec<SeasonEnd> = SEASON_END_STORAGE
+        .with(|service| service.borrow().records())
+        .expect("cannot decode season ends");
+    for season in seasons.iter().filter(|season| season.ended_at.is_none()) {
+        if !season_ends.iter().any(|season_end| season_end.season_id == season.id) {
+            schedule_season_end(season.id, season.ends_at);
+        }
+    }
+    for season_end in season_ends.iter() {
+        schedule_season_end_step(season_end.season_id, Duration::ZERO);
+    }
+}
+
+// helper function to record the schema version of stable memory
+fn set_schema_version(version: u64) {
+    SCHEMA_VERSION
+        .with(|cell| cell.borrow_mut().set(version))
+        .expect("cannot set schema version");
+}
+
+//schema version 1 wraps every record in a versioned envelope and records player owners
+fn migrate_v0_to_v1() {
+    for (id, profile) in legacy_player_profiles() {
+        let stored = Stored::new(&profile).expect("cannot migrate player profile").cast();
+        PLAYER_PROFILE_STORAGE.with(|service| service.borrow_mut().insert(id, stored));
+    }
+    WEAPON_PROFILE_STORAGE.with(|service| rewrite_records(&mut service.borrow_mut()));
+    MATCH_PROFILE_STORAGE.with(|service| rewrite_records(&mut service.borrow_mut()));
+    LEADERBOARD_STORAGE.with(|service| rewrite_records(&mut service.borrow_mut()));
+    ROLE_STORAGE.with(|service| rewrite_records(&mut service.borrow_mut()));
+}
+
+//schema version 2 moves embedded weapons and matches out of player profiles into relation maps
+fn migrate_v1_to_v2() {
+    for (_, profile) in legacy_player_profiles() {
+        for weapon in profile.weapons.iter() {
+            if WEAPON_PROFILE_STORAGE.with(|service| service.borrow().contains_key(&weapon.id)) {
+                PLAYER_WEAPON_STORAGE
+                    .with(|service| service.borrow_mut().insert((profile.id, weapon.id), ()));
+            }
+        }
+        for match_profile in profile.match_history.iter() {
+            if MATCH_PROFILE_STORAGE.with(|service| service.borrow().contains_key(&match_profile.id)) {
+                PLAYER_MATCH_STORAGE
+                    .with(|service| service.borrow_mut().insert((profile.id, match_profile.id), ()));
+            }
+        }
+        do_insert_player(&PlayerProfile::from(profile)).expect("cannot migrate player profile");
+    }
+}
+
+//schema version 3 builds the computed leaderboard from the matches recorded so far
+fn migrate_v2_to_v3() {
+    let matches: Vec<Match> = MATCH_PROFILE_STORAGE
+        .with(|service| service.borrow().records())
+        .expect("cannot decode matches");
+    for match_profile in matches.iter() {
+        do_add_match_to_standings(match_profile).expect("cannot migrate player standings");
+    }
+}
+
+//schema version 4 stores every match as a list of participants
+fn migrate_v3_to_v4() {
+    MATCH_PROFILE_STORAGE.with(|service| rewrite_records(&mut service.borrow_mut()));
+}
+
+//schema version 5 gives every match a status, matches recorded before are completed
+fn migrate_v4_to_v5() {
+    MATCH_PROFILE_STORAGE.with(|service| rewrite_records(&mut service.borrow_mut()));
+}
+
+//schema version 6 tracks XP, existing players start with the XP of the level they had reached
+fn migrate_v5_to_v6() {
+    let config = get_leveling_config().expect("cannot decode the leveling config");
+    let players: Vec<PlayerProfile> = PLAYER_PROFILE_STORAGE
+        .with(|service| service.borrow().records())
+        .expect("cannot decode players");
+    XP_STORAGE.with(|service| {
+        let mut xp = service.borrow_mut();
+        for player in players.iter() {
+            xp.insert(player.id, leveling::xp_for_level(&config, player.level));
+        }
+    });
+}
+
+//schema version 7 records the damage dealt by each participant, unknown for older matches
+fn migrate_v6_to_v7() {
+    MATCH_PROFILE_STORAGE.with(|service| rewrite_records(&mut service.borrow_mut()));
+}
+
+//schema version 8 records the season of every leaderboard entry, older entries belong to no season
+fn migrate_v7_to_v8() {
+    LEADERBOARD_STORAGE.with(|service| rewrite_records(&mut service.borrow_mut()));
+}
+
+//schema version 9 builds weapon telemetry from the completed and disputed matches recorded so far
+fn migrate_v8_to_v9() {
+    let matches: Vec<Match> = MATCH_PROFILE_STORAGE
+        .with(|service| service.borrow().records())
+        .expect("cannot decode matches");
+    for match_profile in matches.iter().filter(|match_profile| match_profile.status.is_finalized()) {
+        do_add_match_to_weapon_telemetry(match_profile).expect("cannot migrate weapon telemetry");
+    }
+}
+
+//schema version 10 keeps the history of every weapon, existing weapons start at their current stats
+fn migrate_v9_to_v10() {
+    let weapons: Vec<Weapon> = WEAPON_PROFILE_STORAGE
+        .with(|service| service.borrow().records())
+        .expect("cannot decode weapons");
+    for weapon in weapons.iter() {
+        do_record_weapon_revision(weapon, Some("recorded when weapon history was introduced".to_string()), None)
+            .expect("cannot migrate weapon revisions");
+    }
+}
+
+//schema version 11 records the status of every token purchase, purchases made before granted their weapon
+fn migrate_v10_to_v11() {
+    TOKEN_PURCHASE_STORAGE.with(|service| rewrite_records(&mut service.borrow_mut()));
+}
+
+//schema version 12 indexes the open trade offers
+fn migrate_v11_to_v12() {
+    let trade_offers: Vec<TradeOffer> = TRADE_STORAGE
+        .with(|service| service.borrow().records())
+        .expect("cannot decode trade offers");
+    OPEN_TRADE_STORAGE.with(|service| {
+        let mut open_trades = service.borrow_mut();
+        for trade_offer in trade_offers.iter().filter(|trade_offer| trade_offer.status == TradeStatus::Open) {
+            open_trades.insert(trade_offer.id, ());
+        }
+    });
+}
+
+//schema version 13 indexes the archived position of every player in the seasons that have ended
+fn migrate_v12_to_v13() {
+    let entries: Vec<(u64, LeaderboardEntry)> = SEASON_ARCHIVE_STORAGE
+        .with(|service| {
+            service
+                .borrow()
+                .iter()
+                .map(|((season_id, _), stored)| Ok((season_id, stored.decode()?)))
+                .collect::<Result<_, Error>>()
+        })
+        .expect("cannot decode season archives");
+    SEASON_POSITION_STORAGE.with(|service| {
+        let mut positions = service.borrow_mut();
+        for (season_id, entry) in entries.iter() {
+            positions.insert((*season_id, entry.standing.player_id), entry.position);
+        }
+    });
+}
+
+//schema version 14 gives every player an active loadout, players without one get a default loadout
+//holding the first weapons they own
+fn migrate_v13_to_v14() {
+    let player_ids: Vec<u64> =
+        PLAYER_PROFILE_STORAGE.with(|service| service.borrow().iter().map(|(id, _)| id).collect());
+    for player_id in player_ids.into_iter().filter(|player_id| active_loadout_id(*player_id).is_none()) {
+        let mut weapon_ids = PLAYER_WEAPON_STORAGE
+            .with(|service| related_ids(&service.borrow(), player_id))
+            .into_iter();
+        do_create_loadout(Loadout {
+            id: 0,
+            player_id,
+            name: DEFAULT_LOADOUT_NAME.to_string(),
+            primary: weapon_ids.next(),
+            secondary: weapon_ids.next(),
+            melee: weapon_ids.next(),
+        })
+        .expect("cannot create default loadouts");
+    }
+}
+
+//schema version 15 indexes the active listings
+fn migrate_v14_to_v15() {
+    let listings: Vec<Listing> = LISTING_STORAGE
+        .with(|service| service.borrow().records())
+        .expect("cannot decode listings");
+    ACTIVE_LISTING_STORAGE.with(|service| {
+        let mut active_listings = service.borrow_mut();
+        for listing in listings.iter().filter(|listing| listing.status == ListingStatus::Active) {
+            active_listings.insert(listing.id, ());
+        }
+    });
+}
+
+//schema version 16 indexes relation maps by related id, so deleting a weapon, match, attachment,
+//achievement, quest or player no longer scans them
+fn migrate_v15_to_v16() {
+    PLAYER_WEAPON_STORAGE.with(|service| service.borrow_mut().index_entries());
+    PLAYER_MATCH_STORAGE.with(|service| service.borrow_mut().index_entries());
+    ACHIEVEMENT_PROGRESS_STORAGE.with(|service| service.borrow_mut().index_entries());
+    PLAYER_ACHIEVEMENT_STORAGE.with(|service| service.borrow_mut().index_entries());
+    QUEST_PROGRESS_STORAGE.with(|service| service.borrow_mut().index_entries());
+    BATTLE_PASS_PROGRESS_STORAGE.with(|service| service.borrow_mut().index_entries());
+    PLAYER_ATTACHMENT_STORAGE.with(|service| service.borrow_mut().index_entries());
+    ATTACHED_STORAGE.with(|service| service.borrow_mut().index_entries());
+}
+
+//schema version 17 stores the memo of every token purchase, so its transfers are deduplicated by the ledger
+fn migrate_v16_to_v17() {
+    TOKEN_PURCHASE_STORAGE.with(|service| rewrite_records(&mut service.borrow_mut()));
+}
+
+// helper function to read every player profile with the v1 layout, for migrations only
+fn legacy_player_profiles() -> Vec<(u64, PlayerProfileV1)> {
+    PLAYER_PROFILE_STORAGE
+        .with(|service| {
+            service
+                .borrow()
+                .iter()
+                .map(|(id, stored)| Ok((id, stored.cast::<PlayerProfileV1>().decode()?)))
+                .collect::<Result<_, Error>>()
+        })
+        .expect("cannot decode player profiles")
+}
+
+// helper function to decode every record of a map and store it again in the current layout
+fn rewrite_records<K, T>(map: &mut StableBTreeMap<K, Stored<T>, Memory>)
+where
+    K: Storable + Ord + Clone,
+    T: Versioned,
+{
+    let keys: Vec<K> = map.iter().map(|(key, _)| key).collect();
+    for key in keys {
+        let migrated = match map.get_record(&key) {
+            Ok(Some(record)) => map.insert_record(key, &record),
+            Ok(None) => Ok(()),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = migrated {
+            ic_cdk::trap(&format!("cannot migrate {}: {:?}", T::NAME, err));
+        }
+    }
+}
+
+//function to grant a role to a principal
+#[ic_cdk::update]
+fn grant_role(principal: Principal, role: Role) -> Result<RoleAssignment, Error> {
+    require_role(Role::Admin)?;
+    if principal == Principal::anonymous() {
+        return Err(Error::InvalidRoleAssignment {
+            msg: "Roles cannot be granted to the anonymous principal".to_string(),
+        });
+    }
+
+    let mut assignment = get_role_assignment(&principal)?.unwrap_or(RoleAssignment {
+        principal,
+        roles: Vec::new(),
+    });
+    if !assignment.roles.contains(&role) {
+        assignment.roles.push(role);
+    }
+    do_insert_roles(&assignment)?;
+    Ok(assignment)
+}
+
+//function to revoke a role from a principal
+#[ic_cdk::update]
+fn revoke_role(principal: Principal, role: Role) -> Result<RoleAssignment, Error> {
+    require_role(Role::Admin)?;
+    let mut assignment = get_role_assignment(&principal)?
+        .filter(|assignment| assignment.roles.contains(&role))
+        .ok_or(Error::NotFound {
+            msg: format!("principal {} does not have the {:?} role", principal, role),
+        })?;
+    if role == Role::Admin {
+        let admin_count = ROLE_STORAGE
+            .with(|service| service.borrow().records())?
+            .iter()
+            .filter(|assignment: &&RoleAssignment| assignment.roles.contains(&Role::Admin))
+            .count();
+        if admin_count <= 1 {
+            return Err(Error::RequirementNotMet {
+                msg: "the last admin cannot be revoked".to_string(),
+            });
+        }
+    }
+
+    assignment.roles.retain(|granted| *granted != role);
+    if assignment.roles.is_empty() {
+        ROLE_STORAGE.with(|service| service.borrow_mut().remove(&StorablePrincipal(principal)));
+    } else {
+        do_insert_roles(&assignment)?;
+    }
+    Ok(assignment)
+}
+
+// function to list every principal with granted roles
+#[ic_cdk::query]
+fn list_roles() -> Result<Vec<RoleAssignment>, Error> {
+    let assignments: Vec<RoleAssignment> =
+        ROLE_STORAGE.with(|service| service.borrow().records())?;
+
+    if !assignments.is_empty() {
+        Ok(assignments)
+    } else {
+        Err(Error::NotFound {
+            msg: "No roles found ".to_string(),
+        })
+    }
+}
 
+// helper function to get the roles granted to a principal
+fn get_role_assignment(principal: &Principal) -> Result<Option<RoleAssignment>, Error> {
+    ROLE_STORAGE.with(|service| service.borrow().get_record(&StorablePrincipal(*principal)))
+}
+
+// helper function to insert role assignment
+fn do_insert_roles(assignment: &RoleAssignment) -> Result<(), Error> {
+    ROLE_STORAGE.with(|service| {
+        service
+            .borrow_mut()
+            .insert_record(StorablePrincipal(assignment.principal), assignment)
+    })
+}
+
+// helper function to check whether a principal holds a role, controllers and admins hold every role
+fn has_role(principal: &Principal, role: Role) -> bool {
+    if env::is_controller(principal) {
+        return true;
+    }
+    get_role_assignment(principal).ok().flatten().is_some_and(|assignment| {
+        assignment
+            .roles
+            .iter()
+            .any(|granted| *granted == role || *granted == Role::Admin)
+    })
+}
 
+// Helper function to ensure the caller holds the given role
+fn require_role(role: Role) -> Result<(), Error> {
+    let caller = env::caller();
+    if has_role(&caller, role) {
+        Ok(())
+    } else {
+        Err(Error::Unauthorized {
+            msg: format!("caller {} does not have the {:?} role", caller, role),
+        })
+    }
 }
 
 //function to create player profile
@@ -51,6 +800,12 @@ fn create_player_profile(
 ) -> Result<PlayerProfile, Error> {
 
     is_valid_player_payload(&player_profile_payload)?;
+    let owner = env::caller();
+    if owner == Principal::anonymous() {
+        return Err(Error::Unauthorized {
+            msg: "Anonymous callers cannot create a player profile".to_string(),
+        });
+    }
     let id = PLAYER_ID_COUNTER
         .with(|counter| {
             let current_value = *counter.borrow().get();
@@ -62,24 +817,31 @@ fn create_player_profile(
         name: player_profile_payload.name,
         id,
         score: player_profile_payload.score,
-        level: player_profile_payload.level,
-        rank: player_profile_payload.rank,
-        weapons: Vec::new(),
-        match_history: Vec::new(),
+        level: leveling::FIRST_LEVEL,
+        rank: moderated_rank(player_profile_payload.rank, FIRST_RANK),
+        owner,
     };
-    do_insert_player(&player_profile);
+    do_insert_player(&player_profile)?;
+    do_create_loadout(Loadout {
+        id: 0,
+        player_id: id,
+        name: DEFAULT_LOADOUT_NAME.to_string(),
+        primary: None,
+        secondary: None,
+        melee: None,
+    })?;
     Ok(player_profile)
 
 }
 
 
 // helper function to get player profile
-fn do_insert_player(player: &PlayerProfile) {
+fn do_insert_player(player: &PlayerProfile) -> Result<(), Error> {
     PLAYER_PROFILE_STORAGE.with(|service| {
         service
             .borrow_mut()
-            .insert(player.id, player.clone())
-    });
+            .insert_record(player.id, player)
+    })
 }
 
 //function to update player profile
@@ -88,24 +850,24 @@ fn update_player_profile(id:u64,player_profile_payload: PlayerProfilePayload)->
     is_valid_player_payload(&player_profile_payload)?;
     let player_profile = PLAYER_PROFILE_STORAGE.with(|service| {
         service
-            .borrow_mut()
-            .get(&id)
+            .borrow()
+            .get_record(&id)?
             .ok_or(Error::NotFound {
                 msg: "Player not found".to_string(),
             })
     })?;
+    is_player_owner_or_admin(&player_profile)?;
 
     let updated_player_profile = PlayerProfile {
         name: player_profile_payload.name,
         id,
         score: player_profile_payload.score,
-        level: player_profile_payload.level,
-        rank: player_profile_payload.rank,
-        weapons: player_profile.weapons,
-        match_history: player_profile.match_history,
+        level: player_profile.level,
+        rank: moderated_rank(player_profile_payload.rank, player_profile.rank),
+        owner: player_profile.owner,
     };
 
-    do_insert_player(&updated_player_profile);
+    do_insert_player(&updated_player_profile)?;
     Ok(updated_player_profile)
 
 }
@@ -116,8 +878,8 @@ fn update_player_profile(id:u64,player_profile_payload: PlayerProfilePayload)->
 fn get_player_profile(id: u64) -> Result<PlayerProfile, Error> {
     PLAYER_PROFILE_STORAGE.with(|service| {
         service
-            .borrow_mut()
-            .get(&id)
+            .borrow()
+            .get_record(&id)?
             .ok_or(Error::NotFound {
                 msg: format!("player with id={} not found", id),
             })
@@ -127,12 +889,8 @@ fn get_player_profile(id: u64) -> Result<PlayerProfile, Error> {
 // function to get all players profile
 #[ic_cdk::query]
 fn get_all_players_profile() -> Result<Vec<PlayerProfile>, Error> {
-    let player_mapping: Vec<(u64, PlayerProfile)> =
-        PLAYER_PROFILE_STORAGE.with(|service| service.borrow().iter().collect());
-    let player_profile: Vec<PlayerProfile> = player_mapping
-        .into_iter()
-        .map(|(_, player)| player)
-        .collect();
+    let player_profile: Vec<PlayerProfile> =
+        PLAYER_PROFILE_STORAGE.with(|service| service.borrow().records())?;
 
     if !player_profile.is_empty() {
         Ok(player_profile)
@@ -143,25 +901,96 @@ fn get_all_players_profile() -> Result<Vec<PlayerProfile>, Error> {
     }
 }
 
+// function to get one page of players profile, starting at the cursor id
+#[ic_cdk::query]
+fn get_players_profile_page(cursor: Option<u64>, limit: u64) -> Result<Page<u64, PlayerProfile>, Error> {
+    PLAYER_PROFILE_STORAGE.with(|service| service.borrow().page(cursor, page_size(limit)))
+}
+
 //function to delete player profile
 #[ic_cdk::update]
 fn delete_player_profile(id: u64) -> Result<(), Error> {
-    PLAYER_PROFILE_STORAGE.with(|service| {
-        service
-            .borrow_mut()
-            .remove(&id)
-            .ok_or(Error::NotFound {
-                msg: format!("player with id={} not found", id),
-            })
+    let player_profile = get_player_profile(id)?;
+    is_player_owner_or_admin(&player_profile)?;
+    do_cancel_trade_offers(|trade_offer| {
+        trade_offer.from_player_id == id || trade_offer.to_player_id == id
     })?;
+    do_cancel_listings(|listing| listing.seller_player_id == id)?;
+    do_withdraw_bids(id)?;
+    PLAYER_PROFILE_STORAGE.with(|service| service.borrow_mut().remove(&id));
+    PLAYER_WEAPON_STORAGE.with(|service| service.borrow_mut().remove_owner(id));
+    PLAYER_MATCH_STORAGE.with(|service| service.borrow_mut().remove_owner(id));
+    do_remove_standing(id)?;
+    RATING_STORAGE.with(|service| service.borrow_mut().remove(&id));
+    RATING_HISTORY_STORAGE.with(|service| {
+        let mut history = service.borrow_mut();
+        let keys: Vec<(u64, u64)> = history
+            .range((id, 0)..=(id, u64::MAX))
+            .map(|(key, _)| key)
+            .collect();
+        for key in keys {
+            history.remove(&key);
+        }
+    });
+    BALANCE_STORAGE.with(|service| service.borrow_mut().remove(&id));
+    XP_STORAGE.with(|service| service.borrow_mut().remove(&id));
+    ACHIEVEMENT_PROGRESS_STORAGE.with(|service| service.borrow_mut().remove_owner(id));
+    PLAYER_ACHIEVEMENT_STORAGE.with(|service| service.borrow_mut().remove_owner(id));
+    QUEST_PROGRESS_STORAGE.with(|service| service.borrow_mut().remove_owner(id));
+    BATTLE_PASS_PROGRESS_STORAGE.with(|service| service.borrow_mut().remove_related_id(id));
+    do_remove_player_loadouts(id);
+    PLAYER_ATTACHMENT_STORAGE.with(|service| service.borrow_mut().remove_owner(id));
+    ATTACHED_STORAGE.with(|service| service.borrow_mut().remove_owner(id));
     Ok(())
 }
 
+// get every weapon owned by a player
+#[ic_cdk::query]
+fn get_player_weapons(player_id: u64) -> Result<Vec<Weapon>, Error> {
+    get_player_profile(player_id)?;
+    let weapon_ids = PLAYER_WEAPON_STORAGE.with(|service| related_ids(&service.borrow(), player_id));
+    weapon_ids
+        .into_iter()
+        .filter_map(|weapon_id| {
+            WEAPON_PROFILE_STORAGE.with(|service| service.borrow().get_record(&weapon_id).transpose())
+        })
+        .collect()
+}
+
+// get every match played by a player
+#[ic_cdk::query]
+fn get_player_matches(player_id: u64) -> Result<Vec<Match>, Error> {
+    get_player_profile(player_id)?;
+    let match_ids = PLAYER_MATCH_STORAGE.with(|service| related_ids(&service.borrow(), player_id));
+    match_ids
+        .into_iter()
+        .filter_map(|match_id| {
+            MATCH_PROFILE_STORAGE.with(|service| service.borrow().get_record(&match_id).transpose())
+        })
+        .collect()
+}
+
+// helper function to get the ids related to a player in a relation map
+fn related_ids(relations: &StableBTreeMap<(u64, u64), (), Memory>, player_id: u64) -> Vec<u64> {
+    relations
+        .range((player_id, 0)..=(player_id, u64::MAX))
+        .map(|((_, related_id), _)| related_id)
+        .collect()
+}
+
+// helper function to remove every relation of a player
+fn remove_player_relations(relations: &mut StableBTreeMap<(u64, u64), (), Memory>, player_id: u64) {
+    for related_id in related_ids(relations, player_id) {
+        relations.remove(&(player_id, related_id));
+    }
+}
+
 //function to create weapon profile
 #[ic_cdk::update]
 fn create_weapon(
     weapon_payload: WeaponProfilePayload,
 ) -> Result<Weapon, Error> {
+    require_role(Role::Designer)?;
     is_valid_weapon_payload(&weapon_payload)?;
 
     let id = WEAPON_ID_COUNTER
@@ -184,28 +1013,39 @@ fn create_weapon(
         level: weapon_payload.level,
         rank: weapon_payload.rank,
     };
-    do_insert_weapon(&weapon);
+    do_record_weapon_revision(&weapon, None, None)?;
+    do_insert_weapon(&weapon)?;
     Ok(weapon)
 }
 
 // helper function to get weapon profile
-fn do_insert_weapon(weapon: &Weapon) {
+fn do_insert_weapon(weapon: &Weapon) -> Result<(), Error> {
     WEAPON_PROFILE_STORAGE.with(|service| {
         service
             .borrow_mut()
-            .insert(weapon.id, weapon.clone())
-    });
+            .insert_record(weapon.id, weapon)
+    })
 }
 
-//function to update weapon profile
+//function to update weapon profile, the change is kept as a revision with an optional patch note
+//and can belong to an open balance patch
 #[ic_cdk::update]
-fn update_weapon_profile(id:u64,weapon_payload: WeaponProfilePayload)-> Result<Weapon, Error>{
+fn update_weapon_profile(
+    id: u64,
+    weapon_payload: WeaponProfilePayload,
+    note: Option<String>,
+    patch_id: Option<u64>,
+) -> Result<Weapon, Error> {
+    require_role(Role::Designer)?;
     is_valid_weapon_payload(&weapon_payload)?;
+    if let Some(patch_id) = patch_id {
+        is_open_balance_patch(patch_id)?;
+    }
 
     WEAPON_PROFILE_STORAGE.with(|service| {
         service
-            .borrow_mut()
-            .get(&id)
+            .borrow()
+            .get_record(&id)?
             .ok_or(Error::NotFound {
                 msg: "Weapon not found".to_string(),
             })
@@ -225,20 +1065,237 @@ fn update_weapon_profile(id:u64,weapon_payload: WeaponProfilePayload)-> Result<W
         rank: weapon_payload.rank,
     };
 
-    do_insert_weapon(&updated_weapon);
+    do_record_weapon_revision(&updated_weapon, note, patch_id)?;
+    do_insert_weapon(&updated_weapon)?;
     Ok(updated_weapon)
 
 
 }
 
+// get every revision of a weapon, oldest first. The history of a deleted weapon is kept
+#[ic_cdk::query]
+fn get_weapon_revisions(weapon_id: u64) -> Result<Vec<WeaponRevision>, Error> {
+    let revisions: Vec<WeaponRevision> = WEAPON_REVISION_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((weapon_id, 0)..=(weapon_id, u64::MAX))
+            .map(|(_, stored)| stored.decode())
+            .collect::<Result<_, _>>()
+    })?;
+    if revisions.is_empty() {
+        return Err(Error::NotFound {
+            msg: format!("weapon with id={} not found", weapon_id),
+        });
+    }
+    Ok(revisions)
+}
 
-// get weapon by id
+// get one revision of a weapon
 #[ic_cdk::query]
-fn get_weapon(id: u64) -> Result<Weapon, Error> {
-    WEAPON_PROFILE_STORAGE.with(|service| {
+fn get_weapon_revision(weapon_id: u64, revision: u64) -> Result<WeaponRevision, Error> {
+    WEAPON_REVISION_STORAGE.with(|service| {
         service
-            .borrow_mut()
-            .get(&id)
+            .borrow()
+            .get_record(&(weapon_id, revision))?
+            .ok_or(Error::NotFound {
+                msg: format!("revision {} of weapon with id={} not found", revision, weapon_id),
+            })
+    })
+}
+
+// get the fields of a weapon that changed between two of its revisions
+#[ic_cdk::query]
+fn diff_weapon_revisions(weapon_id: u64, from_revision: u64, to_revision: u64) -> Result<WeaponRevisionDiff, Error> {
+    let from = get_weapon_revision(weapon_id, from_revision)?;
+    let to = get_weapon_revision(weapon_id, to_revision)?;
+    Ok(WeaponRevisionDiff {
+        weapon_id,
+        from_revision,
+        to_revision,
+        changes: stats::weapon_changes(&from.weapon, &to.weapon),
+    })
+}
+
+//function to start a balance patch, weapon updates made with its id belong to it
+#[ic_cdk::update]
+fn create_balance_patch(patch_payload: BalancePatchPayload) -> Result<BalancePatch, Error> {
+    require_role(Role::Designer)?;
+    if patch_payload.name.trim().is_empty() {
+        return Err(Error::InvalidBalancePatchPayload {
+            msg: "balance patch needs a name".to_string(),
+            payload: patch_payload,
+        });
+    }
+
+    let id = BALANCE_PATCH_ID_COUNTER.with(|counter| *counter.borrow().get());
+    let patch = BalancePatch {
+        id,
+        name: patch_payload.name,
+        notes: patch_payload.notes,
+        created_by: env::caller(),
+        created_at: env::time(),
+        rolled_back_at: None,
+    };
+    do_insert_balance_patch(&patch)?;
+    BALANCE_PATCH_ID_COUNTER
+        .with(|counter| counter.borrow_mut().set(id + 1))
+        .expect("cannot increment id counter");
+    Ok(patch)
+}
+
+// get a balance patch by id
+#[ic_cdk::query]
+fn get_balance_patch(id: u64) -> Result<BalancePatch, Error> {
+    BALANCE_PATCH_STORAGE.with(|service| {
+        service
+            .borrow()
+            .get_record(&id)?
+            .ok_or(Error::NotFound {
+                msg: format!("balance patch with id={} not found", id),
+            })
+    })
+}
+
+// function to get all balance patches
+#[ic_cdk::query]
+fn get_all_balance_patches() -> Result<Vec<BalancePatch>, Error> {
+    let patches: Vec<BalancePatch> = BALANCE_PATCH_STORAGE.with(|service| service.borrow().records())?;
+
+    if !patches.is_empty() {
+        Ok(patches)
+    } else {
+        Err(Error::NotFound {
+            msg: "No balance patches found".to_string(),
+        })
+    }
+}
+
+// get the weapon revisions that belong to a balance patch, ordered by weapon then revision
+#[ic_cdk::query]
+fn get_balance_patch_changes(id: u64) -> Result<Vec<WeaponRevision>, Error> {
+    get_balance_patch(id)?;
+    patch_revision_keys(id)
+        .into_iter()
+        .map(|(weapon_id, revision)| get_weapon_revision(weapon_id, revision))
+        .collect()
+}
+
+//function to undo a balance patch, every weapon it changed goes back to its revision before the patch
+//and the restored weapon is recorded as a new revision
+#[ic_cdk::update]
+fn rollback_balance_patch(id: u64, note: Option<String>) -> Result<Vec<Weapon>, Error> {
+    require_role(Role::Designer)?;
+    let mut patch = get_balance_patch(id)?;
+    if patch.rolled_back_at.is_some() {
+        return Err(Error::RequirementNotMet {
+            msg: format!("balance patch with id={} has already been rolled back", id),
+        });
+    }
+
+    //(first, last, count) of the patch revisions of each weapon, weapons deleted since are left out
+    let mut patched: BTreeMap<u64, (u64, u64, u64)> = BTreeMap::new();
+    for (weapon_id, revision) in patch_revision_keys(id) {
+        if WEAPON_PROFILE_STORAGE.with(|service| service.borrow().contains_key(&weapon_id)) {
+            let entry = patched.entry(weapon_id).or_insert((revision, revision, 0));
+            entry.1 = revision;
+            entry.2 += 1;
+        }
+    }
+
+    let mut restored_weapons = Vec::new();
+    for (weapon_id, (first, last, count)) in patched {
+        let latest = latest_weapon_revision(weapon_id)?.map_or(0, |revision| revision.revision);
+        if latest != last || last - first + 1 != count {
+            return Err(Error::RequirementNotMet {
+                msg: format!(
+                    "weapon with id={} was changed outside balance patch with id={}, roll those changes back first",
+                    weapon_id, id
+                ),
+            });
+        }
+        restored_weapons.push(get_weapon_revision(weapon_id, first - 1)?.weapon);
+    }
+
+    let note = note.unwrap_or(format!("rollback of balance patch {}", patch.name));
+    for weapon in restored_weapons.iter() {
+        do_record_weapon_revision(weapon, Some(note.clone()), None)?;
+        do_insert_weapon(weapon)?;
+    }
+    patch.rolled_back_at = Some(env::time());
+    do_insert_balance_patch(&patch)?;
+    Ok(restored_weapons)
+}
+
+// helper function to store a balance patch
+fn do_insert_balance_patch(patch: &BalancePatch) -> Result<(), Error> {
+    BALANCE_PATCH_STORAGE.with(|service| service.borrow_mut().insert_record(patch.id, patch))
+}
+
+// helper function to check that weapon changes can still be added to a balance patch
+fn is_open_balance_patch(patch_id: u64) -> Result<(), Error> {
+    if get_balance_patch(patch_id)?.rolled_back_at.is_some() {
+        return Err(Error::RequirementNotMet {
+            msg: format!("balance patch with id={} has been rolled back", patch_id),
+        });
+    }
+    Ok(())
+}
+
+// helper function to list the (weapon_id, revision) pairs of a balance patch
+fn patch_revision_keys(patch_id: u64) -> Vec<(u64, u64)> {
+    PATCH_REVISION_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((patch_id, 0, 0)..=(patch_id, u64::MAX, u64::MAX))
+            .map(|((_, weapon_id, revision), _)| (weapon_id, revision))
+            .collect()
+    })
+}
+
+// helper function to get the newest revision of a weapon
+fn latest_weapon_revision(weapon_id: u64) -> Result<Option<WeaponRevision>, Error> {
+    WEAPON_REVISION_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((weapon_id, 0)..=(weapon_id, u64::MAX))
+            .last()
+            .map(|(_, stored)| stored.decode())
+            .transpose()
+    })
+}
+
+// helper function to record a weapon as it is after a change, before the weapon itself is stored
+fn do_record_weapon_revision(
+    weapon: &Weapon,
+    note: Option<String>,
+    patch_id: Option<u64>,
+) -> Result<WeaponRevision, Error> {
+    let revision = latest_weapon_revision(weapon.id)?.map_or(1, |latest| latest.revision + 1);
+    let weapon_revision = WeaponRevision {
+        weapon_id: weapon.id,
+        revision,
+        weapon: weapon.clone(),
+        changed_by: env::caller(),
+        changed_at: env::time(),
+        note,
+        patch_id,
+    };
+    WEAPON_REVISION_STORAGE
+        .with(|service| service.borrow_mut().insert_record((weapon.id, revision), &weapon_revision))?;
+    if let Some(patch_id) = patch_id {
+        PATCH_REVISION_STORAGE.with(|service| service.borrow_mut().insert((patch_id, weapon.id, revision), ()));
+    }
+    Ok(weapon_revision)
+}
+
+
+// get weapon by id
+#[ic_cdk::query]
+fn get_weapon(id: u64) -> Result<Weapon, Error> {
+    WEAPON_PROFILE_STORAGE.with(|service| {
+        service
+            .borrow()
+            .get_record(&id)?
             .ok_or(Error::NotFound {
                 msg: format!("weapon with id={} not found", id),
             })
@@ -248,12 +1305,8 @@ fn get_weapon(id: u64) -> Result<Weapon, Error> {
 // function to get all weapons
 #[ic_cdk::query]
 fn get_all_weapons() -> Result<Vec<Weapon>, Error> {
-    let weapon_mapping: Vec<(u64, Weapon)> =
-        WEAPON_PROFILE_STORAGE.with(|service| service.borrow().iter().collect());
-    let weapon: Vec<Weapon> = weapon_mapping
-        .into_iter()
-        .map(|(_, weapon)| weapon)
-        .collect();
+    let weapon: Vec<Weapon> =
+        WEAPON_PROFILE_STORAGE.with(|service| service.borrow().records())?;
 
     if !weapon.is_empty() {
         Ok(weapon)
@@ -263,15 +1316,17 @@ fn get_all_weapons() -> Result<Vec<Weapon>, Error> {
         })
     }
 }
+
+// function to get one page of weapons, starting at the cursor id
+#[ic_cdk::query]
+fn get_weapons_page(cursor: Option<u64>, limit: u64) -> Result<Page<u64, Weapon>, Error> {
+    WEAPON_PROFILE_STORAGE.with(|service| service.borrow().page(cursor, page_size(limit)))
+}
 //rank weapons by damage
 #[ic_cdk::query]
 fn rank_weapons_by_damage() -> Result<Vec<Weapon>, Error> {
-    let weapon_mapping: Vec<(u64, Weapon)> =
-        WEAPON_PROFILE_STORAGE.with(|service| service.borrow().iter().collect());
-    let mut weapon: Vec<Weapon> = weapon_mapping
-        .into_iter()
-        .map(|(_, weapon)| weapon)
-        .collect();
+    let mut weapon: Vec<Weapon> =
+        WEAPON_PROFILE_STORAGE.with(|service| service.borrow().records())?;
 
     if !weapon.is_empty() {
         weapon.sort_by(|a, b| b.damage.cmp(&a.damage));
@@ -282,372 +1337,3855 @@ fn rank_weapons_by_damage() -> Result<Vec<Weapon>, Error> {
         })
     }
 }
-//function to delete weapon profile
-#[ic_cdk::update]
-fn delete_weapon(id: u64) -> Result<(), Error> {
-    WEAPON_PROFILE_STORAGE.with(|service| {
-        service
-            .borrow_mut()
-            .remove(&id)
-            .ok_or(Error::NotFound {
-                msg: format!("weapon with id={} not found", id),
-            })
-    })?;
-    do_delete_weapon_from_player_profiles(id);
-    Ok(())
-}
-
-// remove weapon from player profiles
-fn do_delete_weapon_from_player_profiles(weapon_id: u64) {
-    let player_mapping: Vec<(u64, PlayerProfile)> =
-        PLAYER_PROFILE_STORAGE.with(|service| service.borrow().iter().collect());
-    let mut player_profile: Vec<PlayerProfile> = player_mapping
+// get one page of the weapons matching a filter in the requested order, by id when no sort is given.
+// The cursor is the position of the first weapon of the page in the sorted results
+#[ic_cdk::query]
+fn query_weapons(
+    filter: WeaponFilter,
+    sort: Option<WeaponSort>,
+    cursor: Option<u64>,
+    limit: u64,
+) -> Result<Page<u64, Weapon>, Error> {
+    let name = filter.name.as_ref().map(|name| name.to_lowercase());
+    let in_range = |value: u64, min: Option<u64>, max: Option<u64>| {
+        value >= min.unwrap_or(0) && value <= max.unwrap_or(u64::MAX)
+    };
+    let mut weapons: Vec<Weapon> = WEAPON_PROFILE_STORAGE
+        .with(|service| service.borrow().records())?
         .into_iter()
-        .map(|(_, player)| player)
+        .filter(|weapon: &Weapon| {
+            let name_matches = match &name {
+                Some(name) => weapon.name.to_lowercase().contains(name),
+                None => true,
+            };
+            name_matches
+                && in_range(weapon.damage, filter.min_damage, filter.max_damage)
+                && in_range(weapon.price, filter.min_price, filter.max_price)
+                && in_range(weapon.level, filter.min_level, filter.max_level)
+                && in_range(weapon.rank, filter.min_rank, filter.max_rank)
+                && in_range(weapon.accuracy, filter.min_accuracy, filter.max_accuracy)
+        })
         .collect();
-
-    for player in player_profile.iter_mut() {
-        let mut weapons = player.weapons.clone();
-        weapons.retain(|weapon| weapon.id != weapon_id);
-        player.weapons = weapons;
-        do_insert_player(&player);
+    if let Some(sort) = sort {
+        weapons.sort_by(|a, b| {
+            sort.primary
+                .compare(a, b)
+                .then_with(|| match &sort.secondary {
+                    Some(secondary) => secondary.compare(a, b),
+                    None => Ordering::Equal,
+                })
+                .then_with(|| a.id.cmp(&b.id))
+        });
     }
+
+    let start = (cursor.unwrap_or(0) as usize).min(weapons.len());
+    let end = start.saturating_add(page_size(limit)).min(weapons.len());
+    Ok(Page {
+        next_cursor: (end < weapons.len()).then_some(end as u64),
+        items: weapons.drain(start..end).collect(),
+    })
 }
 
+// get the metrics derived from the stats of a weapon
+#[ic_cdk::query]
+fn get_weapon_analytics(weapon_id: u64) -> Result<WeaponAnalytics, Error> {
+    let weapon = get_weapon(weapon_id)?;
+    let config = get_weapon_analytics_config()?;
+    Ok(stats::analyze(&weapon, stats::base_stats(&weapon), config.target_health))
+}
 
+// get the metrics of a player's weapon with its attachments
+#[ic_cdk::query]
+fn get_player_weapon_analytics(player_id: u64, weapon_id: u64) -> Result<WeaponAnalytics, Error> {
+    let weapon_stats = get_effective_weapon_stats(player_id, weapon_id)?;
+    let config = get_weapon_analytics_config()?;
+    Ok(stats::analyze(&get_weapon(weapon_id)?, weapon_stats.effective, config.target_health))
+}
 
-//add weapon to player profile
-#[ic_cdk::update]
-fn add_weapon_to_player_profile(player_id: u64, weapon_id: u64) -> Result<(), Error> {
-    let player_profile = PLAYER_PROFILE_STORAGE.with(|service| {
-        service
-            .borrow_mut()
-            .get(&player_id)
-            .ok_or(Error::NotFound {
-                msg: format!("player with id={} not found", player_id),
-            })
-    })?;
+// rank the best `limit` weapons by a metric, the shortest time to kill ranks first and weapons that
+// cannot kill rank last
+#[ic_cdk::query]
+fn rank_weapons_by_metric(metric: WeaponMetric, limit: u64) -> Result<Vec<WeaponAnalytics>, Error> {
+    let weapons: Vec<Weapon> = WEAPON_PROFILE_STORAGE.with(|service| service.borrow().records())?;
+    if weapons.is_empty() {
+        return Err(Error::NotFound {
+            msg: "No weapons found ".to_string(),
+        });
+    }
 
-    let weapon = WEAPON_PROFILE_STORAGE.with(|service| {
-        service
-            .borrow_mut()
-            .get(&weapon_id)
-            .ok_or(Error::NotFound {
-                msg: format!("weapon with id={} not found", weapon_id),
-            })
-    })?;
+    let config = get_weapon_analytics_config()?;
+    let mut analytics: Vec<WeaponAnalytics> = weapons
+        .iter()
+        .map(|weapon| stats::analyze(weapon, stats::base_stats(weapon), config.target_health))
+        .collect();
+    analytics.sort_by(|a, b| match metric {
+        WeaponMetric::Damage => b.stats.damage.cmp(&a.stats.damage),
+        WeaponMetric::BurstDps => b.burst_dps.total_cmp(&a.burst_dps),
+        WeaponMetric::SustainedDps => b.sustained_dps.total_cmp(&a.sustained_dps),
+        WeaponMetric::AccuracyWeightedDps => b.accuracy_weighted_dps.total_cmp(&a.accuracy_weighted_dps),
+        WeaponMetric::EffectiveRange => b.effective_range_score.total_cmp(&a.effective_range_score),
+        WeaponMetric::TimeToKill => match (a.time_to_kill, b.time_to_kill) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            (a, b) => a.is_none().cmp(&b.is_none()),
+        },
+    });
+    analytics.truncate(page_size(limit));
+    Ok(analytics)
+}
 
-    let mut player_profile = player_profile.clone();
-    player_profile.weapons.push(weapon.clone());
-    do_insert_player(&player_profile);
-    Ok(())
+// get the health pool that time to kill is measured against
+#[ic_cdk::query]
+fn get_weapon_analytics_config() -> Result<WeaponAnalyticsConfig, Error> {
+    WEAPON_ANALYTICS_CONFIG.with(|cell| cell.borrow().get().decode())
 }
 
-//function to create match
+//function to choose the health pool that time to kill is measured against
 #[ic_cdk::update]
-fn create_match(
-    match_payload: MatchProfilePayload,
-) -> Result<Match, Error> {
-    is_valid_match_payload(&match_payload)?;
+fn set_weapon_analytics_config(config: WeaponAnalyticsConfig) -> Result<WeaponAnalyticsConfig, Error> {
+    require_role(Role::Designer)?;
+    if config.target_health == 0 {
+        return Err(Error::InvalidWeaponAnalyticsConfig {
+            msg: "target health must be positive".to_string(),
+            payload: config,
+        });
+    }
+    let stored = Stored::new(&config)?;
+    WEAPON_ANALYTICS_CONFIG
+        .with(|cell| cell.borrow_mut().set(stored))
+        .expect("cannot set weapon analytics config");
+    Ok(config)
+}
 
-    let id = MATCH_ID_COUNTER
-        .with(|counter| {
-            let current_value = *counter.borrow().get();
-            counter.borrow_mut().set(current_value + 1)
-        })
-        .expect("cannot increment id counter");
+// get the balance figures of a weapon from the completed matches it was used in
+#[ic_cdk::query]
+fn get_weapon_balance(weapon_id: u64) -> Result<WeaponBalance, Error> {
+    let weapon = get_weapon(weapon_id)?;
+    let telemetry = WEAPON_TELEMETRY_STORAGE
+        .with(|service| service.borrow().get_record(&weapon_id))?
+        .unwrap_or(WeaponTelemetry {
+            weapon_id,
+            ..Default::default()
+        });
+    Ok(weapon_balance(&weapon, &telemetry, total_weapon_picks()?))
+}
 
-    let match_profile = Match {
-        id,
-        player_id: match_payload.player_id,
-        weapon_id: match_payload.weapon_id,
-        score: match_payload.score,
-        level: match_payload.level,
-        rank: match_payload.rank,
-        time: match_payload.time,
-        result: match_payload.result,
+// get the balance figures of every weapon that was used in a completed match
+#[ic_cdk::query]
+fn get_all_weapon_balances() -> Result<Vec<WeaponBalance>, Error> {
+    let total_picks = total_weapon_picks()?;
+    let telemetry: Vec<WeaponTelemetry> =
+        WEAPON_TELEMETRY_STORAGE.with(|service| service.borrow().records())?;
+    telemetry
+        .iter()
+        .map(|telemetry| Ok(weapon_balance(&get_weapon(telemetry.weapon_id)?, telemetry, total_picks)))
+        .collect()
+}
+
+// get the weapons whose balance figures are outliers among the weapons picked at least
+// min_picks times, for one figure or all of them
+#[ic_cdk::query]
+fn get_weapon_outliers(metric: Option<BalanceMetric>) -> Result<Vec<WeaponOutlier>, Error> {
+    let config = get_telemetry_config()?;
+    let balances: Vec<WeaponBalance> = get_all_weapon_balances()?
+        .into_iter()
+        .filter(|balance| balance.picks >= config.min_picks)
+        .collect();
+    let metrics = match metric {
+        Some(metric) => vec![metric],
+        None => BalanceMetric::ALL.to_vec(),
     };
-    do_insert_match(&match_profile);
-    Ok(match_profile)
+
+    let mut outliers = Vec::new();
+    for metric in metrics {
+        let values: Vec<f64> = balances.iter().map(|balance| metric.value(balance)).collect();
+        let Some((mean, deviation)) = stats::mean_and_deviation(&values) else {
+            continue;
+        };
+        if deviation == 0.0 {
+            continue;
+        }
+        for (balance, value) in balances.iter().zip(values) {
+            let z_score = (value - mean) / deviation;
+            if z_score.abs() >= config.outlier_z_score {
+                outliers.push(WeaponOutlier {
+                    weapon_id: balance.weapon_id,
+                    name: balance.name.clone(),
+                    metric,
+                    value,
+                    mean,
+                    z_score,
+                });
+            }
+        }
+    }
+    Ok(outliers)
 }
 
-// helper function to get match profile
-fn do_insert_match(match_profile: &Match) {
-    MATCH_PROFILE_STORAGE.with(|service| {
-        service
-            .borrow_mut()
-            .insert(match_profile.id, match_profile.clone())
-    });
+// get the outlier detection settings
+#[ic_cdk::query]
+fn get_telemetry_config() -> Result<TelemetryConfig, Error> {
+    TELEMETRY_CONFIG.with(|cell| cell.borrow().get().decode())
 }
 
-//function to update match
+//function to choose how far from the other weapons a weapon must be to be flagged
 #[ic_cdk::update]
-fn update_match(id:u64,match_payload: MatchProfilePayload)-> Result<Match, Error>{
-    is_valid_match_payload(&match_payload)?;
-    MATCH_PROFILE_STORAGE.with(|service| {
-        service
-            .borrow_mut()
-            .get(&id)
-            .ok_or(Error::NotFound {
-                msg: "Match not found".to_string(),
-            })
-    })?;
+fn set_telemetry_config(config: TelemetryConfig) -> Result<TelemetryConfig, Error> {
+    require_role(Role::Designer)?;
+    if !(config.outlier_z_score.is_finite() && config.outlier_z_score > 0.0) {
+        return Err(Error::InvalidTelemetryConfig {
+            msg: "the outlier z-score must be a positive number".to_string(),
+            payload: config,
+        });
+    }
+    let stored = Stored::new(&config)?;
+    TELEMETRY_CONFIG
+        .with(|cell| cell.borrow_mut().set(stored))
+        .expect("cannot set telemetry config");
+    Ok(config)
+}
 
-    let updated_match = Match {
-        id,
-        player_id: match_payload.player_id, 
-        weapon_id: match_payload.weapon_id,
-        score: match_payload.score,
-        level: match_payload.level,
-        rank: match_payload.rank,
-        time: match_payload.time,
-        result: match_payload.result,
+// helper function to derive the balance figures of a weapon from its telemetry
+fn weapon_balance(weapon: &Weapon, telemetry: &WeaponTelemetry, total_picks: u64) -> WeaponBalance {
+    let ratio = |value: u64, total: u64| {
+        if total == 0 {
+            0.0
+        } else {
+            value as f64 / total as f64
+        }
     };
+    WeaponBalance {
+        weapon_id: weapon.id,
+        name: weapon.name.clone(),
+        picks: telemetry.picks,
+        pick_rate: ratio(telemetry.picks, total_picks),
+        win_rate: ratio(telemetry.wins, telemetry.picks),
+        average_score: ratio(telemetry.total_score, telemetry.picks),
+        average_match_time: ratio(telemetry.total_match_time, telemetry.picks),
+    }
+}
 
-    do_insert_match(&updated_match);
-    Ok(updated_match)   
+// helper function to count the participants of every completed match, over all weapons
+fn total_weapon_picks() -> Result<u64, Error> {
+    let telemetry: Vec<WeaponTelemetry> =
+        WEAPON_TELEMETRY_STORAGE.with(|service| service.borrow().records())?;
+    Ok(telemetry.iter().map(|telemetry| telemetry.picks).sum())
 }
 
-// get match by id
-#[ic_cdk::query]
-fn get_match(id: u64) -> Result<Match, Error> {
-    MATCH_PROFILE_STORAGE.with(|service| {
-        service
-            .borrow_mut()
-            .get(&id)
-            .ok_or(Error::NotFound {
-                msg: format!("match with id={} not found", id),
-            })
-    })
+// helper function to add a completed match to the telemetry of the weapons its participants used
+fn do_add_match_to_weapon_telemetry(match_profile: &Match) -> Result<(), Error> {
+    for participant in match_profile.participants.iter() {
+        do_update_weapon_telemetry(participant.weapon_id, |telemetry| {
+            telemetry.picks += 1;
+            telemetry.wins += (participant.outcome == MatchOutcome::Win) as u64;
+            telemetry.total_score = telemetry.total_score.saturating_add(participant.score);
+            telemetry.total_match_time = telemetry.total_match_time.saturating_add(match_profile.time);
+        })?;
+    }
+    Ok(())
 }
 
-// function to get all matches
-#[ic_cdk::query]
-fn get_all_matches() -> Result<Vec<Match>, Error> {
-    let match_mapping: Vec<(u64, Match)> =
-        MATCH_PROFILE_STORAGE.with(|service| service.borrow().iter().collect());
-    let match_profile: Vec<Match> = match_mapping
-        .into_iter()
-        .map(|(_, match_profile)| match_profile)
-        .collect();
+// helper function to take a match that is corrected or deleted out of the telemetry of its weapons
+fn do_remove_match_from_weapon_telemetry(match_profile: &Match) -> Result<(), Error> {
+    for participant in match_profile.participants.iter() {
+        do_update_weapon_telemetry(participant.weapon_id, |telemetry| {
+            telemetry.picks = telemetry.picks.saturating_sub(1);
+            telemetry.wins = telemetry
+                .wins
+                .saturating_sub((participant.outcome == MatchOutcome::Win) as u64);
+            telemetry.total_score = telemetry.total_score.saturating_sub(participant.score);
+            telemetry.total_match_time = telemetry.total_match_time.saturating_sub(match_profile.time);
+        })?;
+    }
+    Ok(())
+}
 
-    if !match_profile.is_empty() {
-        Ok(match_profile)
-    } else {
-        Err(Error::NotFound {
-            msg: "No matches found ".to_string(),
-        })
+// helper function to change the telemetry of a weapon, matches of weapons that were deleted are ignored
+fn do_update_weapon_telemetry(weapon_id: u64, update: impl FnOnce(&mut WeaponTelemetry)) -> Result<(), Error> {
+    if !WEAPON_PROFILE_STORAGE.with(|service| service.borrow().contains_key(&weapon_id)) {
+        return Ok(());
     }
+    let mut telemetry = WEAPON_TELEMETRY_STORAGE
+        .with(|service| service.borrow().get_record(&weapon_id))?
+        .unwrap_or(WeaponTelemetry {
+            weapon_id,
+            ..Default::default()
+        });
+    update(&mut telemetry);
+    WEAPON_TELEMETRY_STORAGE.with(|service| service.borrow_mut().insert_record(weapon_id, &telemetry))
 }
 
-//function to delete match
+//function to delete weapon profile
 #[ic_cdk::update]
-fn delete_match(id: u64) -> Result<(), Error> {
-    MATCH_PROFILE_STORAGE.with(|service| {
+fn delete_weapon(id: u64) -> Result<(), Error> {
+    require_role(Role::Designer)?;
+    get_weapon(id)?;
+    do_cancel_trade_offers(|trade_offer| {
+        trade_offer.offered_weapon_ids.contains(&id) || trade_offer.requested_weapon_ids.contains(&id)
+    })?;
+    do_cancel_listings(|listing| listing.weapon_id == id)?;
+    WEAPON_PROFILE_STORAGE.with(|service| {
         service
             .borrow_mut()
             .remove(&id)
             .ok_or(Error::NotFound {
-                msg: format!("match with id={} not found", id),
+                msg: format!("weapon with id={} not found", id),
             })
     })?;
-    do_delete_match_from_player_profiles(id);
+    let owner_ids = PLAYER_WEAPON_STORAGE.with(|service| service.borrow().owner_ids(id));
+    for player_id in owner_ids {
+        do_unequip_weapon(player_id, id)?;
+    }
+    PLAYER_WEAPON_STORAGE.with(|service| service.borrow_mut().remove_related_id(id));
+    WEAPON_TOKEN_PRICE_STORAGE.with(|service| service.borrow_mut().remove(&id));
+    WEAPON_TELEMETRY_STORAGE.with(|service| service.borrow_mut().remove(&id));
     Ok(())
 }
 
-// remove match from player profiles
-fn do_delete_match_from_player_profiles(match_id: u64) {
-    let player_mapping: Vec<(u64, PlayerProfile)> =
-        PLAYER_PROFILE_STORAGE.with(|service| service.borrow().iter().collect());
-    let mut player_profile: Vec<PlayerProfile> = player_mapping
-        .into_iter()
-        .map(|(_, player)| player)
-        .collect();
-
-    for player in player_profile.iter_mut() {
-        let mut matches = player.match_history.clone();
-        matches.retain(|match_profile| match_profile.id != match_id);
-        player.match_history = matches;
-        do_insert_player(&player);
-    }
-}
 
 
-//add match to player profile
+//add weapon to player profile without charging for it, players buy weapons with purchase_weapon
 #[ic_cdk::update]
-fn add_match_to_player_profile(player_id: u64, match_id: u64) -> Result<(), Error> {
+fn add_weapon_to_player_profile(player_id: u64, weapon_id: u64) -> Result<(), Error> {
+    require_role(Role::Admin)?;
     let player_profile = PLAYER_PROFILE_STORAGE.with(|service| {
         service
-            .borrow_mut()
-            .get(&player_id)
+            .borrow()
+            .get_record(&player_id)?
             .ok_or(Error::NotFound {
                 msg: format!("player with id={} not found", player_id),
             })
     })?;
 
-    let match_profile = MATCH_PROFILE_STORAGE.with(|service| {
+    get_weapon(weapon_id)?;
+
+    PLAYER_WEAPON_STORAGE.with(|service| {
         service
             .borrow_mut()
-            .get(&match_id)
-            .ok_or(Error::NotFound {
-                msg: format!("match with id={} not found", match_id),
-            })
-    })?;
-
-    let mut player_profile = player_profile.clone();
-    player_profile.match_history.push(match_profile.clone());
-    do_insert_player(&player_profile);
+            .insert((player_profile.id, weapon_id), ())
+    });
     Ok(())
 }
 
-// get average for match score
-#[ic_cdk::query]
-fn get_average_match_score() -> Result<u64, Error> {
-    let match_mapping: Vec<(u64, Match)> =
-        MATCH_PROFILE_STORAGE.with(|service| service.borrow().iter().collect());
-    let match_profile: Vec<Match> = match_mapping
-        .into_iter()
-        .map(|(_, match_profile)| match_profile)
-        .collect();
+//function to create a loadout for a player, the first loadout of a player becomes their active one
+#[ic_cdk::update]
+fn create_loadout(loadout_payload: LoadoutPayload) -> Result<Loadout, Error> {
+    let player_profile = get_player_profile(loadout_payload.player_id)?;
+    is_player_owner_or_admin(&player_profile)?;
+    is_valid_loadout_payload(&loadout_payload)?;
+    let player_id = player_profile.id;
+    let loadout_count = PLAYER_LOADOUT_STORAGE.with(|service| related_ids(&service.borrow(), player_id).len());
+    if loadout_count >= MAX_LOADOUTS_PER_PLAYER {
+        return Err(Error::InvalidLoadoutPayload {
+            msg: format!("a player can have at most {} loadouts", MAX_LOADOUTS_PER_PLAYER),
+            payload: loadout_payload,
+        });
+    }
 
-    if !match_profile.is_empty() {
-        let mut sum = 0;
-        for match_profile in match_profile.iter() {
-            sum += match_profile.score;
-        }
-        let average = sum / match_profile.len() as u64;
-        Ok(average)
-    } else {
+    do_create_loadout(Loadout {
+        id: 0,
+        player_id,
+        name: loadout_payload.name,
+        primary: loadout_payload.primary,
+        secondary: loadout_payload.secondary,
+        melee: loadout_payload.melee,
+    })
+}
+
+// helper function to store a new loadout under the next id, it becomes active if the player has no
+// active loadout
+fn do_create_loadout(mut loadout: Loadout) -> Result<Loadout, Error> {
+    let id = LOADOUT_ID_COUNTER.with(|counter| *counter.borrow().get());
+    loadout.id = id;
+    do_insert_loadout(&loadout)?;
+    LOADOUT_ID_COUNTER
+        .with(|counter| counter.borrow_mut().set(id + 1))
+        .expect("cannot increment id counter");
+    PLAYER_LOADOUT_STORAGE.with(|service| service.borrow_mut().insert((loadout.player_id, id), ()));
+    if active_loadout_id(loadout.player_id).is_none() {
+        ACTIVE_LOADOUT_STORAGE.with(|service| service.borrow_mut().insert(loadout.player_id, id));
+    }
+    Ok(loadout)
+}
+
+//function to rename a loadout or change the weapons in its slots
+#[ic_cdk::update]
+fn update_loadout(id: u64, loadout_payload: LoadoutPayload) -> Result<Loadout, Error> {
+    let loadout = get_loadout(id)?;
+    is_player_owner_or_admin(&get_player_profile(loadout.player_id)?)?;
+    if loadout_payload.player_id != loadout.player_id {
+        return Err(Error::InvalidLoadoutPayload {
+            msg: format!("loadout with id={} belongs to another player", id),
+            payload: loadout_payload,
+        });
+    }
+    is_valid_loadout_payload(&loadout_payload)?;
+
+    let updated_loadout = Loadout {
+        id,
+        player_id: loadout.player_id,
+        name: loadout_payload.name,
+        primary: loadout_payload.primary,
+        secondary: loadout_payload.secondary,
+        melee: loadout_payload.melee,
+    };
+    do_insert_loadout(&updated_loadout)?;
+    Ok(updated_loadout)
+}
+
+//function to delete a loadout, the player's oldest remaining loadout becomes active if it was active.
+//Players keep at least one loadout so that matches can always be checked against it
+#[ic_cdk::update]
+fn delete_loadout(id: u64) -> Result<(), Error> {
+    let loadout = get_loadout(id)?;
+    is_player_owner_or_admin(&get_player_profile(loadout.player_id)?)?;
+    let loadout_count =
+        PLAYER_LOADOUT_STORAGE.with(|service| related_ids(&service.borrow(), loadout.player_id).len());
+    if loadout_count <= 1 {
+        return Err(Error::RequirementNotMet {
+            msg: format!("loadout with id={} is the last loadout of player with id={}", id, loadout.player_id),
+        });
+    }
+    LOADOUT_STORAGE.with(|service| service.borrow_mut().remove(&id));
+    PLAYER_LOADOUT_STORAGE.with(|service| service.borrow_mut().remove(&(loadout.player_id, id)));
+
+    if active_loadout_id(loadout.player_id) == Some(id) {
+        let next_loadout_id = PLAYER_LOADOUT_STORAGE
+            .with(|service| related_ids(&service.borrow(), loadout.player_id).first().copied());
+        if let Some(next_loadout_id) = next_loadout_id {
+            ACTIVE_LOADOUT_STORAGE.with(|service| service.borrow_mut().insert(loadout.player_id, next_loadout_id));
+        }
+    }
+    Ok(())
+}
+
+// get loadout by id
+#[ic_cdk::query]
+fn get_loadout(id: u64) -> Result<Loadout, Error> {
+    LOADOUT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .get_record(&id)?
+            .ok_or(Error::NotFound {
+                msg: format!("loadout with id={} not found", id),
+            })
+    })
+}
+
+// get every loadout of a player
+#[ic_cdk::query]
+fn get_player_loadouts(player_id: u64) -> Result<Vec<Loadout>, Error> {
+    get_player_profile(player_id)?;
+    let loadout_ids = PLAYER_LOADOUT_STORAGE.with(|service| related_ids(&service.borrow(), player_id));
+    loadout_ids.into_iter().map(get_loadout).collect()
+}
+
+//function to choose the loadout a player takes into matches
+#[ic_cdk::update]
+fn set_active_loadout(player_id: u64, loadout_id: u64) -> Result<Loadout, Error> {
+    is_player_owner_or_admin(&get_player_profile(player_id)?)?;
+    let loadout = get_loadout(loadout_id)?;
+    if loadout.player_id != player_id {
+        return Err(Error::NotFound {
+            msg: format!("player with id={} has no loadout with id={}", player_id, loadout_id),
+        });
+    }
+    ACTIVE_LOADOUT_STORAGE.with(|service| service.borrow_mut().insert(player_id, loadout_id));
+    Ok(loadout)
+}
+
+// get the loadout a player takes into matches
+#[ic_cdk::query]
+fn get_active_loadout(player_id: u64) -> Result<Loadout, Error> {
+    get_player_profile(player_id)?;
+    let loadout_id = active_loadout_id(player_id).ok_or(Error::NotFound {
+        msg: format!("player with id={} has no loadout", player_id),
+    })?;
+    get_loadout(loadout_id)
+}
+
+// helper function to insert loadout
+fn do_insert_loadout(loadout: &Loadout) -> Result<(), Error> {
+    LOADOUT_STORAGE.with(|service| service.borrow_mut().insert_record(loadout.id, loadout))
+}
+
+// helper function to get the id of a player's active loadout
+fn active_loadout_id(player_id: u64) -> Option<u64> {
+    ACTIVE_LOADOUT_STORAGE.with(|service| service.borrow().get(&player_id))
+}
+
+// helper function to take a weapon the player no longer owns out of their loadouts and detach its
+// attachments, which stay in the player's inventory
+fn do_unequip_weapon(player_id: u64, weapon_id: u64) -> Result<(), Error> {
+    let attachment_ids: Vec<u64> = weapon_attachments(player_id, weapon_id)?
+        .into_iter()
+        .map(|attachment| attachment.id)
+        .collect();
+    ATTACHED_STORAGE.with(|service| {
+        let mut attached = service.borrow_mut();
+        for attachment_id in attachment_ids {
+            attached.remove(&(player_id, attachment_id));
+        }
+    });
+
+    let loadout_ids = PLAYER_LOADOUT_STORAGE.with(|service| related_ids(&service.borrow(), player_id));
+    for loadout_id in loadout_ids {
+        let mut loadout = get_loadout(loadout_id)?;
+        if loadout.unequip(weapon_id) {
+            do_insert_loadout(&loadout)?;
+        }
+    }
+    Ok(())
+}
+
+// helper function to remove every loadout of a deleted player
+fn do_remove_player_loadouts(player_id: u64) {
+    let loadout_ids = PLAYER_LOADOUT_STORAGE.with(|service| related_ids(&service.borrow(), player_id));
+    LOADOUT_STORAGE.with(|service| {
+        let mut loadouts = service.borrow_mut();
+        for loadout_id in loadout_ids.iter() {
+            loadouts.remove(loadout_id);
+        }
+    });
+    PLAYER_LOADOUT_STORAGE.with(|service| remove_player_relations(&mut service.borrow_mut(), player_id));
+    ACTIVE_LOADOUT_STORAGE.with(|service| service.borrow_mut().remove(&player_id));
+}
+
+//function to add an attachment to the catalog
+#[ic_cdk::update]
+fn create_attachment(attachment_payload: AttachmentPayload) -> Result<Attachment, Error> {
+    require_role(Role::Designer)?;
+    is_valid_attachment_payload(&attachment_payload)?;
+
+    let id = ATTACHMENT_ID_COUNTER.with(|counter| *counter.borrow().get());
+    let attachment = Attachment {
+        id,
+        name: attachment_payload.name,
+        kind: attachment_payload.kind,
+        modifiers: attachment_payload.modifiers,
+        price: attachment_payload.price,
+    };
+    do_insert_attachment(&attachment)?;
+    ATTACHMENT_ID_COUNTER
+        .with(|counter| counter.borrow_mut().set(id + 1))
+        .expect("cannot increment id counter");
+    Ok(attachment)
+}
+
+//function to update an attachment of the catalog, attached copies change with it. Its kind cannot
+//change because weapons hold one attachment of each kind
+#[ic_cdk::update]
+fn update_attachment(id: u64, attachment_payload: AttachmentPayload) -> Result<Attachment, Error> {
+    require_role(Role::Designer)?;
+    is_valid_attachment_payload(&attachment_payload)?;
+    let attachment = get_attachment(id)?;
+    if attachment.kind != attachment_payload.kind {
+        return Err(Error::InvalidAttachmentPayload {
+            msg: format!("attachment with id={} is a {:?}", id, attachment.kind),
+            payload: attachment_payload,
+        });
+    }
+
+    let updated_attachment = Attachment {
+        id,
+        name: attachment_payload.name,
+        kind: attachment_payload.kind,
+        modifiers: attachment_payload.modifiers,
+        price: attachment_payload.price,
+    };
+    do_insert_attachment(&updated_attachment)?;
+    Ok(updated_attachment)
+}
+
+//function to delete an attachment, it is removed from every inventory and weapon
+#[ic_cdk::update]
+fn delete_attachment(id: u64) -> Result<(), Error> {
+    require_role(Role::Designer)?;
+    get_attachment(id)?;
+    ATTACHMENT_STORAGE.with(|service| service.borrow_mut().remove(&id));
+    PLAYER_ATTACHMENT_STORAGE.with(|service| service.borrow_mut().remove_related_id(id));
+    ATTACHED_STORAGE.with(|service| service.borrow_mut().remove_related_id(id));
+    Ok(())
+}
+
+// get attachment by id
+#[ic_cdk::query]
+fn get_attachment(id: u64) -> Result<Attachment, Error> {
+    ATTACHMENT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .get_record(&id)?
+            .ok_or(Error::NotFound {
+                msg: format!("attachment with id={} not found", id),
+            })
+    })
+}
+
+// function to get all attachments of the catalog
+#[ic_cdk::query]
+fn get_all_attachments() -> Result<Vec<Attachment>, Error> {
+    let attachments: Vec<Attachment> = ATTACHMENT_STORAGE.with(|service| service.borrow().records())?;
+
+    if !attachments.is_empty() {
+        Ok(attachments)
+    } else {
+        Err(Error::NotFound {
+            msg: "No attachments found ".to_string(),
+        })
+    }
+}
+
+//function to buy an attachment with the player's balance, only the player's owner can spend it
+#[ic_cdk::update]
+fn purchase_attachment(player_id: u64, attachment_id: u64) -> Result<Transaction, Error> {
+    let player_profile = get_player_profile(player_id)?;
+    require_player_owner(&player_profile)?;
+    let attachment = get_attachment(attachment_id)?;
+    if player_owns_attachment(player_id, attachment_id) {
+        return Err(Error::InvalidTransaction {
+            msg: format!(
+                "player with id={} already owns attachment with id={}",
+                player_id, attachment_id
+            ),
+        });
+    }
+
+    let transaction = do_debit(
+        player_id,
+        TransactionKind::AttachmentPurchase { attachment_id },
+        attachment.price,
+        None,
+    )?;
+    PLAYER_ATTACHMENT_STORAGE.with(|service| service.borrow_mut().insert((player_id, attachment_id), ()));
+    Ok(transaction)
+}
+
+//add attachment to player profile without charging for it
+#[ic_cdk::update]
+fn add_attachment_to_player_profile(player_id: u64, attachment_id: u64) -> Result<(), Error> {
+    require_role(Role::Admin)?;
+    get_player_profile(player_id)?;
+    get_attachment(attachment_id)?;
+    PLAYER_ATTACHMENT_STORAGE.with(|service| service.borrow_mut().insert((player_id, attachment_id), ()));
+    Ok(())
+}
+
+// get every attachment owned by a player with the weapon it is attached to
+#[ic_cdk::query]
+fn get_player_attachments(player_id: u64) -> Result<Vec<PlayerAttachment>, Error> {
+    get_player_profile(player_id)?;
+    let attachment_ids = PLAYER_ATTACHMENT_STORAGE.with(|service| related_ids(&service.borrow(), player_id));
+    attachment_ids
+        .into_iter()
+        .map(|attachment_id| {
+            Ok(PlayerAttachment {
+                attachment: get_attachment(attachment_id)?,
+                weapon_id: ATTACHED_STORAGE.with(|service| service.borrow().get(&(player_id, attachment_id))),
+            })
+        })
+        .collect()
+}
+
+//function to attach an owned attachment to an owned weapon. It replaces the weapon's attachment of
+//the same kind and leaves the weapon it was attached to before
+#[ic_cdk::update]
+fn equip_attachment(player_id: u64, weapon_id: u64, attachment_id: u64) -> Result<EffectiveWeaponStats, Error> {
+    is_player_owner_or_admin(&get_player_profile(player_id)?)?;
+    if !player_owns_weapon(player_id, weapon_id) {
+        return Err(Error::RequirementNotMet {
+            msg: format!("player with id={} does not own weapon with id={}", player_id, weapon_id),
+        });
+    }
+    if !player_owns_attachment(player_id, attachment_id) {
+        return Err(Error::RequirementNotMet {
+            msg: format!("player with id={} does not own attachment with id={}", player_id, attachment_id),
+        });
+    }
+    let attachment = get_attachment(attachment_id)?;
+
+    let replaced: Vec<u64> = weapon_attachments(player_id, weapon_id)?
+        .into_iter()
+        .filter(|attached| attached.kind == attachment.kind && attached.id != attachment_id)
+        .map(|attached| attached.id)
+        .collect();
+    ATTACHED_STORAGE.with(|service| {
+        let mut attached = service.borrow_mut();
+        for replaced_id in replaced.iter() {
+            attached.remove(&(player_id, *replaced_id));
+        }
+        attached.insert((player_id, attachment_id), weapon_id);
+    });
+    get_effective_weapon_stats(player_id, weapon_id)
+}
+
+//function to detach an attachment from the weapon it is attached to, it stays in the inventory
+#[ic_cdk::update]
+fn unequip_attachment(player_id: u64, attachment_id: u64) -> Result<(), Error> {
+    is_player_owner_or_admin(&get_player_profile(player_id)?)?;
+    ATTACHED_STORAGE.with(|service| {
+        service
+            .borrow_mut()
+            .remove(&(player_id, attachment_id))
+            .ok_or(Error::NotFound {
+                msg: format!("attachment with id={} is not attached", attachment_id),
+            })
+    })?;
+    Ok(())
+}
+
+// get the stats of a player's weapon after the modifiers of its attachments
+#[ic_cdk::query]
+fn get_effective_weapon_stats(player_id: u64, weapon_id: u64) -> Result<EffectiveWeaponStats, Error> {
+    get_player_profile(player_id)?;
+    let weapon = get_weapon(weapon_id)?;
+    if !player_owns_weapon(player_id, weapon_id) {
+        return Err(Error::NotFound {
+            msg: format!("player with id={} does not own weapon with id={}", player_id, weapon_id),
+        });
+    }
+    let attachments = weapon_attachments(player_id, weapon_id)?;
+    let modifiers: Vec<StatModifiers> = attachments
+        .iter()
+        .map(|attachment| attachment.modifiers.clone())
+        .collect();
+    Ok(EffectiveWeaponStats {
+        player_id,
+        weapon_id,
+        attachment_ids: attachments.iter().map(|attachment| attachment.id).collect(),
+        base: stats::base_stats(&weapon),
+        effective: stats::apply_modifiers(&weapon, &modifiers),
+    })
+}
+
+// helper function to insert attachment
+fn do_insert_attachment(attachment: &Attachment) -> Result<(), Error> {
+    ATTACHMENT_STORAGE.with(|service| service.borrow_mut().insert_record(attachment.id, attachment))
+}
+
+// helper function to check whether a player owns an attachment
+fn player_owns_attachment(player_id: u64, attachment_id: u64) -> bool {
+    PLAYER_ATTACHMENT_STORAGE.with(|service| service.borrow().contains_key(&(player_id, attachment_id)))
+}
+
+// helper function to get the attachments attached to a player's weapon
+fn weapon_attachments(player_id: u64, weapon_id: u64) -> Result<Vec<Attachment>, Error> {
+    let attachment_ids: Vec<u64> = ATTACHED_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((player_id, 0)..=(player_id, u64::MAX))
+            .filter(|(_, attached_weapon_id)| *attached_weapon_id == weapon_id)
+            .map(|((_, attachment_id), _)| attachment_id)
+            .collect()
+    });
+    attachment_ids.into_iter().map(get_attachment).collect()
+}
+
+//function to buy a weapon with the player's balance, only the player's owner can spend it and the
+//player must reach the weapon's level and rank
+#[ic_cdk::update]
+fn purchase_weapon(player_id: u64, weapon_id: u64) -> Result<Transaction, Error> {
+    let player_profile = get_player_profile(player_id)?;
+    require_player_owner(&player_profile)?;
+    let weapon = get_weapon(weapon_id)?;
+    is_valid_purchase(&player_profile, &weapon)?;
+
+    let balance = player_balance(player_id);
+    let balance_after = balance.checked_sub(weapon.price).ok_or(Error::InsufficientFunds {
+        msg: format!("player with id={} cannot afford weapon with id={}", player_id, weapon_id),
+        balance,
+        price: weapon.price,
+    })?;
+
+    let transaction = do_record_transaction(
+        player_id,
+        TransactionKind::Purchase { weapon_id },
+        weapon.price,
+        balance_after,
+        None,
+    )?;
+    PLAYER_WEAPON_STORAGE.with(|service| service.borrow_mut().insert((player_id, weapon_id), ()));
+    Ok(transaction)
+}
+
+//function for admins to credit soft currency to a player
+#[ic_cdk::update]
+fn grant_currency(player_id: u64, amount: u64, note: Option<String>) -> Result<Transaction, Error> {
+    require_role(Role::Admin)?;
+    get_player_profile(player_id)?;
+    if amount == 0 {
+        return Err(Error::InvalidTransaction {
+            msg: "granted amount must be positive".to_string(),
+        });
+    }
+    do_credit(player_id, TransactionKind::Grant, amount, note)
+}
+
+//function for admins to refund a purchase, the price is credited back and the weapon is taken back
+#[ic_cdk::update]
+fn refund_purchase(transaction_id: u64, note: Option<String>) -> Result<Transaction, Error> {
+    require_role(Role::Admin)?;
+    let purchase = get_transaction(transaction_id)?;
+    let weapon_id = match purchase.kind {
+        TransactionKind::Purchase { weapon_id } => weapon_id,
+        _ => {
+            return Err(Error::InvalidTransaction {
+                msg: format!("transaction with id={} is not a purchase", transaction_id),
+            })
+        }
+    };
+    get_player_profile(purchase.player_id)?;
+    let already_refunded = get_player_transactions(purchase.player_id)?.iter().any(|transaction| {
+        matches!(transaction.kind, TransactionKind::Refund { transaction_id: refunded, .. } if refunded == transaction_id)
+    });
+    if already_refunded {
+        return Err(Error::InvalidTransaction {
+            msg: format!("transaction with id={} was already refunded", transaction_id),
+        });
+    }
+    is_tradable_weapon(purchase.player_id, weapon_id)?;
+
+    let refund = do_credit(
+        purchase.player_id,
+        TransactionKind::Refund { transaction_id, weapon_id },
+        purchase.amount,
+        note,
+    )?;
+    PLAYER_WEAPON_STORAGE.with(|service| service.borrow_mut().remove(&(purchase.player_id, weapon_id)));
+    do_unequip_weapon(purchase.player_id, weapon_id)?;
+    Ok(refund)
+}
+
+// get the soft-currency balance of a player
+#[ic_cdk::query]
+fn get_balance(player_id: u64) -> Result<u64, Error> {
+    get_player_profile(player_id)?;
+    Ok(player_balance(player_id))
+}
+
+// get transaction by id
+#[ic_cdk::query]
+fn get_transaction(id: u64) -> Result<Transaction, Error> {
+    TRANSACTION_STORAGE.with(|service| {
+        service
+            .borrow()
+            .get_record(&id)?
+            .ok_or(Error::NotFound {
+                msg: format!("transaction with id={} not found", id),
+            })
+    })
+}
+
+// get every transaction of a player, oldest first
+#[ic_cdk::query]
+fn get_player_transactions(player_id: u64) -> Result<Vec<Transaction>, Error> {
+    get_player_profile(player_id)?;
+    let transaction_ids =
+        PLAYER_TRANSACTION_STORAGE.with(|service| related_ids(&service.borrow(), player_id));
+    transaction_ids
+        .into_iter()
+        .filter_map(|transaction_id| {
+            TRANSACTION_STORAGE.with(|service| service.borrow().get_record(&transaction_id).transpose())
+        })
+        .collect()
+}
+
+// helper function to get a player's balance, players that were never credited have nothing
+fn player_balance(player_id: u64) -> u64 {
+    BALANCE_STORAGE.with(|service| service.borrow().get(&player_id).unwrap_or(0))
+}
+
+//function to buy a weapon with ICRC tokens. The caller must own the player and first approve this
+//canister on the configured ledger for the weapon's token price; the weapon is granted once the
+//icrc2_transfer_from call succeeds
+#[ic_cdk::update]
+async fn purchase_weapon_with_tokens(player_id: u64, weapon_id: u64) -> Result<TokenPurchase, Error> {
+    let player_profile = get_player_profile(player_id)?;
+    require_player_owner(&player_profile)?;
+    let weapon = get_weapon(weapon_id)?;
+    let amount = get_weapon_token_price(weapon_id)?;
+    let ledger = get_payment_config()?.ledger.ok_or(Error::PaymentFailed {
+        msg: "no payment ledger is configured".to_string(),
+    })?;
+    let to = icrc::Account {
+        owner: ic_cdk::id(),
+        subaccount: None,
+    };
+    do_purchase_weapon_with_tokens(&icrc::IcrcLedger(ledger), &player_profile, &weapon, amount, to).await
+}
+
+//function to send the payment of a pending token purchase to the ledger again, open to the buyer and
+//to admins. The ledger charges it at most once and the weapon is granted once it confirms
+#[ic_cdk::update]
+async fn retry_token_purchase(purchase_id: u64) -> Result<TokenPurchase, Error> {
+    let purchase = get_token_purchase(purchase_id)?;
+    if env::caller() != purchase.buyer {
+        require_role(Role::Admin)?;
+    }
+    if purchase.status != TokenPurchaseStatus::Pending {
+        return Err(Error::InvalidTransaction {
+            msg: format!("token purchase with id={} is not pending", purchase_id),
+        });
+    }
+    let ledger = icrc::IcrcLedger(purchase.ledger);
+    let to = icrc::Account {
+        owner: ic_cdk::id(),
+        subaccount: None,
+    };
+    send_token_purchase(&ledger, purchase, to).await
+}
+
+//function to send the tokens of a purchase that could not grant its weapon back to the buyer,
+//open to the buyer and to admins
+#[ic_cdk::update]
+async fn refund_token_purchase(purchase_id: u64) -> Result<TokenPurchase, Error> {
+    let purchase = get_token_purchase(purchase_id)?;
+    if env::caller() != purchase.buyer {
+        require_role(Role::Admin)?;
+    }
+    let ledger = icrc::IcrcLedger(purchase.ledger);
+    do_refund_token_purchase(&ledger, purchase).await
+}
+
+// get a token purchase by id
+#[ic_cdk::query]
+fn get_token_purchase(id: u64) -> Result<TokenPurchase, Error> {
+    TOKEN_PURCHASE_STORAGE.with(|service| {
+        service
+            .borrow()
+            .get_record(&id)?
+            .ok_or(Error::NotFound {
+                msg: format!("token purchase with id={} not found", id),
+            })
+    })
+}
+
+// get every token purchase whose tokens are waiting to be refunded
+#[ic_cdk::query]
+fn get_refundable_token_purchases() -> Result<Vec<TokenPurchase>, Error> {
+    require_role(Role::Admin)?;
+    let purchases: Vec<TokenPurchase> = TOKEN_PURCHASE_STORAGE.with(|service| service.borrow().records())?;
+    Ok(purchases
+        .into_iter()
+        .filter(|purchase| matches!(purchase.status, TokenPurchaseStatus::Refundable { .. }))
+        .collect())
+}
+
+// helper function to record a pending purchase of a weapon for tokens and send its payment. The
+// purchase is stored before the ledger is called, so its created_at_time and memo are known to every
+// later retry
+async fn do_purchase_weapon_with_tokens(
+    ledger: &impl icrc::Ledger,
+    player_profile: &PlayerProfile,
+    weapon: &Weapon,
+    amount: u64,
+    to: icrc::Account,
+) -> Result<TokenPurchase, Error> {
+    is_valid_purchase(player_profile, weapon)?;
+    let id = TOKEN_PURCHASE_ID_COUNTER.with(|counter| *counter.borrow().get());
+    let purchase = TokenPurchase {
+        id,
+        player_id: player_profile.id,
+        weapon_id: weapon.id,
+        buyer: player_profile.owner,
+        ledger: ledger.id(),
+        amount,
+        block_index: None,
+        timestamp: env::time(),
+        memo: id.to_be_bytes().to_vec(),
+        refund: None,
+        status: TokenPurchaseStatus::Pending,
+    };
+    TOKEN_PURCHASE_STORAGE.with(|service| service.borrow_mut().insert_record(id, &purchase))?;
+    TOKEN_PURCHASE_ID_COUNTER
+        .with(|counter| counter.borrow_mut().set(id + 1))
+        .expect("cannot increment id counter");
+    PLAYER_TOKEN_PURCHASE_STORAGE.with(|service| service.borrow_mut().insert((player_profile.id, id), ()));
+    send_token_purchase(ledger, purchase, to).await
+}
+
+// helper function to send the payment of a pending purchase and grant its weapon once the ledger
+// confirms it. The player, the weapon and its ownership are checked again once the ledger answers,
+// because other messages run during the call; a payment that can no longer grant the weapon is
+// recorded as refundable. A payment the ledger rejected took no tokens and its purchase is dropped
+async fn send_token_purchase(
+    ledger: &impl icrc::Ledger,
+    mut purchase: TokenPurchase,
+    to: icrc::Account,
+) -> Result<TokenPurchase, Error> {
+    let (player_id, weapon_id) = (purchase.player_id, purchase.weapon_id);
+    if !PENDING_TOKEN_PURCHASES.with(|pending| pending.borrow_mut().insert((player_id, weapon_id))) {
+        return Err(Error::InvalidTransaction {
+            msg: format!(
+                "a purchase of weapon with id={} for player with id={} is already in progress",
+                weapon_id, player_id
+            ),
+        });
+    }
+    let from = icrc::Account {
+        owner: purchase.buyer,
+        subaccount: None,
+    };
+    let transfer = ledger
+        .transfer_from(from, to, purchase.amount, purchase.timestamp, purchase.memo.clone())
+        .await;
+    PENDING_TOKEN_PURCHASES.with(|pending| pending.borrow_mut().remove(&(player_id, weapon_id)));
+    let block_index = match transfer {
+        Ok(block_index) => block_index,
+        Err(icrc::LedgerError::Rejected(msg)) => {
+            TOKEN_PURCHASE_STORAGE.with(|service| service.borrow_mut().remove(&purchase.id));
+            PLAYER_TOKEN_PURCHASE_STORAGE.with(|service| service.borrow_mut().remove(&(player_id, purchase.id)));
+            return Err(Error::PaymentFailed { msg });
+        }
+        Err(error) => {
+            return Err(Error::PaymentFailed {
+                msg: format!("{}, retry token purchase with id={}", error.message(), purchase.id),
+            })
+        }
+    };
+
+    let player_exists = PLAYER_PROFILE_STORAGE.with(|service| service.borrow().contains_key(&player_id));
+    let refund_reason = if !player_exists {
+        Some("the player was deleted while the payment was processed")
+    } else if !WEAPON_PROFILE_STORAGE.with(|service| service.borrow().contains_key(&weapon_id)) {
+        Some("the weapon was deleted while the payment was processed")
+    } else if player_owns_weapon(player_id, weapon_id) {
+        Some("the player received the weapon while the payment was processed")
+    } else {
+        None
+    };
+    purchase.status = match refund_reason {
+        Some(reason) => TokenPurchaseStatus::Refundable {
+            reason: reason.to_string(),
+        },
+        None => TokenPurchaseStatus::Completed,
+    };
+    purchase.block_index = Some(block_index);
+    TOKEN_PURCHASE_STORAGE.with(|service| service.borrow_mut().insert_record(purchase.id, &purchase))?;
+    if !player_exists {
+        PLAYER_TOKEN_PURCHASE_STORAGE.with(|service| service.borrow_mut().remove(&(player_id, purchase.id)));
+    }
+    if purchase.status == TokenPurchaseStatus::Completed {
+        PLAYER_WEAPON_STORAGE.with(|service| service.borrow_mut().insert((player_id, weapon_id), ()));
+    }
+    Ok(purchase)
+}
+
+// helper function to send the tokens of a refundable purchase back to the buyer, less the ledger fee
+async fn do_refund_token_purchase(
+    ledger: &impl icrc::Ledger,
+    mut purchase: TokenPurchase,
+) -> Result<TokenPurchase, Error> {
+    if !matches!(purchase.status, TokenPurchaseStatus::Refundable { .. }) {
+        return Err(Error::InvalidTransaction {
+            msg: format!("token purchase with id={} cannot be refunded", purchase.id),
+        });
+    }
+    if !PENDING_TOKEN_REFUNDS.with(|pending| pending.borrow_mut().insert(purchase.id)) {
+        return Err(Error::InvalidTransaction {
+            msg: format!("a refund of token purchase with id={} is already in progress", purchase.id),
+        });
+    }
+    let refund = send_token_refund(ledger, &mut purchase).await;
+    PENDING_TOKEN_REFUNDS.with(|pending| pending.borrow_mut().remove(&purchase.id));
+    let (amount, block_index) = refund?;
+
+    purchase.refund = None;
+    purchase.status = TokenPurchaseStatus::Refunded { amount, block_index };
+    TOKEN_PURCHASE_STORAGE.with(|service| service.borrow_mut().insert_record(purchase.id, &purchase))?;
+    Ok(purchase)
+}
+
+// helper function to send a refund to the ledger. Its created_at_time and fee are stored on the purchase
+// before the first send and reused by every later one, so the ledger refunds once; a refund the ledger
+// rejected is forgotten and the next one is sent with the fee of the time
+async fn send_token_refund(ledger: &impl icrc::Ledger, purchase: &mut TokenPurchase) -> Result<(u64, candid::Nat), Error> {
+    let refund = match purchase.refund.clone() {
+        Some(refund) => refund,
+        None => {
+            let fee = ledger.fee().await.map_err(|msg| Error::PaymentFailed { msg })?;
+            if fee >= purchase.amount {
+                return Err(Error::PaymentFailed {
+                    msg: format!("the ledger fee {} leaves nothing to refund", fee),
+                });
+            }
+            let refund = TokenRefund {
+                created_at_time: env::time(),
+                fee,
+            };
+            purchase.refund = Some(refund.clone());
+            TOKEN_PURCHASE_STORAGE.with(|service| service.borrow_mut().insert_record(purchase.id, purchase))?;
+            refund
+        }
+    };
+    let to = icrc::Account {
+        owner: purchase.buyer,
+        subaccount: None,
+    };
+    let amount = purchase.amount - refund.fee;
+    match ledger
+        .transfer(to, amount, refund.fee, refund.created_at_time, purchase.memo.clone())
+        .await
+    {
+        Ok(block_index) => Ok((amount, block_index)),
+        Err(icrc::LedgerError::Rejected(msg)) => {
+            purchase.refund = None;
+            TOKEN_PURCHASE_STORAGE.with(|service| service.borrow_mut().insert_record(purchase.id, purchase))?;
+            Err(Error::PaymentFailed { msg })
+        }
+        Err(error) => Err(Error::PaymentFailed { msg: error.message() }),
+    }
+}
+
+//function to sell a weapon for ledger tokens, None stops selling it for tokens
+#[ic_cdk::update]
+fn set_weapon_token_price(weapon_id: u64, price: Option<u64>) -> Result<(), Error> {
+    require_role(Role::Designer)?;
+    get_weapon(weapon_id)?;
+    match price {
+        Some(price) if price > 0 => {
+            WEAPON_TOKEN_PRICE_STORAGE.with(|service| service.borrow_mut().insert(weapon_id, price));
+        }
+        Some(_) => {
+            return Err(Error::InvalidTransaction {
+                msg: "token price must be positive".to_string(),
+            })
+        }
+        None => {
+            WEAPON_TOKEN_PRICE_STORAGE.with(|service| service.borrow_mut().remove(&weapon_id));
+        }
+    }
+    Ok(())
+}
+
+// get the price in ledger tokens of a weapon
+#[ic_cdk::query]
+fn get_weapon_token_price(weapon_id: u64) -> Result<u64, Error> {
+    WEAPON_TOKEN_PRICE_STORAGE
+        .with(|service| service.borrow().get(&weapon_id))
+        .ok_or(Error::NotFound {
+            msg: format!("weapon with id={} is not sold for tokens", weapon_id),
+        })
+}
+
+// get every token purchase of a player, oldest first
+#[ic_cdk::query]
+fn get_player_token_purchases(player_id: u64) -> Result<Vec<TokenPurchase>, Error> {
+    let purchase_ids =
+        PLAYER_TOKEN_PURCHASE_STORAGE.with(|service| related_ids(&service.borrow(), player_id));
+    purchase_ids
+        .into_iter()
+        .filter_map(|purchase_id| {
+            TOKEN_PURCHASE_STORAGE.with(|service| service.borrow().get_record(&purchase_id).transpose())
+        })
+        .collect()
+}
+
+// get the payment settings
+#[ic_cdk::query]
+fn get_payment_config() -> Result<PaymentConfig, Error> {
+    PAYMENT_CONFIG.with(|cell| cell.borrow().get().decode())
+}
+
+//function to choose the ledger that token prices are charged on
+#[ic_cdk::update]
+fn set_payment_config(config: PaymentConfig) -> Result<PaymentConfig, Error> {
+    require_role(Role::Admin)?;
+    let stored = Stored::new(&config)?;
+    PAYMENT_CONFIG
+        .with(|cell| cell.borrow_mut().set(stored))
+        .expect("cannot set payment config");
+    Ok(config)
+}
+
+// helper function to check that a player can buy a weapon they do not own yet
+fn is_valid_purchase(player_profile: &PlayerProfile, weapon: &Weapon) -> Result<(), Error> {
+    if player_owns_weapon(player_profile.id, weapon.id) {
+        return Err(Error::InvalidTransaction {
+            msg: format!(
+                "player with id={} already owns weapon with id={}",
+                player_profile.id, weapon.id
+            ),
+        });
+    }
+    if PENDING_TOKEN_PURCHASES.with(|pending| pending.borrow().contains(&(player_profile.id, weapon.id))) {
+        return Err(Error::InvalidTransaction {
+            msg: format!(
+                "a token purchase of weapon with id={} for player with id={} is in progress",
+                weapon.id, player_profile.id
+            ),
+        });
+    }
+    meets_weapon_requirements(player_profile, weapon)
+}
+
+// helper function to ensure a player has the level and rank a weapon requires
+fn meets_weapon_requirements(player_profile: &PlayerProfile, weapon: &Weapon) -> Result<(), Error> {
+    if player_profile.level < weapon.level || player_profile.rank < weapon.rank {
+        return Err(Error::RequirementNotMet {
+            msg: format!(
+                "weapon with id={} requires level {} and rank {}",
+                weapon.id, weapon.level, weapon.rank
+            ),
+        });
+    }
+    Ok(())
+}
+
+// helper function to check whether a player owns a weapon
+fn player_owns_weapon(player_id: u64, weapon_id: u64) -> bool {
+    PLAYER_WEAPON_STORAGE.with(|service| service.borrow().contains_key(&(player_id, weapon_id)))
+}
+
+// helper function to store a transaction and set the player's new balance. The transaction is
+// stored first so that a failed write leaves the balance untouched
+fn do_record_transaction(
+    player_id: u64,
+    kind: TransactionKind,
+    amount: u64,
+    balance_after: u64,
+    note: Option<String>,
+) -> Result<Transaction, Error> {
+    let id = TRANSACTION_ID_COUNTER.with(|counter| *counter.borrow().get());
+    let transaction = Transaction {
+        id,
+        player_id,
+        kind,
+        amount,
+        balance_after,
+        caller: env::caller(),
+        timestamp: env::time(),
+        note,
+    };
+    TRANSACTION_STORAGE.with(|service| service.borrow_mut().insert_record(id, &transaction))?;
+    TRANSACTION_ID_COUNTER
+        .with(|counter| counter.borrow_mut().set(id + 1))
+        .expect("cannot increment id counter");
+    BALANCE_STORAGE.with(|service| service.borrow_mut().insert(player_id, balance_after));
+    PLAYER_TRANSACTION_STORAGE.with(|service| service.borrow_mut().insert((player_id, id), ()));
+    Ok(transaction)
+}
+
+// helper function to add currency to a player's balance
+fn do_credit(
+    player_id: u64,
+    kind: TransactionKind,
+    amount: u64,
+    note: Option<String>,
+) -> Result<Transaction, Error> {
+    let balance_after = player_balance(player_id)
+        .checked_add(amount)
+        .ok_or(Error::InvalidTransaction {
+            msg: format!("balance of player with id={} would overflow", player_id),
+        })?;
+    do_record_transaction(player_id, kind, amount, balance_after, note)
+}
+
+// helper function to take currency from a player's balance
+fn do_debit(
+    player_id: u64,
+    kind: TransactionKind,
+    amount: u64,
+    note: Option<String>,
+) -> Result<Transaction, Error> {
+    let balance = player_balance(player_id);
+    let balance_after = balance.checked_sub(amount).ok_or(Error::InsufficientFunds {
+        msg: format!("player with id={} cannot afford {}", player_id, amount),
+        balance,
+        price: amount,
+    })?;
+    do_record_transaction(player_id, kind, amount, balance_after, note)
+}
+
+//function to offer weapons and currency to another player in exchange for theirs. The offered
+//weapons and currency are held in escrow until the offer is accepted, cancelled or expires
+#[ic_cdk::update]
+fn create_trade_offer(trade_payload: TradeOfferPayload) -> Result<TradeOffer, Error> {
+    is_valid_trade_offer_payload(&trade_payload)?;
+    let from_player = get_player_profile(trade_payload.from_player_id)?;
+    require_player_owner(&from_player)?;
+    let to_player = get_player_profile(trade_payload.to_player_id)?;
+    for weapon_id in trade_payload.offered_weapon_ids.iter() {
+        is_tradable_weapon(trade_payload.from_player_id, *weapon_id)?;
+        meets_weapon_requirements(&to_player, &get_weapon(*weapon_id)?)?;
+    }
+    for weapon_id in trade_payload.requested_weapon_ids.iter() {
+        meets_weapon_requirements(&from_player, &get_weapon(*weapon_id)?)?;
+        if !player_owns_weapon(trade_payload.to_player_id, *weapon_id) {
+            return Err(Error::InvalidTransaction {
+                msg: format!(
+                    "player with id={} does not own weapon with id={}",
+                    trade_payload.to_player_id, weapon_id
+                ),
+            });
+        }
+    }
+    let balance = player_balance(trade_payload.from_player_id);
+    if balance < trade_payload.offered_currency {
+        return Err(Error::InsufficientFunds {
+            msg: format!("player with id={} cannot afford the offer", trade_payload.from_player_id),
+            balance,
+            price: trade_payload.offered_currency,
+        });
+    }
+
+    let id = TRADE_ID_COUNTER.with(|counter| *counter.borrow().get());
+    let now = env::time();
+    let trade_offer = TradeOffer {
+        id,
+        from_player_id: trade_payload.from_player_id,
+        to_player_id: trade_payload.to_player_id,
+        offered_weapon_ids: trade_payload.offered_weapon_ids,
+        offered_currency: trade_payload.offered_currency,
+        requested_weapon_ids: trade_payload.requested_weapon_ids,
+        requested_currency: trade_payload.requested_currency,
+        status: TradeStatus::Open,
+        created_at: now,
+        expires_at: now.saturating_add(trade_payload.expires_in_seconds.saturating_mul(1_000_000_000)),
+        closed_at: None,
+    };
+    do_insert_trade_offer(&trade_offer)?;
+    TRADE_ID_COUNTER
+        .with(|counter| counter.borrow_mut().set(id + 1))
+        .expect("cannot increment id counter");
+    PLAYER_TRADE_STORAGE.with(|service| {
+        let mut relations = service.borrow_mut();
+        relations.insert((trade_offer.from_player_id, id), ());
+        relations.insert((trade_offer.to_player_id, id), ());
+    });
+    ESCROWED_WEAPON_STORAGE.with(|service| {
+        let mut escrow = service.borrow_mut();
+        for weapon_id in trade_offer.offered_weapon_ids.iter() {
+            escrow.insert((trade_offer.from_player_id, *weapon_id), id);
+        }
+    });
+    if trade_offer.offered_currency > 0 {
+        do_debit(
+            trade_offer.from_player_id,
+            TransactionKind::TradeEscrow { trade_id: id },
+            trade_offer.offered_currency,
+            None,
+        )?;
+    }
+    Ok(trade_offer)
+}
+
+//function for the receiving player to accept an open offer, both sides are exchanged at once
+#[ic_cdk::update]
+fn accept_trade_offer(id: u64) -> Result<TradeOffer, Error> {
+    let mut trade_offer = get_trade_offer(id)?;
+    let to_player = get_player_profile(trade_offer.to_player_id)?;
+    require_player_owner(&to_player)?;
+    is_open_trade_offer(&trade_offer)?;
+    if env::time() >= trade_offer.expires_at {
+        do_close_trade_offer(trade_offer, TradeStatus::Expired)?;
+        return Err(Error::InvalidTransaction {
+            msg: format!("trade offer with id={} has expired", id),
+        });
+    }
+    //levels and ranks may have changed since the offer was made
+    let from_player = get_player_profile(trade_offer.from_player_id)?;
+    for weapon_id in trade_offer.requested_weapon_ids.iter() {
+        is_tradable_weapon(trade_offer.to_player_id, *weapon_id)?;
+        meets_weapon_requirements(&from_player, &get_weapon(*weapon_id)?)?;
+        if player_owns_weapon(trade_offer.from_player_id, *weapon_id) {
+            return Err(Error::InvalidTransaction {
+                msg: format!("player with id={} already owns weapon with id={}", trade_offer.from_player_id, weapon_id),
+            });
+        }
+    }
+    for weapon_id in trade_offer.offered_weapon_ids.iter() {
+        meets_weapon_requirements(&to_player, &get_weapon(*weapon_id)?)?;
+        if player_owns_weapon(trade_offer.to_player_id, *weapon_id) {
+            return Err(Error::InvalidTransaction {
+                msg: format!("player with id={} already owns weapon with id={}", trade_offer.to_player_id, weapon_id),
+            });
+        }
+    }
+    let balance = player_balance(trade_offer.to_player_id);
+    if balance < trade_offer.requested_currency {
+        return Err(Error::InsufficientFunds {
+            msg: format!("player with id={} cannot afford the trade", trade_offer.to_player_id),
+            balance,
+            price: trade_offer.requested_currency,
+        });
+    }
+
+    let (from_player_id, to_player_id) = (trade_offer.from_player_id, trade_offer.to_player_id);
+    //balances are checked before the first write, and the currency moves before the weapons, so that
+    //nothing can fail once the trade has started to be applied
+    let overflows = player_balance(from_player_id)
+        .checked_add(trade_offer.requested_currency)
+        .is_none()
+        || (balance - trade_offer.requested_currency)
+            .checked_add(trade_offer.offered_currency)
+            .is_none();
+    if overflows {
+        return Err(Error::InvalidTransaction {
+            msg: format!("trade offer with id={} would overflow a balance", id),
+        });
+    }
+    if trade_offer.requested_currency > 0 {
+        do_debit(
+            to_player_id,
+            TransactionKind::TradeSent { trade_id: id },
+            trade_offer.requested_currency,
+            None,
+        )?;
+        do_credit(
+            from_player_id,
+            TransactionKind::TradeReceived { trade_id: id },
+            trade_offer.requested_currency,
+            None,
+        )?;
+    }
+    if trade_offer.offered_currency > 0 {
+        do_credit(
+            to_player_id,
+            TransactionKind::TradeReceived { trade_id: id },
+            trade_offer.offered_currency,
+            None,
+        )?;
+    }
+
+    PLAYER_WEAPON_STORAGE.with(|service| {
+        let mut inventory = service.borrow_mut();
+        for weapon_id in trade_offer.offered_weapon_ids.iter() {
+            inventory.remove(&(from_player_id, *weapon_id));
+            inventory.insert((to_player_id, *weapon_id), ());
+        }
+        for weapon_id in trade_offer.requested_weapon_ids.iter() {
+            inventory.remove(&(to_player_id, *weapon_id));
+            inventory.insert((from_player_id, *weapon_id), ());
+        }
+    });
+    ESCROWED_WEAPON_STORAGE.with(|service| {
+        let mut escrow = service.borrow_mut();
+        for weapon_id in trade_offer.offered_weapon_ids.iter() {
+            escrow.remove(&(from_player_id, *weapon_id));
+        }
+    });
+    for weapon_id in trade_offer.offered_weapon_ids.iter() {
+        do_unequip_weapon(from_player_id, *weapon_id)?;
+    }
+    for weapon_id in trade_offer.requested_weapon_ids.iter() {
+        do_unequip_weapon(to_player_id, *weapon_id)?;
+    }
+
+    trade_offer.status = TradeStatus::Accepted;
+    trade_offer.closed_at = Some(env::time());
+    do_insert_trade_offer(&trade_offer)?;
+    Ok(trade_offer)
+}
+
+//function for the offering player to withdraw an open offer, the escrow is returned
+#[ic_cdk::update]
+fn cancel_trade_offer(id: u64) -> Result<TradeOffer, Error> {
+    let trade_offer = get_trade_offer(id)?;
+    let from_player = get_player_profile(trade_offer.from_player_id)?;
+    is_player_owner_or_admin(&from_player)?;
+    is_open_trade_offer(&trade_offer)?;
+    do_close_trade_offer(trade_offer, TradeStatus::Cancelled)
+}
+
+//function to close every open offer past its expiry and return the escrow, open to any caller
+#[ic_cdk::update]
+fn expire_trade_offers() -> Result<Vec<TradeOffer>, Error> {
+    let now = env::time();
+    open_trade_offers()?
+        .into_iter()
+        .filter(|trade_offer| now >= trade_offer.expires_at)
+        .map(|trade_offer| do_close_trade_offer(trade_offer, TradeStatus::Expired))
+        .collect()
+}
+
+// get trade offer by id
+#[ic_cdk::query]
+fn get_trade_offer(id: u64) -> Result<TradeOffer, Error> {
+    TRADE_STORAGE.with(|service| {
+        service
+            .borrow()
+            .get_record(&id)?
+            .ok_or(Error::NotFound {
+                msg: format!("trade offer with id={} not found", id),
+            })
+    })
+}
+
+// get every trade offer made or received by a player, oldest first
+#[ic_cdk::query]
+fn get_player_trades(player_id: u64) -> Result<Vec<TradeOffer>, Error> {
+    let trade_ids = PLAYER_TRADE_STORAGE.with(|service| related_ids(&service.borrow(), player_id));
+    trade_ids
+        .into_iter()
+        .filter_map(|trade_id| {
+            TRADE_STORAGE.with(|service| service.borrow().get_record(&trade_id).transpose())
+        })
+        .collect()
+}
+
+// helper function to insert trade offer
+fn do_insert_trade_offer(trade_offer: &TradeOffer) -> Result<(), Error> {
+    TRADE_STORAGE.with(|service| {
+        service
+            .borrow_mut()
+            .insert_record(trade_offer.id, trade_offer)
+    })?;
+    OPEN_TRADE_STORAGE.with(|service| {
+        if trade_offer.status == TradeStatus::Open {
+            service.borrow_mut().insert(trade_offer.id, ());
+        } else {
+            service.borrow_mut().remove(&trade_offer.id);
+        }
+    });
+    Ok(())
+}
+
+// helper function to get every open trade offer
+fn open_trade_offers() -> Result<Vec<TradeOffer>, Error> {
+    let trade_ids: Vec<u64> = OPEN_TRADE_STORAGE.with(|service| service.borrow().iter().map(|(id, _)| id).collect());
+    trade_ids.into_iter().map(get_trade_offer).collect()
+}
+
+// helper function to close an open offer and return its escrow to the offering player
+fn do_close_trade_offer(mut trade_offer: TradeOffer, status: TradeStatus) -> Result<TradeOffer, Error> {
+    ESCROWED_WEAPON_STORAGE.with(|service| {
+        let mut escrow = service.borrow_mut();
+        for weapon_id in trade_offer.offered_weapon_ids.iter() {
+            escrow.remove(&(trade_offer.from_player_id, *weapon_id));
+        }
+    });
+    if trade_offer.offered_currency > 0 && get_player_profile(trade_offer.from_player_id).is_ok() {
+        do_credit(
+            trade_offer.from_player_id,
+            TransactionKind::TradeRelease { trade_id: trade_offer.id },
+            trade_offer.offered_currency,
+            None,
+        )?;
+    }
+    trade_offer.status = status;
+    trade_offer.closed_at = Some(env::time());
+    do_insert_trade_offer(&trade_offer)?;
+    Ok(trade_offer)
+}
+
+// helper function to cancel the open offers matching a predicate, used when players or weapons are deleted
+fn do_cancel_trade_offers(affected: impl Fn(&TradeOffer) -> bool) -> Result<(), Error> {
+    for trade_offer in open_trade_offers()? {
+        if affected(&trade_offer) {
+            do_close_trade_offer(trade_offer, TradeStatus::Cancelled)?;
+        }
+    }
+    Ok(())
+}
+
+// helper function to get the open trade holding a player's weapon in escrow
+fn escrowed_trade_id(player_id: u64, weapon_id: u64) -> Option<u64> {
+    ESCROWED_WEAPON_STORAGE.with(|service| service.borrow().get(&(player_id, weapon_id)))
+}
+
+// helper function to ensure a player owns a weapon that is neither held in escrow nor listed
+fn is_tradable_weapon(player_id: u64, weapon_id: u64) -> Result<(), Error> {
+    if !player_owns_weapon(player_id, weapon_id) {
+        return Err(Error::InvalidTransaction {
+            msg: format!("player with id={} does not own weapon with id={}", player_id, weapon_id),
+        });
+    }
+    if let Some(trade_id) = escrowed_trade_id(player_id, weapon_id) {
+        return Err(Error::InvalidTransaction {
+            msg: format!("weapon with id={} is held in escrow by trade offer with id={}", weapon_id, trade_id),
+        });
+    }
+    if let Some(listing_id) = LISTED_WEAPON_STORAGE.with(|service| service.borrow().get(&(player_id, weapon_id))) {
+        return Err(Error::InvalidTransaction {
+            msg: format!("weapon with id={} is listed on the marketplace with listing id={}", weapon_id, listing_id),
+        });
+    }
+    Ok(())
+}
+
+// helper function to ensure a trade offer is still open
+fn is_open_trade_offer(trade_offer: &TradeOffer) -> Result<(), Error> {
+    if trade_offer.status == TradeStatus::Open {
+        Ok(())
+    } else {
+        Err(Error::InvalidTransaction {
+            msg: format!("trade offer with id={} is {:?}", trade_offer.id, trade_offer.status),
+        })
+    }
+}
+
+//function to list an owned weapon for sale, the weapon cannot be traded or listed again while active
+#[ic_cdk::update]
+fn create_listing(listing_payload: ListingPayload) -> Result<Listing, Error> {
+    if listing_payload.price == 0 {
+        return Err(Error::InvalidListingPayload {
+            msg: "listing price must be positive".to_string(),
+            payload: listing_payload,
+        });
+    }
+    let seller = get_player_profile(listing_payload.seller_player_id)?;
+    require_player_owner(&seller)?;
+    get_weapon(listing_payload.weapon_id)?;
+    is_tradable_weapon(listing_payload.seller_player_id, listing_payload.weapon_id)?;
+
+    let id = LISTING_ID_COUNTER.with(|counter| *counter.borrow().get());
+    let listing = Listing {
+        id,
+        seller_player_id: listing_payload.seller_player_id,
+        weapon_id: listing_payload.weapon_id,
+        price: listing_payload.price,
+        status: ListingStatus::Active,
+        highest_bid: None,
+        buyer_player_id: None,
+        sale_price: None,
+        fee: None,
+        created_at: env::time(),
+        closed_at: None,
+    };
+    do_insert_listing(&listing)?;
+    LISTING_ID_COUNTER
+        .with(|counter| counter.borrow_mut().set(id + 1))
+        .expect("cannot increment id counter");
+    PLAYER_LISTING_STORAGE.with(|service| service.borrow_mut().insert((listing.seller_player_id, id), ()));
+    LISTED_WEAPON_STORAGE.with(|service| {
+        service
+            .borrow_mut()
+            .insert((listing.seller_player_id, listing.weapon_id), id)
+    });
+    Ok(listing)
+}
+
+//function to withdraw an active listing, the highest bid is returned to its bidder
+#[ic_cdk::update]
+fn cancel_listing(id: u64) -> Result<Listing, Error> {
+    let listing = get_listing(id)?;
+    let seller = get_player_profile(listing.seller_player_id)?;
+    is_player_owner_or_admin(&seller)?;
+    is_active_listing(&listing)?;
+    do_close_listing(listing, ListingStatus::Cancelled)
+}
+
+//function to buy a listed weapon at its price
+#[ic_cdk::update]
+fn buy_listing(id: u64, buyer_player_id: u64) -> Result<Listing, Error> {
+    let listing = get_listing(id)?;
+    let buyer = get_player_profile(buyer_player_id)?;
+    require_player_owner(&buyer)?;
+    is_active_listing(&listing)?;
+    is_valid_market_buyer(&listing, &buyer)?;
+
+    do_debit(
+        buyer_player_id,
+        TransactionKind::MarketPurchase { listing_id: id },
+        listing.price,
+        None,
+    )?;
+    let price = listing.price;
+    do_settle_listing(listing, buyer_player_id, price)
+}
+
+//function to bid below the listing price, the bid is held in escrow until it is outbid, accepted
+//or the listing closes
+#[ic_cdk::update]
+fn place_bid(id: u64, bidder_player_id: u64, amount: u64) -> Result<Listing, Error> {
+    let mut listing = get_listing(id)?;
+    let bidder = get_player_profile(bidder_player_id)?;
+    require_player_owner(&bidder)?;
+    is_active_listing(&listing)?;
+    is_valid_market_buyer(&listing, &bidder)?;
+    if amount == 0 || amount >= listing.price {
+        return Err(Error::InvalidTransaction {
+            msg: format!("bids must be positive and below the listing price of {}", listing.price),
+        });
+    }
+    if let Some(highest_bid) = listing.highest_bid.as_ref() {
+        if amount <= highest_bid.amount {
+            return Err(Error::InvalidTransaction {
+                msg: format!("bids must be higher than the current bid of {}", highest_bid.amount),
+            });
+        }
+    }
+
+    do_debit(bidder_player_id, TransactionKind::MarketBid { listing_id: id }, amount, None)?;
+    do_refund_bid(&listing)?;
+    listing.highest_bid = Some(Bid {
+        bidder_player_id,
+        amount,
+        placed_at: env::time(),
+    });
+    do_insert_listing(&listing)?;
+    Ok(listing)
+}
+
+//function for the seller to sell to the highest bidder
+#[ic_cdk::update]
+fn accept_bid(id: u64) -> Result<Listing, Error> {
+    let listing = get_listing(id)?;
+    let seller = get_player_profile(listing.seller_player_id)?;
+    require_player_owner(&seller)?;
+    is_active_listing(&listing)?;
+    let highest_bid = listing.highest_bid.clone().ok_or(Error::NotFound {
+        msg: format!("listing with id={} has no bids", id),
+    })?;
+    if player_owns_weapon(highest_bid.bidder_player_id, listing.weapon_id) {
+        return Err(Error::InvalidTransaction {
+            msg: format!(
+                "player with id={} already owns weapon with id={}",
+                highest_bid.bidder_player_id, listing.weapon_id
+            ),
+        });
+    }
+    do_settle_listing(listing, highest_bid.bidder_player_id, highest_bid.amount)
+}
+
+// get listing by id
+#[ic_cdk::query]
+fn get_listing(id: u64) -> Result<Listing, Error> {
+    LISTING_STORAGE.with(|service| {
+        service
+            .borrow()
+            .get_record(&id)?
+            .ok_or(Error::NotFound {
+                msg: format!("listing with id={} not found", id),
+            })
+    })
+}
+
+// get every listing created by a player, oldest first
+#[ic_cdk::query]
+fn get_player_listings(player_id: u64) -> Result<Vec<Listing>, Error> {
+    let listing_ids = PLAYER_LISTING_STORAGE.with(|service| related_ids(&service.borrow(), player_id));
+    listing_ids
+        .into_iter()
+        .filter_map(|listing_id| {
+            LISTING_STORAGE.with(|service| service.borrow().get_record(&listing_id).transpose())
+        })
+        .collect()
+}
+
+// get one page of the active listings matching a filter, starting at the cursor listing id
+#[ic_cdk::query]
+fn search_listings(
+    filter: ListingFilter,
+    cursor: Option<u64>,
+    limit: u64,
+) -> Result<Page<u64, Listing>, Error> {
+    let limit = page_size(limit);
+    let name = filter.name.as_ref().map(|name| name.to_lowercase());
+    ACTIVE_LISTING_STORAGE.with(|service| {
+        let active_listings = service.borrow();
+        let mut items = Vec::new();
+        for (id, _) in active_listings.range(cursor.unwrap_or(0)..) {
+            if items.len() == limit {
+                return Ok(Page {
+                    items,
+                    next_cursor: Some(id),
+                });
+            }
+            let listing = get_listing(id)?;
+            if filter.min_price.is_some_and(|min_price| listing.price < min_price)
+                || filter.max_price.is_some_and(|max_price| listing.price > max_price)
+            {
+                continue;
+            }
+            let weapon = get_weapon(listing.weapon_id)?;
+            if name.as_ref().is_some_and(|name| !weapon.name.to_lowercase().contains(name))
+                || filter.min_level.is_some_and(|min_level| weapon.level < min_level)
+                || filter.max_level.is_some_and(|max_level| weapon.level > max_level)
+            {
+                continue;
+            }
+            items.push(listing);
+        }
+        Ok(Page {
+            items,
+            next_cursor: None,
+        })
+    })
+}
+
+// get the marketplace settings
+#[ic_cdk::query]
+fn get_market_config() -> Result<MarketConfig, Error> {
+    MARKET_CONFIG.with(|cell| cell.borrow().get().decode())
+}
+
+//function to choose the marketplace fee, applies to sales made afterwards
+#[ic_cdk::update]
+fn set_market_config(config: MarketConfig) -> Result<MarketConfig, Error> {
+    require_role(Role::Admin)?;
+    if config.fee_basis_points > 10_000 {
+        return Err(Error::InvalidMarketConfig {
+            msg: "fee cannot exceed 10000 basis points".to_string(),
+            payload: config,
+        });
+    }
+    let stored = Stored::new(&config)?;
+    MARKET_CONFIG
+        .with(|cell| cell.borrow_mut().set(stored))
+        .expect("cannot set market config");
+    Ok(config)
+}
+
+// get the total of the fees taken by the marketplace
+#[ic_cdk::query]
+fn get_market_fees_collected() -> u64 {
+    MARKET_FEES_COLLECTED.with(|cell| *cell.borrow().get())
+}
+
+// helper function to insert listing, keeping the index of active listings up to date
+fn do_insert_listing(listing: &Listing) -> Result<(), Error> {
+    LISTING_STORAGE.with(|service| service.borrow_mut().insert_record(listing.id, listing))?;
+    ACTIVE_LISTING_STORAGE.with(|service| {
+        if listing.status == ListingStatus::Active {
+            service.borrow_mut().insert(listing.id, ());
+        } else {
+            service.borrow_mut().remove(&listing.id);
+        }
+    });
+    Ok(())
+}
+
+// helper function to get every active listing, oldest first
+fn active_listings() -> Result<Vec<Listing>, Error> {
+    let listing_ids: Vec<u64> =
+        ACTIVE_LISTING_STORAGE.with(|service| service.borrow().iter().map(|(id, _)| id).collect());
+    listing_ids.into_iter().map(get_listing).collect()
+}
+
+// helper function to ensure a listing can still be bought or bid on
+fn is_active_listing(listing: &Listing) -> Result<(), Error> {
+    if listing.status == ListingStatus::Active {
+        Ok(())
+    } else {
+        Err(Error::InvalidTransaction {
+            msg: format!("listing with id={} is {:?}", listing.id, listing.status),
+        })
+    }
+}
+
+// helper function to ensure a player can buy a listed weapon
+fn is_valid_market_buyer(listing: &Listing, player_profile: &PlayerProfile) -> Result<(), Error> {
+    let player_id = player_profile.id;
+    if player_id == listing.seller_player_id {
+        return Err(Error::InvalidTransaction {
+            msg: "players cannot buy their own listings".to_string(),
+        });
+    }
+    if player_owns_weapon(player_id, listing.weapon_id) {
+        return Err(Error::InvalidTransaction {
+            msg: format!("player with id={} already owns weapon with id={}", player_id, listing.weapon_id),
+        });
+    }
+    meets_weapon_requirements(player_profile, &get_weapon(listing.weapon_id)?)
+}
+
+// helper function to return the highest bid of a listing to its bidder, bidders that were deleted
+// lose their bid along with their balance
+fn do_refund_bid(listing: &Listing) -> Result<(), Error> {
+    if let Some(bid) = listing.highest_bid.as_ref() {
+        if get_player_profile(bid.bidder_player_id).is_ok() {
+            do_credit(
+                bid.bidder_player_id,
+                TransactionKind::MarketBidRefund { listing_id: listing.id },
+                bid.amount,
+                None,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// helper function to hand a listed weapon to its buyer and pay the seller the sale price minus the fee.
+// The buyer has already paid sale_price, either directly or through their bid
+fn do_settle_listing(mut listing: Listing, buyer_player_id: u64, sale_price: u64) -> Result<Listing, Error> {
+    let fee_basis_points = get_market_config()?.fee_basis_points;
+    let fee = (sale_price as u128 * fee_basis_points as u128 / 10_000) as u64;
+    let is_bid_sale = listing
+        .highest_bid
+        .as_ref()
+        .is_some_and(|bid| bid.bidder_player_id == buyer_player_id && bid.amount == sale_price);
+    if !is_bid_sale {
+        do_refund_bid(&listing)?;
+    }
+    if sale_price > fee {
+        do_credit(
+            listing.seller_player_id,
+            TransactionKind::MarketSale { listing_id: listing.id, fee },
+            sale_price - fee,
+            None,
+        )?;
+    }
+    MARKET_FEES_COLLECTED
+        .with(|cell| {
+            let collected = *cell.borrow().get();
+            cell.borrow_mut().set(collected.saturating_add(fee))
+        })
+        .expect("cannot update collected fees");
+
+    PLAYER_WEAPON_STORAGE.with(|service| {
+        let mut inventory = service.borrow_mut();
+        inventory.remove(&(listing.seller_player_id, listing.weapon_id));
+        inventory.insert((buyer_player_id, listing.weapon_id), ());
+    });
+    do_unequip_weapon(listing.seller_player_id, listing.weapon_id)?;
+    LISTED_WEAPON_STORAGE.with(|service| {
+        service
+            .borrow_mut()
+            .remove(&(listing.seller_player_id, listing.weapon_id))
+    });
+    listing.status = ListingStatus::Sold;
+    listing.buyer_player_id = Some(buyer_player_id);
+    listing.sale_price = Some(sale_price);
+    listing.fee = Some(fee);
+    listing.closed_at = Some(env::time());
+    do_insert_listing(&listing)?;
+    Ok(listing)
+}
+
+// helper function to close an active listing without a sale
+fn do_close_listing(mut listing: Listing, status: ListingStatus) -> Result<Listing, Error> {
+    do_refund_bid(&listing)?;
+    LISTED_WEAPON_STORAGE.with(|service| {
+        service
+            .borrow_mut()
+            .remove(&(listing.seller_player_id, listing.weapon_id))
+    });
+    listing.status = status;
+    listing.closed_at = Some(env::time());
+    do_insert_listing(&listing)?;
+    Ok(listing)
+}
+
+// helper function to drop the bids of a deleted player, their escrow is lost along with their balance
+fn do_withdraw_bids(player_id: u64) -> Result<(), Error> {
+    for mut listing in active_listings()? {
+        let is_bidder = listing
+            .highest_bid
+            .as_ref()
+            .is_some_and(|bid| bid.bidder_player_id == player_id);
+        if is_bidder {
+            listing.highest_bid = None;
+            do_insert_listing(&listing)?;
+        }
+    }
+    Ok(())
+}
+
+// helper function to cancel the active listings matching a predicate, used when players or weapons
+// are deleted
+fn do_cancel_listings(affected: impl Fn(&Listing) -> bool) -> Result<(), Error> {
+    for listing in active_listings()? {
+        if affected(&listing) {
+            do_close_listing(listing, ListingStatus::Cancelled)?;
+        }
+    }
+    Ok(())
+}
+
+//function to create match, records a finished match in one call
+#[ic_cdk::update]
+fn create_match(
+    match_payload: MatchProfilePayload,
+) -> Result<RecordedMatch, Error> {
+    require_role(Role::GameServer)?;
+    is_valid_match_payload(&match_payload)?;
+    is_valid_match_loadouts(&match_payload)?;
+
+    let match_profile = do_create_match(match_payload, MatchStatus::Completed)?;
+    do_record_completed_match(match_profile)
+}
+
+//function to schedule a match, results can be edited with update_match until it is completed
+#[ic_cdk::update]
+fn schedule_match(match_payload: MatchProfilePayload) -> Result<Match, Error> {
+    require_role(Role::GameServer)?;
+    is_valid_match_payload(&match_payload)?;
+    is_valid_match_loadouts(&match_payload)?;
+    do_create_match(match_payload, MatchStatus::Scheduled)
+}
+
+//function to move a scheduled match to in progress
+#[ic_cdk::update]
+fn start_match(id: u64) -> Result<Match, Error> {
+    require_role(Role::GameServer)?;
+    let mut match_profile = get_match(id)?;
+    do_transition_match(&mut match_profile, MatchStatus::InProgress, None)?;
+    do_insert_match(&match_profile)?;
+    Ok(match_profile)
+}
+
+//function to complete a match in progress, its results then count towards standings and ratings
+#[ic_cdk::update]
+fn complete_match(id: u64) -> Result<RecordedMatch, Error> {
+    require_role(Role::GameServer)?;
+    let mut match_profile = get_match(id)?;
+    do_transition_match(&mut match_profile, MatchStatus::Completed, None)?;
+    do_insert_match(&match_profile)?;
+    do_record_completed_match(match_profile)
+}
+
+// helper function to count a newly completed match towards standings, ratings and XP
+fn do_record_completed_match(match_profile: Match) -> Result<RecordedMatch, Error> {
+    do_add_match_to_standings(&match_profile)?;
+    do_add_match_to_weapon_telemetry(&match_profile)?;
+    do_rate_match(&match_profile)?;
+    let level_ups = do_award_match_xp(&match_profile)?;
+    do_award_battle_pass_xp(&match_profile)?;
+    let unlocked_achievements = do_unlock_achievements(&match_profile)?;
+    let completed_quests = do_track_quests(&match_profile)?;
+    Ok(RecordedMatch {
+        match_profile,
+        level_ups,
+        unlocked_achievements,
+        completed_quests,
+    })
+}
+
+//function to dispute the results of a completed match, open to the game server and the owners of
+//the participating players
+#[ic_cdk::update]
+fn dispute_match(id: u64, reason: String) -> Result<Match, Error> {
+    let mut match_profile = get_match(id)?;
+    let caller = env::caller();
+    let owns_participant = match_profile.participants.iter().any(|participant| {
+        get_player_profile(participant.player_id).is_ok_and(|player| player.owner == caller)
+    });
+    if !owns_participant {
+        require_role(Role::GameServer)?;
+    }
+    is_valid_dispute_note(&reason)?;
+    let dispute_rounds = match_profile
+        .transitions
+        .iter()
+        .filter(|transition| transition.status == MatchStatus::Disputed)
+        .count();
+    if dispute_rounds >= MAX_DISPUTE_ROUNDS {
+        return Err(Error::RequirementNotMet {
+            msg: format!("match with id={} has already been disputed {} times", id, MAX_DISPUTE_ROUNDS),
+        });
+    }
+
+    do_transition_match(&mut match_profile, MatchStatus::Disputed, Some(reason))?;
+    do_insert_match(&match_profile)?;
+    Ok(match_profile)
+}
+
+//function for moderators to resolve a dispute, optionally correcting the results. Standings and
+//weapon telemetry follow the corrected results. Ratings, XP, battle pass XP, achievements and quest
+//rewards were awarded when the match completed and cannot be taken back once spent or claimed, so a
+//correction that would change any of them is rejected
+#[ic_cdk::update]
+fn resolve_match_dispute(
+    id: u64,
+    corrected_payload: Option<MatchProfilePayload>,
+    note: String,
+) -> Result<Match, Error> {
+    require_role(Role::Moderator)?;
+    is_valid_dispute_note(&note)?;
+    let disputed_match = get_match(id)?;
+    let mut resolved_match = disputed_match.clone();
+    do_transition_match(&mut resolved_match, MatchStatus::Completed, Some(note))?;
+
+    match corrected_payload {
+        Some(match_payload) => {
+            is_valid_match_payload(&match_payload)?;
+            is_valid_match_loadouts(&match_payload)?;
+            is_award_preserving_correction(&disputed_match, &match_payload)?;
+            resolved_match.participants = match_payload.participants;
+            resolved_match.level = match_payload.level;
+            resolved_match.rank = match_payload.rank;
+            resolved_match.time = match_payload.time;
+
+            do_insert_match(&resolved_match)?;
+            do_unlink_match_participants(&disputed_match);
+            do_link_match_participants(&resolved_match);
+            do_remove_match_from_standings(&disputed_match)?;
+            do_add_match_to_standings(&resolved_match)?;
+            do_remove_match_from_weapon_telemetry(&disputed_match)?;
+            do_add_match_to_weapon_telemetry(&resolved_match)?;
+        }
+        None => do_insert_match(&resolved_match)?,
+    }
+    Ok(resolved_match)
+}
+
+// helper function to store a new match with the given status
+fn do_create_match(match_payload: MatchProfilePayload, status: MatchStatus) -> Result<Match, Error> {
+    let id = MATCH_ID_COUNTER
+        .with(|counter| {
+            let current_value = *counter.borrow().get();
+            counter.borrow_mut().set(current_value + 1)
+        })
+        .expect("cannot increment id counter");
+
+    let match_profile = Match {
+        id,
+        participants: match_payload.participants,
+        level: match_payload.level,
+        rank: match_payload.rank,
+        time: match_payload.time,
+        status,
+        transitions: vec![new_match_transition(status, None)],
+    };
+    do_insert_match(&match_profile)?;
+    do_link_match_participants(&match_profile);
+    Ok(match_profile)
+}
+
+// helper function to move a match to its next status, rejecting transitions that are not allowed
+fn do_transition_match(
+    match_profile: &mut Match,
+    status: MatchStatus,
+    note: Option<String>,
+) -> Result<(), Error> {
+    if !match_profile.status.can_transition_to(status) {
+        return Err(Error::InvalidMatchTransition {
+            msg: format!(
+                "match with id={} cannot move from {:?} to {:?}",
+                match_profile.id, match_profile.status, status
+            ),
+            status: match_profile.status,
+        });
+    }
+    match_profile.status = status;
+    match_profile.transitions.push(new_match_transition(status, note));
+    Ok(())
+}
+
+// helper function to record a status change made by the caller now
+fn new_match_transition(status: MatchStatus, note: Option<String>) -> MatchTransition {
+    MatchTransition {
+        status,
+        timestamp: env::time(),
+        caller: env::caller(),
+        note,
+    }
+}
+
+// helper function to add a match to the match history of each participant
+fn do_link_match_participants(match_profile: &Match) {
+    PLAYER_MATCH_STORAGE.with(|service| {
+        let mut relations = service.borrow_mut();
+        for participant in match_profile.participants.iter() {
+            relations.insert((participant.player_id, match_profile.id), ());
+        }
+    });
+}
+
+// helper function to remove a match from the match history of each participant
+fn do_unlink_match_participants(match_profile: &Match) {
+    PLAYER_MATCH_STORAGE.with(|service| {
+        let mut relations = service.borrow_mut();
+        for participant in match_profile.participants.iter() {
+            relations.remove(&(participant.player_id, match_profile.id));
+        }
+    });
+}
+
+// helper function to get match profile
+fn do_insert_match(match_profile: &Match) -> Result<(), Error> {
+    MATCH_PROFILE_STORAGE.with(|service| {
+        service
+            .borrow_mut()
+            .insert_record(match_profile.id, match_profile)
+    })
+}
+
+//function to update match
+#[ic_cdk::update]
+fn update_match(id:u64,match_payload: MatchProfilePayload)-> Result<Match, Error>{
+    require_role(Role::GameServer)?;
+    is_valid_match_payload(&match_payload)?;
+    is_valid_match_loadouts(&match_payload)?;
+    let match_profile = MATCH_PROFILE_STORAGE.with(|service| {
+        service
+            .borrow()
+            .get_record(&id)?
+            .ok_or(Error::NotFound {
+                msg: "Match not found".to_string(),
+            })
+    })?;
+    if match_profile.status.is_finalized() {
+        return Err(Error::InvalidMatchTransition {
+            msg: format!("match with id={} is finalized and cannot be edited", id),
+            status: match_profile.status,
+        });
+    }
+
+    let updated_match = Match {
+        id,
+        participants: match_payload.participants,
+        level: match_payload.level,
+        rank: match_payload.rank,
+        time: match_payload.time,
+        status: match_profile.status,
+        transitions: match_profile.transitions.clone(),
+    };
+
+    do_insert_match(&updated_match)?;
+    do_unlink_match_participants(&match_profile);
+    do_link_match_participants(&updated_match);
+    Ok(updated_match)   
+}
+
+// get match by id
+#[ic_cdk::query]
+fn get_match(id: u64) -> Result<Match, Error> {
+    MATCH_PROFILE_STORAGE.with(|service| {
+        service
+            .borrow()
+            .get_record(&id)?
+            .ok_or(Error::NotFound {
+                msg: format!("match with id={} not found", id),
+            })
+    })
+}
+
+// function to get all matches
+#[ic_cdk::query]
+fn get_all_matches() -> Result<Vec<Match>, Error> {
+    let match_profile: Vec<Match> =
+        MATCH_PROFILE_STORAGE.with(|service| service.borrow().records())?;
+
+    if !match_profile.is_empty() {
+        Ok(match_profile)
+    } else {
+        Err(Error::NotFound {
+            msg: "No matches found ".to_string(),
+        })
+    }
+}
+
+// function to get one page of matches, starting at the cursor id
+#[ic_cdk::query]
+fn get_matches_page(cursor: Option<u64>, limit: u64) -> Result<Page<u64, Match>, Error> {
+    MATCH_PROFILE_STORAGE.with(|service| service.borrow().page(cursor, page_size(limit)))
+}
+
+//function to delete match
+#[ic_cdk::update]
+fn delete_match(id: u64) -> Result<(), Error> {
+    require_role(Role::GameServer)?;
+    let match_profile = get_match(id)?;
+    if match_profile.status.is_finalized() {
+        require_role(Role::Moderator)?;
+    }
+    MATCH_PROFILE_STORAGE.with(|service| service.borrow_mut().remove(&id));
+    if match_profile.status.is_finalized() {
+        do_remove_match_from_standings(&match_profile)?;
+        do_remove_match_from_weapon_telemetry(&match_profile)?;
+    }
+    PLAYER_MATCH_STORAGE.with(|service| service.borrow_mut().remove_related_id(id));
+    Ok(())
+}
+
+
+//add match to player profile
+#[ic_cdk::update]
+fn add_match_to_player_profile(player_id: u64, match_id: u64) -> Result<(), Error> {
+    let player_profile = PLAYER_PROFILE_STORAGE.with(|service| {
+        service
+            .borrow()
+            .get_record(&player_id)?
+            .ok_or(Error::NotFound {
+                msg: format!("player with id={} not found", player_id),
+            })
+    })?;
+    is_player_owner_or_admin(&player_profile)?;
+
+    get_match(match_id)?;
+
+    PLAYER_MATCH_STORAGE.with(|service| {
+        service
+            .borrow_mut()
+            .insert((player_profile.id, match_id), ())
+    });
+    Ok(())
+}
+
+// get average score of a participant across all matches
+#[ic_cdk::query]
+fn get_average_match_score() -> Result<u64, Error> {
+    let match_profile: Vec<Match> =
+        MATCH_PROFILE_STORAGE.with(|service| service.borrow().records())?;
+    let participants: Vec<&Participant> = match_profile
+        .iter()
+        .flat_map(|match_profile| match_profile.participants.iter())
+        .collect();
+
+    if !participants.is_empty() {
+        let mut sum = 0;
+        for participant in participants.iter() {
+            sum += participant.score;
+        }
+        let average = sum / participants.len() as u64;
+        Ok(average)
+    } else {
+        Err(Error::NotFound {
+            msg: "No matches found ".to_string(),
+        })
+    }
+}
+
+// leaderboard
+
+//function to create leaderboard
+#[ic_cdk::update]
+fn create_leaderboard(
+    leaderboard_payload: LeaderboardPayload,
+) -> Result<Leaderboard, Error> {
+    require_role(Role::GameServer)?;
+    is_valid_leaderboard_payload(&leaderboard_payload)?;
+
+    let id = LEADERBOARD_ID_COUNTER
+        .with(|counter| {
+            let current_value = *counter.borrow().get();
+            counter.borrow_mut().set(current_value + 1)
+        })
+        .expect("cannot increment id counter");
+
+    let leaderboard = Leaderboard {
+        id,
+        player_id: leaderboard_payload.player_id,
+        score: leaderboard_payload.score,
+        level: leaderboard_payload.level,
+        rank: leaderboard_payload.rank,
+        season_id: live_season_at(env::time())?.map(|season| season.id),
+    };
+    do_insert_leaderboard(&leaderboard)?;
+    Ok(leaderboard)
+}
+
+// helper function to get leaderboard
+
+fn do_insert_leaderboard(leaderboard: &Leaderboard) -> Result<(), Error> {
+    LEADERBOARD_STORAGE.with(|service| {
+        service
+            .borrow_mut()
+            .insert_record(leaderboard.id, leaderboard)
+    })
+}
+
+//function to update leaderboard
+#[ic_cdk::update]
+fn update_leaderboard(id:u64,leaderboard_payload: LeaderboardPayload)-> Result<Leaderboard, Error>{
+    require_role(Role::GameServer)?;
+    is_valid_leaderboard_payload(&leaderboard_payload)?;
+    let leaderboard = LEADERBOARD_STORAGE.with(|service| {
+        service
+            .borrow()
+            .get_record(&id)?
+            .ok_or(Error::NotFound {
+                msg: "Leaderboard not found".to_string(),
+            })
+    })?;
+
+    let updated_leaderboard = Leaderboard {
+        id,
+        player_id: leaderboard_payload.player_id,
+        score: leaderboard_payload.score,
+        level: leaderboard_payload.level,
+        rank: leaderboard_payload.rank,
+        season_id: leaderboard.season_id,
+    };
+
+    do_insert_leaderboard(&updated_leaderboard)?;
+    Ok(updated_leaderboard)   
+}
+
+// get leaderboard by id
+#[ic_cdk::query]
+fn get_leaderboard(id: u64) -> Result<Leaderboard, Error> {
+    LEADERBOARD_STORAGE.with(|service| {
+        service
+            .borrow()
+            .get_record(&id)?
+            .ok_or(Error::NotFound {
+                msg: format!("leaderboard with id={} not found", id),
+            })
+    })
+}
+
+// function to get all leaderboards for different game played
+#[ic_cdk::query]
+fn get_all_leaderboards() -> Result<Vec<Leaderboard>, Error> {
+    let leaderboard: Vec<Leaderboard> =
+        LEADERBOARD_STORAGE.with(|service| service.borrow().records())?;
+
+    if !leaderboard.is_empty() {
+        Ok(leaderboard)
+    } else {
         Err(Error::NotFound {
-            msg: "No matches found ".to_string(),
+            msg: "No leaderboards found ".to_string(),
+        })
+    }
+}
+
+// function to get one page of leaderboards, starting at the cursor id
+#[ic_cdk::query]
+fn get_leaderboards_page(cursor: Option<u64>, limit: u64) -> Result<Page<u64, Leaderboard>, Error> {
+    LEADERBOARD_STORAGE.with(|service| service.borrow().page(cursor, page_size(limit)))
+}
+
+//function to delete leaderboard
+#[ic_cdk::update]
+fn delete_leaderboard(id: u64) -> Result<(), Error> {
+    require_role(Role::GameServer)?;
+    LEADERBOARD_STORAGE.with(|service| {
+        service
+            .borrow_mut()
+            .remove(&id)
+            .ok_or(Error::NotFound {
+                msg: format!("leaderboard with id={} not found", id),
+            })
+    })?;
+    Ok(())
+}
+
+// sort leaderboard by score in descending order
+#[ic_cdk::query]
+fn sort_leaderboard_by_score() -> Result<Vec<Leaderboard>, Error> {
+    let mut leaderboard: Vec<Leaderboard> =
+        LEADERBOARD_STORAGE.with(|service| service.borrow().records())?;
+
+    if !leaderboard.is_empty() {
+        leaderboard.sort_by(|a, b| b.score.cmp(&a.score));
+        Ok(leaderboard)
+    } else {
+        Err(Error::NotFound {
+            msg: "No leaderboards found ".to_string(),
+        })
+    }
+}
+
+// get the best players of the computed leaderboard
+#[ic_cdk::query]
+fn get_top_players(limit: u64) -> Result<Vec<LeaderboardEntry>, Error> {
+    let player_ids: Vec<u64> = STANDING_INDEX.with(|service| {
+        service
+            .borrow()
+            .iter()
+            .take(page_size(limit))
+            .map(|((_, _, player_id), _)| player_id)
+            .collect()
+    });
+
+    player_ids
+        .into_iter()
+        .enumerate()
+        .map(|(index, player_id)| {
+            Ok(LeaderboardEntry {
+                position: index as u64 + 1,
+                standing: get_standing(player_id)?,
+            })
+        })
+        .collect()
+}
+
+// get the position of a player on the computed leaderboard
+#[ic_cdk::query]
+fn get_player_position(player_id: u64) -> Result<LeaderboardEntry, Error> {
+    let standing = get_standing(player_id)?;
+    let players_ahead =
+        STANDING_INDEX.with(|service| service.borrow().range(..standing_key(&standing)).count());
+    Ok(LeaderboardEntry {
+        position: players_ahead as u64 + 1,
+        standing,
+    })
+}
+
+// helper function to get the standing of a player who has recorded matches
+fn get_standing(player_id: u64) -> Result<PlayerStanding, Error> {
+    STANDING_STORAGE.with(|service| {
+        service
+            .borrow()
+            .get_record(&player_id)?
+            .ok_or(Error::NotFound {
+                msg: format!("player with id={} has no recorded matches", player_id),
+            })
+    })
+}
+
+// helper function to get the leaderboard index key of a standing
+fn standing_key(standing: &PlayerStanding) -> StandingKey {
+    (
+        Reverse(standing.total_score),
+        Reverse(standing.wins),
+        standing.player_id,
+    )
+}
+
+// helper function to add a recorded match to the standing of each participant
+fn do_add_match_to_standings(match_profile: &Match) -> Result<(), Error> {
+    let season = match_season(match_profile)?;
+    for participant in match_profile.participants.iter() {
+        let update = |standing: &mut PlayerStanding| {
+            standing.total_score += participant.score;
+            standing.matches_played += 1;
+            standing.wins += (participant.outcome == MatchOutcome::Win) as u64;
+        };
+        do_update_standing(participant.player_id, update)?;
+        if let Some(season) = &season {
+            do_update_season_standing(season.id, participant.player_id, update)?;
+        }
+    }
+    Ok(())
+}
+
+// helper function to take a match that is updated or deleted out of the standing of each participant
+fn do_remove_match_from_standings(match_profile: &Match) -> Result<(), Error> {
+    let season = match_season(match_profile)?;
+    for participant in match_profile.participants.iter() {
+        let update = |standing: &mut PlayerStanding| {
+            standing.total_score = standing.total_score.saturating_sub(participant.score);
+            standing.matches_played = standing.matches_played.saturating_sub(1);
+            standing.wins = standing
+                .wins
+                .saturating_sub((participant.outcome == MatchOutcome::Win) as u64);
+        };
+        do_update_standing(participant.player_id, update)?;
+        if let Some(season) = &season {
+            do_update_season_standing(season.id, participant.player_id, update)?;
+        }
+    }
+    Ok(())
+}
+
+// helper function to change a player's standing and move it to its new place in the index,
+// matches of players that were deleted are ignored
+fn do_update_standing(player_id: u64, update: impl FnOnce(&mut PlayerStanding)) -> Result<(), Error> {
+    if !PLAYER_PROFILE_STORAGE.with(|service| service.borrow().contains_key(&player_id)) {
+        return Ok(());
+    }
+
+    let mut standing = STANDING_STORAGE
+        .with(|service| service.borrow().get_record(&player_id))?
+        .unwrap_or(PlayerStanding {
+            player_id,
+            ..Default::default()
+        });
+    let old_key = standing_key(&standing);
+    update(&mut standing);
+
+    STANDING_STORAGE.with(|service| service.borrow_mut().insert_record(player_id, &standing))?;
+    STANDING_INDEX.with(|service| {
+        let mut index = service.borrow_mut();
+        index.remove(&old_key);
+        index.insert(standing_key(&standing), ());
+    });
+    Ok(())
+}
+
+// helper function to drop the standings of a deleted player, archived season standings are kept
+fn do_remove_standing(player_id: u64) -> Result<(), Error> {
+    let standing = STANDING_STORAGE.with(|service| service.borrow().get_record(&player_id))?;
+    if let Some(standing) = standing {
+        STANDING_STORAGE.with(|service| service.borrow_mut().remove(&player_id));
+        STANDING_INDEX.with(|service| service.borrow_mut().remove(&standing_key(&standing)));
+    }
+
+    let seasons: Vec<Season> = SEASON_STORAGE.with(|service| service.borrow().records())?;
+    for season in seasons.iter().filter(|season| season.ended_at.is_none()) {
+        let standing = SEASON_STANDING_STORAGE
+            .with(|service| service.borrow().get_record(&(season.id, player_id)))?;
+        if let Some(standing) = standing {
+            SEASON_STANDING_STORAGE.with(|service| service.borrow_mut().remove(&(season.id, player_id)));
+            SEASON_STANDING_INDEX
+                .with(|service| service.borrow_mut().remove(&(season.id, standing_key(&standing))));
+        }
+    }
+    Ok(())
+}
+
+//function to create a season, seasons cannot overlap and must end in the future
+#[ic_cdk::update]
+fn create_season(season_payload: SeasonPayload) -> Result<Season, Error> {
+    require_role(Role::Admin)?;
+    is_valid_season_payload(&season_payload)?;
+
+    let id = SEASON_ID_COUNTER.with(|counter| *counter.borrow().get());
+    let season = Season {
+        id,
+        name: season_payload.name,
+        starts_at: season_payload.starts_at,
+        ends_at: season_payload.ends_at,
+        ended_at: None,
+    };
+    do_insert_season(&season)?;
+    SEASON_ID_COUNTER
+        .with(|counter| counter.borrow_mut().set(id + 1))
+        .expect("cannot increment id counter");
+    schedule_season_end(season.id, season.ends_at);
+    Ok(season)
+}
+
+//function to delete a season that has not started yet
+#[ic_cdk::update]
+fn delete_season(id: u64) -> Result<(), Error> {
+    require_role(Role::Admin)?;
+    let season = get_season(id)?;
+    if season.starts_at <= env::time() {
+        return Err(Error::RequirementNotMet {
+            msg: format!("season with id={} has already started", id),
+        });
+    }
+    SEASON_STORAGE.with(|service| service.borrow_mut().remove(&id));
+    BATTLE_PASS_STORAGE.with(|service| service.borrow_mut().remove(&id));
+    Ok(())
+}
+
+//function to end a live season before its end time. Matches stop counting at once, its standings are
+//archived and ratings reset over the next timer ticks
+#[ic_cdk::update]
+fn end_season(id: u64) -> Result<Season, Error> {
+    require_role(Role::Admin)?;
+    let mut season = get_season(id)?;
+    if season.ended_at.is_some() || env::time() < season.starts_at {
+        return Err(Error::RequirementNotMet {
+            msg: format!("season with id={} is not live", id),
+        });
+    }
+    season.ended_at = Some(env::time());
+    do_insert_season(&season)?;
+    do_start_season_end(id)?;
+    Ok(season)
+}
+
+// get season by id
+#[ic_cdk::query]
+fn get_season(id: u64) -> Result<Season, Error> {
+    SEASON_STORAGE.with(|service| {
+        service
+            .borrow()
+            .get_record(&id)?
+            .ok_or(Error::NotFound {
+                msg: format!("season with id={} not found", id),
+            })
+    })
+}
+
+// function to get all seasons
+#[ic_cdk::query]
+fn get_all_seasons() -> Result<Vec<Season>, Error> {
+    let seasons: Vec<Season> = SEASON_STORAGE.with(|service| service.borrow().records())?;
+
+    if !seasons.is_empty() {
+        Ok(seasons)
+    } else {
+        Err(Error::NotFound {
+            msg: "No seasons found ".to_string(),
+        })
+    }
+}
+
+// get the season matches currently count towards
+#[ic_cdk::query]
+fn get_current_season() -> Result<Season, Error> {
+    live_season_at(env::time())?.ok_or(Error::NotFound {
+        msg: "No season is live".to_string(),
+    })
+}
+
+// get the best players of a season. The archived standings come first, a season that is still
+// being ended continues with its live standings
+#[ic_cdk::query]
+fn get_season_top_players(season_id: u64, limit: u64) -> Result<Vec<LeaderboardEntry>, Error> {
+    get_season(season_id)?;
+    let limit = page_size(limit);
+    let mut entries: Vec<LeaderboardEntry> = SEASON_ARCHIVE_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((season_id, 1)..=(season_id, u64::MAX))
+            .take(limit)
+            .map(|(_, stored)| stored.decode())
+            .collect::<Result<_, _>>()
+    })?;
+
+    let archived_players = entries.len() as u64;
+    let player_ids: Vec<u64> = SEASON_STANDING_INDEX.with(|service| {
+        service
+            .borrow()
+            .range(season_index_range(season_id))
+            .take(limit - entries.len())
+            .map(|((_, (_, _, player_id)), _)| player_id)
+            .collect()
+    });
+    for (index, player_id) in player_ids.into_iter().enumerate() {
+        entries.push(LeaderboardEntry {
+            position: archived_players + index as u64 + 1,
+            standing: get_season_standing(season_id, player_id)?,
+        });
+    }
+    Ok(entries)
+}
+
+// get the position of a player on the leaderboard of a season
+#[ic_cdk::query]
+fn get_season_player_position(season_id: u64, player_id: u64) -> Result<LeaderboardEntry, Error> {
+    get_season(season_id)?;
+    let archived_position = SEASON_POSITION_STORAGE.with(|service| service.borrow().get(&(season_id, player_id)));
+    if let Some(position) = archived_position {
+        return SEASON_ARCHIVE_STORAGE.with(|service| {
+            service
+                .borrow()
+                .get_record(&(season_id, position))?
+                .ok_or(Error::NotFound {
+                    msg: format!("position {} of season with id={} is not archived", position, season_id),
+                })
+        });
+    }
+
+    let standing = get_season_standing(season_id, player_id)?;
+    let archived_players = SEASON_END_STORAGE
+        .with(|service| service.borrow().get_record(&season_id))?
+        .map_or(0, |season_end| season_end.archived_players);
+    let players_ahead = SEASON_STANDING_INDEX.with(|service| {
+        service
+            .borrow()
+            .range((season_id, (Reverse(u64::MAX), Reverse(u64::MAX), 0))..(season_id, standing_key(&standing)))
+            .count()
+    });
+    Ok(LeaderboardEntry {
+        position: archived_players + players_ahead as u64 + 1,
+        standing,
+    })
+}
+
+// get the progress of a season that is being ended, with the error of its last failed batch
+#[ic_cdk::query]
+fn get_season_end_progress(season_id: u64) -> Result<SeasonEnd, Error> {
+    SEASON_END_STORAGE.with(|service| {
+        service
+            .borrow()
+            .get_record(&season_id)?
+            .ok_or(Error::NotFound {
+                msg: format!("season with id={} is not being ended", season_id),
+            })
+    })
+}
+
+// get the leaderboard entries created during a season, sorted by score in descending order
+#[ic_cdk::query]
+fn get_season_leaderboards(season_id: u64) -> Result<Vec<Leaderboard>, Error> {
+    get_season(season_id)?;
+    let mut leaderboard: Vec<Leaderboard> = LEADERBOARD_STORAGE
+        .with(|service| service.borrow().records())?
+        .into_iter()
+        .filter(|leaderboard: &Leaderboard| leaderboard.season_id == Some(season_id))
+        .collect();
+
+    if !leaderboard.is_empty() {
+        leaderboard.sort_by_key(|leaderboard| Reverse(leaderboard.score));
+        Ok(leaderboard)
+    } else {
+        Err(Error::NotFound {
+            msg: format!("No leaderboards found for season with id={}", season_id),
         })
     }
 }
 
-// leaderboard
+// get the settings applied when a season ends
+#[ic_cdk::query]
+fn get_season_config() -> Result<SeasonConfig, Error> {
+    SEASON_CONFIG.with(|cell| cell.borrow().get().decode())
+}
+
+//function to choose how ratings are reset between seasons, applies to seasons that end afterwards
+#[ic_cdk::update]
+fn set_season_config(config: SeasonConfig) -> Result<SeasonConfig, Error> {
+    require_role(Role::Admin)?;
+    if config.rating_carryover_basis_points > 10_000
+        || !(config.reset_deviation.is_finite() && config.reset_deviation >= 0.0)
+    {
+        return Err(Error::InvalidSeasonConfig {
+            msg: "carryover cannot exceed 10000 basis points and the reset deviation must not be negative"
+                .to_string(),
+            payload: config,
+        });
+    }
+    let stored = Stored::new(&config)?;
+    SEASON_CONFIG
+        .with(|cell| cell.borrow_mut().set(stored))
+        .expect("cannot set season config");
+    Ok(config)
+}
+
+// helper function to insert season
+fn do_insert_season(season: &Season) -> Result<(), Error> {
+    SEASON_STORAGE.with(|service| service.borrow_mut().insert_record(season.id, season))
+}
+
+// helper function to get the season that is live at a time
+fn live_season_at(time: u64) -> Result<Option<Season>, Error> {
+    let seasons: Vec<Season> = SEASON_STORAGE.with(|service| service.borrow().records())?;
+    Ok(seasons.into_iter().find(|season| season.is_live_at(time)))
+}
+
+// helper function to get the live season a finalized match counts towards. Corrections made after
+// a dispute belong to the season in which the match was first completed
+fn match_season(match_profile: &Match) -> Result<Option<Season>, Error> {
+    let completed_at = match_profile
+        .transitions
+        .iter()
+        .find(|transition| transition.status == MatchStatus::Completed)
+        .map(|transition| transition.timestamp);
+    match completed_at {
+        Some(completed_at) => live_season_at(completed_at),
+        None => Ok(None),
+    }
+}
+
+// helper function to get the standing of a player during a season that has not ended
+fn get_season_standing(season_id: u64, player_id: u64) -> Result<PlayerStanding, Error> {
+    SEASON_STANDING_STORAGE.with(|service| {
+        service
+            .borrow()
+            .get_record(&(season_id, player_id))?
+            .ok_or(Error::NotFound {
+                msg: format!("player with id={} has no matches in season with id={}", player_id, season_id),
+            })
+    })
+}
+
+// helper function to get the index keys of every standing of a season
+fn season_index_range(season_id: u64) -> std::ops::RangeInclusive<(u64, StandingKey)> {
+    (season_id, (Reverse(u64::MAX), Reverse(u64::MAX), 0))..=(season_id, (Reverse(0), Reverse(0), u64::MAX))
+}
+
+// helper function to change a player's standing during a season, like do_update_standing
+fn do_update_season_standing(
+    season_id: u64,
+    player_id: u64,
+    update: impl FnOnce(&mut PlayerStanding),
+) -> Result<(), Error> {
+    if !PLAYER_PROFILE_STORAGE.with(|service| service.borrow().contains_key(&player_id)) {
+        return Ok(());
+    }
+
+    let mut standing = SEASON_STANDING_STORAGE
+        .with(|service| service.borrow().get_record(&(season_id, player_id)))?
+        .unwrap_or(PlayerStanding {
+            player_id,
+            ..Default::default()
+        });
+    let old_key = (season_id, standing_key(&standing));
+    update(&mut standing);
+
+    SEASON_STANDING_STORAGE
+        .with(|service| service.borrow_mut().insert_record((season_id, player_id), &standing))?;
+    SEASON_STANDING_INDEX.with(|service| {
+        let mut index = service.borrow_mut();
+        index.remove(&old_key);
+        index.insert((season_id, standing_key(&standing)), ());
+    });
+    Ok(())
+}
+
+// helper function to arm a timer that ends a season at its end time. Timers are cleared by
+// upgrades, so post_upgrade arms them again for the seasons that have not ended
+fn schedule_season_end(season_id: u64, ends_at: u64) {
+    let delay = Duration::from_nanos(ends_at.saturating_sub(env::time()));
+    ic_cdk_timers::set_timer(delay, move || end_due_season(season_id));
+}
+
+// helper function run by the season timers, seasons that were deleted or ended early are skipped
+fn end_due_season(season_id: u64) {
+    match get_season(season_id) {
+        Ok(season) if season.ended_at.is_some() => {}
+        Err(Error::NotFound { .. }) => {}
+        _ => {
+            if let Err(error) = do_start_season_end(season_id) {
+                record_season_end_error(season_id, &error);
+                schedule_season_end_step(season_id, SEASON_END_RETRY);
+            }
+        }
+    }
+}
+
+// helper function to record that a season is being ended and arm the timer of its first batch.
+// A season that is already being ended keeps its progress and timer
+fn do_start_season_end(season_id: u64) -> Result<(), Error> {
+    if SEASON_END_STORAGE.with(|service| service.borrow().contains_key(&season_id)) {
+        return Ok(());
+    }
+    do_insert_season_end(&SeasonEnd {
+        season_id,
+        archived_players: 0,
+        ratings_from: 0,
+        last_error: None,
+    })?;
+    schedule_season_end_step(season_id, Duration::ZERO);
+    Ok(())
+}
 
-//function to create leaderboard
+// helper function to arm the timer of the next batch of a season that is being ended
+fn schedule_season_end_step(season_id: u64, delay: Duration) {
+    ic_cdk_timers::set_timer(delay, move || continue_season_end(season_id));
+}
+
+// helper function run by the season end timers, a failed batch is recorded and retried after a delay
+fn continue_season_end(season_id: u64) {
+    match do_season_end_step(season_id) {
+        Ok(true) => {
+            SEASON_END_STORAGE.with(|service| service.borrow_mut().remove(&season_id));
+        }
+        Ok(false) => schedule_season_end_step(season_id, Duration::ZERO),
+        Err(error) => {
+            record_season_end_error(season_id, &error);
+            schedule_season_end_step(season_id, SEASON_END_RETRY);
+        }
+    }
+}
+
+// helper function to keep the error of a failed season end where admins can read it
+fn record_season_end_error(season_id: u64, error: &Error) {
+    let mut season_end = SEASON_END_STORAGE
+        .with(|service| service.borrow().get_record(&season_id))
+        .ok()
+        .flatten()
+        .unwrap_or(SeasonEnd {
+            season_id,
+            archived_players: 0,
+            ratings_from: 0,
+            last_error: None,
+        });
+    season_end.last_error = Some(timer_error_message(error));
+    //nothing is left to record the error in if the progress itself cannot be stored
+    let _ = do_insert_season_end(&season_end);
+}
+
+// helper function to describe the error of a timer in a message short enough to store
+fn timer_error_message(error: &Error) -> String {
+    format!("{:?}", error).chars().take(MAX_TIMER_ERROR_LEN).collect()
+}
+
+fn do_insert_season_end(season_end: &SeasonEnd) -> Result<(), Error> {
+    SEASON_END_STORAGE.with(|service| service.borrow_mut().insert_record(season_end.season_id, season_end))
+}
+
+// helper function to run one batch of ending a season: archive the next best standings, or once
+// every standing is archived softly reset the next ratings towards the initial rating. Progress is
+// stored after every record so a failed batch resumes where it stopped. Returns true once done
+fn do_season_end_step(season_id: u64) -> Result<bool, Error> {
+    let mut season = get_season(season_id)?;
+    if season.ended_at.is_none() {
+        season.ended_at = Some(env::time());
+        do_insert_season(&season)?;
+    }
+    let mut season_end = get_season_end_progress(season_id)?;
+
+    let player_ids: Vec<u64> = SEASON_STANDING_INDEX.with(|service| {
+        service
+            .borrow()
+            .range(season_index_range(season_id))
+            .take(SEASON_END_BATCH)
+            .map(|((_, (_, _, player_id)), _)| player_id)
+            .collect()
+    });
+    if !player_ids.is_empty() {
+        for player_id in player_ids {
+            let standing = get_season_standing(season_id, player_id)?;
+            let index_key = (season_id, standing_key(&standing));
+            let entry = LeaderboardEntry {
+                position: season_end.archived_players + 1,
+                standing,
+            };
+            SEASON_ARCHIVE_STORAGE
+                .with(|service| service.borrow_mut().insert_record((season_id, entry.position), &entry))?;
+            season_end.archived_players = entry.position;
+            season_end.last_error = None;
+            do_insert_season_end(&season_end)?;
+            SEASON_POSITION_STORAGE
+                .with(|service| service.borrow_mut().insert((season_id, player_id), entry.position));
+            SEASON_STANDING_STORAGE.with(|service| service.borrow_mut().remove(&(season_id, player_id)));
+            SEASON_STANDING_INDEX.with(|service| service.borrow_mut().remove(&index_key));
+        }
+        return Ok(false);
+    }
+
+    let rating_config = get_rating_config()?;
+    let season_config = get_season_config()?;
+    let player_ratings: Vec<PlayerRating> = RATING_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range(season_end.ratings_from..)
+            .take(SEASON_END_BATCH)
+            .map(|(_, stored)| stored.decode())
+            .collect::<Result<_, _>>()
+    })?;
+    for player_rating in player_ratings.iter() {
+        let reset_rating = rating::soft_reset(
+            &rating_config,
+            player_rating,
+            season_config.rating_carryover_basis_points,
+            season_config.reset_deviation,
+        );
+        RATING_STORAGE
+            .with(|service| service.borrow_mut().insert_record(reset_rating.player_id, &reset_rating))?;
+        season_end.ratings_from = player_rating.player_id.saturating_add(1);
+        season_end.last_error = None;
+        do_insert_season_end(&season_end)?;
+    }
+    Ok(player_ratings.len() < SEASON_END_BATCH)
+}
+
+//function to set the battle pass of a season, replacing its tiers. Tiers that were claimed stay claimed
 #[ic_cdk::update]
-fn create_leaderboard(
-    leaderboard_payload: LeaderboardPayload,
-) -> Result<Leaderboard, Error> {
-    is_valid_leaderboard_payload(&leaderboard_payload)?;
+fn set_battle_pass(season_id: u64, battle_pass_payload: BattlePassPayload) -> Result<BattlePass, Error> {
+    require_role(Role::Admin)?;
+    let season = get_season(season_id)?;
+    if season.ended_at.is_some() {
+        return Err(Error::RequirementNotMet {
+            msg: format!("season with id={} has ended", season_id),
+        });
+    }
+    is_valid_battle_pass_payload(&battle_pass_payload)?;
 
-    let id = LEADERBOARD_ID_COUNTER
+    let battle_pass = BattlePass {
+        season_id,
+        premium_price: battle_pass_payload.premium_price,
+        tiers: battle_pass_payload.tiers,
+    };
+    BATTLE_PASS_STORAGE.with(|service| service.borrow_mut().insert_record(season_id, &battle_pass))?;
+    Ok(battle_pass)
+}
+
+// get the battle pass of a season
+#[ic_cdk::query]
+fn get_battle_pass(season_id: u64) -> Result<BattlePass, Error> {
+    BATTLE_PASS_STORAGE.with(|service| {
+        service
+            .borrow()
+            .get_record(&season_id)?
+            .ok_or(Error::NotFound {
+                msg: format!("season with id={} has no battle pass", season_id),
+            })
+    })
+}
+
+// get a player's season XP and claimed tiers on the battle pass of a season
+#[ic_cdk::query]
+fn get_player_battle_pass(season_id: u64, player_id: u64) -> Result<BattlePassProgress, Error> {
+    get_player_profile(player_id)?;
+    get_battle_pass(season_id)?;
+    battle_pass_progress(season_id, player_id)
+}
+
+//function to buy the premium pass of a season with soft currency, it unlocks the premium rewards
+//of every tier the player reaches
+#[ic_cdk::update]
+fn purchase_premium_pass(season_id: u64, player_id: u64) -> Result<Transaction, Error> {
+    let player_profile = get_player_profile(player_id)?;
+    require_player_owner(&player_profile)?;
+    let battle_pass = get_battle_pass(season_id)?;
+    if get_season(season_id)?.ended_at.is_some() {
+        return Err(Error::RequirementNotMet {
+            msg: format!("season with id={} has ended", season_id),
+        });
+    }
+    let mut progress = battle_pass_progress(season_id, player_id)?;
+    if progress.premium {
+        return Err(Error::RequirementNotMet {
+            msg: format!("player with id={} already has the premium pass", player_id),
+        });
+    }
+
+    let transaction = do_debit(
+        player_id,
+        TransactionKind::PremiumPass { season_id },
+        battle_pass.premium_price,
+        None,
+    )?;
+    progress.premium = true;
+    do_insert_battle_pass_progress(&progress)?;
+    Ok(transaction)
+}
+
+//function to claim the reward of an unlocked battle pass tier, each tier of each track can be
+//claimed once. Rewards can still be claimed after the season has ended
+#[ic_cdk::update]
+fn claim_battle_pass_reward(
+    season_id: u64,
+    player_id: u64,
+    tier: u64,
+    track: BattlePassTrack,
+) -> Result<BattlePassClaim, Error> {
+    let player_profile = get_player_profile(player_id)?;
+    require_player_owner(&player_profile)?;
+    let battle_pass = get_battle_pass(season_id)?;
+    let battle_pass_tier = tier
+        .checked_sub(1)
+        .and_then(|index| battle_pass.tiers.get(index as usize))
+        .ok_or(Error::NotFound {
+            msg: format!("battle pass of season with id={} has no tier {}", season_id, tier),
+        })?;
+    let mut progress = battle_pass_progress(season_id, player_id)?;
+    if progress.xp < battle_pass_tier.xp_required {
+        return Err(Error::RequirementNotMet {
+            msg: format!(
+                "tier {} needs {} season XP, player with id={} has {}",
+                tier, battle_pass_tier.xp_required, player_id, progress.xp
+            ),
+        });
+    }
+
+    let (reward, claimed_tiers) = match track {
+        BattlePassTrack::Free => (&battle_pass_tier.free_reward, &mut progress.claimed_free_tiers),
+        BattlePassTrack::Premium => {
+            if !progress.premium {
+                return Err(Error::RequirementNotMet {
+                    msg: format!("player with id={} does not have the premium pass", player_id),
+                });
+            }
+            (&battle_pass_tier.premium_reward, &mut progress.claimed_premium_tiers)
+        }
+    };
+    let reward = reward.clone().ok_or(Error::NotFound {
+        msg: format!("tier {} has no {:?} reward", tier, track),
+    })?;
+    if claimed_tiers.contains(&tier) {
+        return Err(Error::AlreadyClaimed {
+            msg: format!("player with id={} already claimed the {:?} reward of tier {}", player_id, track, tier),
+        });
+    }
+    claimed_tiers.push(tier);
+
+    //the claim is encoded before and stored after the reward is granted, so a reward that cannot be
+    //granted is not marked as claimed and a granted one always is
+    let stored_progress = Stored::new(&progress)?;
+    do_grant_reward(player_id, &reward, TransactionKind::BattlePassReward { season_id, tier })?;
+    BATTLE_PASS_PROGRESS_STORAGE.with(|service| service.borrow_mut().insert((season_id, player_id), stored_progress));
+    Ok(BattlePassClaim {
+        season_id,
+        player_id,
+        tier,
+        track,
+        reward,
+        claimed_at: env::time(),
+    })
+}
+
+// helper function to get a player's battle pass progress during a season, empty if they have none
+fn battle_pass_progress(season_id: u64, player_id: u64) -> Result<BattlePassProgress, Error> {
+    let progress = BATTLE_PASS_PROGRESS_STORAGE
+        .with(|service| service.borrow().get_record(&(season_id, player_id)))?;
+    Ok(progress.unwrap_or(BattlePassProgress {
+        season_id,
+        player_id,
+        xp: 0,
+        premium: false,
+        claimed_free_tiers: Vec::new(),
+        claimed_premium_tiers: Vec::new(),
+    }))
+}
+
+// helper function to insert battle pass progress
+fn do_insert_battle_pass_progress(progress: &BattlePassProgress) -> Result<(), Error> {
+    BATTLE_PASS_PROGRESS_STORAGE.with(|service| {
+        service
+            .borrow_mut()
+            .insert_record((progress.season_id, progress.player_id), progress)
+    })
+}
+
+// helper function to add the XP of a completed match to its participants' season XP, matches
+// completed while no season is live earn none
+fn do_award_battle_pass_xp(match_profile: &Match) -> Result<(), Error> {
+    let Some(season) = match_season(match_profile)? else {
+        return Ok(());
+    };
+    let config = get_leveling_config()?;
+    for participant in match_profile.participants.iter() {
+        if !PLAYER_PROFILE_STORAGE.with(|service| service.borrow().contains_key(&participant.player_id)) {
+            continue;
+        }
+        let mut progress = battle_pass_progress(season.id, participant.player_id)?;
+        progress.xp = progress
+            .xp
+            .saturating_add(leveling::match_xp(&config, participant, match_profile.time));
+        do_insert_battle_pass_progress(&progress)?;
+    }
+    Ok(())
+}
+
+// get the skill rating of a player, unrated players have the configured initial rating
+#[ic_cdk::query]
+fn get_player_rating(player_id: u64) -> Result<PlayerRating, Error> {
+    get_player_profile(player_id)?;
+    let player_rating = RATING_STORAGE.with(|service| service.borrow().get_record(&player_id))?;
+    match player_rating {
+        Some(player_rating) => Ok(player_rating),
+        None => Ok(rating::initial_rating(&get_rating_config()?, player_id)),
+    }
+}
+
+// get the rating change caused by every rated match of a player, oldest match first
+#[ic_cdk::query]
+fn get_rating_history(player_id: u64) -> Result<Vec<RatingChange>, Error> {
+    get_player_profile(player_id)?;
+    RATING_HISTORY_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((player_id, 0)..=(player_id, u64::MAX))
+            .map(|(_, stored)| stored.decode())
+            .collect()
+    })
+}
+
+// get the rating algorithm and parameters
+#[ic_cdk::query]
+fn get_rating_config() -> Result<RatingConfig, Error> {
+    RATING_CONFIG.with(|cell| cell.borrow().get().decode())
+}
+
+//function to choose the rating algorithm and parameters, applies to matches recorded afterwards
+#[ic_cdk::update]
+fn set_rating_config(config: RatingConfig) -> Result<RatingConfig, Error> {
+    require_role(Role::Admin)?;
+    rating::validate_config(&config).map_err(|msg| Error::InvalidRatingConfig {
+        msg,
+        payload: config.clone(),
+    })?;
+
+    let stored = Stored::new(&config)?;
+    RATING_CONFIG
+        .with(|cell| cell.borrow_mut().set(stored))
+        .expect("cannot set rating config");
+    Ok(config)
+}
+
+// get the XP and level of a player
+#[ic_cdk::query]
+fn get_player_xp(player_id: u64) -> Result<PlayerXp, Error> {
+    let player_profile = get_player_profile(player_id)?;
+    let config = get_leveling_config()?;
+    Ok(PlayerXp {
+        player_id,
+        xp: player_xp(player_id),
+        level: player_profile.level,
+        next_level_xp: leveling::next_level_xp(&config, player_profile.level),
+    })
+}
+
+// get the XP awards and level curve
+#[ic_cdk::query]
+fn get_leveling_config() -> Result<LevelingConfig, Error> {
+    LEVELING_CONFIG.with(|cell| cell.borrow().get().decode())
+}
+
+//function to choose the XP awards and level curve, applies to matches completed afterwards
+#[ic_cdk::update]
+fn set_leveling_config(config: LevelingConfig) -> Result<LevelingConfig, Error> {
+    require_role(Role::Admin)?;
+    leveling::validate_config(&config).map_err(|msg| Error::InvalidLevelingConfig {
+        msg,
+        payload: config.clone(),
+    })?;
+
+    let stored = Stored::new(&config)?;
+    LEVELING_CONFIG
+        .with(|cell| cell.borrow_mut().set(stored))
+        .expect("cannot set leveling config");
+    Ok(config)
+}
+
+// helper function to get a player's total XP
+fn player_xp(player_id: u64) -> u64 {
+    XP_STORAGE.with(|service| service.borrow().get(&player_id).unwrap_or(0))
+}
+
+// helper function to award XP to every participant of a completed match and level them up.
+// Levels never go down, even when a new curve would put a player lower
+fn do_award_match_xp(match_profile: &Match) -> Result<Vec<LevelUp>, Error> {
+    let config = get_leveling_config()?;
+    let mut level_ups = Vec::new();
+    for participant in match_profile.participants.iter() {
+        let Some(mut player_profile) = PLAYER_PROFILE_STORAGE
+            .with(|service| service.borrow().get_record(&participant.player_id))?
+        else {
+            continue;
+        };
+        let xp = player_xp(participant.player_id)
+            .saturating_add(leveling::match_xp(&config, participant, match_profile.time));
+        XP_STORAGE.with(|service| service.borrow_mut().insert(participant.player_id, xp));
+
+        let new_level = leveling::level_for_xp(&config, xp);
+        if new_level > player_profile.level {
+            level_ups.push(LevelUp {
+                player_id: participant.player_id,
+                previous_level: player_profile.level,
+                new_level,
+                xp,
+            });
+            player_profile.level = new_level;
+            do_insert_player(&player_profile)?;
+        }
+    }
+    Ok(level_ups)
+}
+
+//function to define an achievement, progress counts matches completed after it is created
+#[ic_cdk::update]
+fn create_achievement(achievement_payload: AchievementPayload) -> Result<Achievement, Error> {
+    require_role(Role::Admin)?;
+    is_valid_achievement_payload(&achievement_payload)?;
+
+    let id = ACHIEVEMENT_ID_COUNTER
         .with(|counter| {
             let current_value = *counter.borrow().get();
             counter.borrow_mut().set(current_value + 1)
         })
         .expect("cannot increment id counter");
 
-    let leaderboard = Leaderboard {
+    let achievement = Achievement {
         id,
-        player_id: leaderboard_payload.player_id,
-        score: leaderboard_payload.score,
-        level: leaderboard_payload.level,
-        rank: leaderboard_payload.rank,
+        name: achievement_payload.name,
+        description: achievement_payload.description,
+        badge: achievement_payload.badge,
+        metric: achievement_payload.metric,
+        threshold: achievement_payload.threshold,
+        min_weapon_rank: achievement_payload.min_weapon_rank,
+        created_at: env::time(),
     };
-    do_insert_leaderboard(&leaderboard);
-    Ok(leaderboard)
+    ACHIEVEMENT_STORAGE.with(|service| service.borrow_mut().insert_record(id, &achievement))?;
+    Ok(achievement)
 }
 
-// helper function to get leaderboard
-
-fn do_insert_leaderboard(leaderboard: &Leaderboard) {
-    LEADERBOARD_STORAGE.with(|service| {
+//function to delete an achievement along with every player's progress and unlock
+#[ic_cdk::update]
+fn delete_achievement(id: u64) -> Result<(), Error> {
+    require_role(Role::Admin)?;
+    ACHIEVEMENT_STORAGE.with(|service| {
         service
             .borrow_mut()
-            .insert(leaderboard.id, leaderboard.clone())
-    });
+            .remove(&id)
+            .ok_or(Error::NotFound {
+                msg: format!("achievement with id={} not found", id),
+            })
+    })?;
+    ACHIEVEMENT_PROGRESS_STORAGE.with(|service| service.borrow_mut().remove_related_id(id));
+    PLAYER_ACHIEVEMENT_STORAGE.with(|service| service.borrow_mut().remove_related_id(id));
+    Ok(())
 }
 
-//function to update leaderboard
-#[ic_cdk::update]
-fn update_leaderboard(id:u64,leaderboard_payload: LeaderboardPayload)-> Result<Leaderboard, Error>{
-    is_valid_leaderboard_payload(&leaderboard_payload)?;
-    LEADERBOARD_STORAGE.with(|service| {
+// get achievement by id
+#[ic_cdk::query]
+fn get_achievement(id: u64) -> Result<Achievement, Error> {
+    ACHIEVEMENT_STORAGE.with(|service| {
         service
-            .borrow_mut()
-            .get(&id)
+            .borrow()
+            .get_record(&id)?
             .ok_or(Error::NotFound {
-                msg: "Leaderboard not found".to_string(),
+                msg: format!("achievement with id={} not found", id),
             })
-    })?;
+    })
+}
 
-    let updated_leaderboard = Leaderboard {
+// function to get all achievements
+#[ic_cdk::query]
+fn get_all_achievements() -> Result<Vec<Achievement>, Error> {
+    let achievements: Vec<Achievement> =
+        ACHIEVEMENT_STORAGE.with(|service| service.borrow().records())?;
+
+    if !achievements.is_empty() {
+        Ok(achievements)
+    } else {
+        Err(Error::NotFound {
+            msg: "No achievements found ".to_string(),
+        })
+    }
+}
+
+// get every achievement unlocked by a player with its unlock time
+#[ic_cdk::query]
+fn get_player_achievements(player_id: u64) -> Result<Vec<AchievementUnlock>, Error> {
+    get_player_profile(player_id)?;
+    Ok(PLAYER_ACHIEVEMENT_STORAGE.with(|service| {
+        service
+            .borrow()
+            .range((player_id, 0)..=(player_id, u64::MAX))
+            .map(|((player_id, achievement_id), unlocked_at)| AchievementUnlock {
+                player_id,
+                achievement_id,
+                unlocked_at,
+            })
+            .collect()
+    }))
+}
+
+// get the share of players that unlocked each achievement
+#[ic_cdk::query]
+fn get_achievement_stats() -> Result<Vec<AchievementStats>, Error> {
+    let achievements: Vec<Achievement> =
+        ACHIEVEMENT_STORAGE.with(|service| service.borrow().records())?;
+    let player_count = PLAYER_PROFILE_STORAGE.with(|service| service.borrow().len());
+    let mut unlocked_counts: std::collections::BTreeMap<u64, u64> = Default::default();
+    PLAYER_ACHIEVEMENT_STORAGE.with(|service| {
+        for ((_, achievement_id), _) in service.borrow().iter() {
+            *unlocked_counts.entry(achievement_id).or_default() += 1;
+        }
+    });
+
+    Ok(achievements
+        .into_iter()
+        .map(|achievement| {
+            let unlocked_count = unlocked_counts.get(&achievement.id).copied().unwrap_or(0);
+            let unlock_percentage = if player_count == 0 {
+                0.0
+            } else {
+                unlocked_count as f64 * 100.0 / player_count as f64
+            };
+            AchievementStats {
+                achievement_id: achievement.id,
+                name: achievement.name,
+                unlocked_count,
+                unlock_percentage,
+            }
+        })
+        .collect())
+}
+
+// helper function to add a completed match to every participant's achievement progress and
+// unlock the achievements whose threshold is reached
+fn do_unlock_achievements(match_profile: &Match) -> Result<Vec<AchievementUnlock>, Error> {
+    let achievements: Vec<Achievement> =
+        ACHIEVEMENT_STORAGE.with(|service| service.borrow().records())?;
+    let unlocked_at = env::time();
+    let mut unlocks = Vec::new();
+    for participant in match_profile.participants.iter() {
+        let player_id = participant.player_id;
+        if !PLAYER_PROFILE_STORAGE.with(|service| service.borrow().contains_key(&player_id)) {
+            continue;
+        }
+        let weapon_rank = WEAPON_PROFILE_STORAGE
+            .with(|service| service.borrow().get_record(&participant.weapon_id))?
+            .map(|weapon| weapon.rank);
+
+        for achievement in achievements.iter() {
+            let key = (player_id, achievement.id);
+            if PLAYER_ACHIEVEMENT_STORAGE.with(|service| service.borrow().contains_key(&key)) {
+                continue;
+            }
+            let meets_weapon_rank = match (achievement.min_weapon_rank, weapon_rank) {
+                (None, _) => true,
+                (Some(min_weapon_rank), Some(rank)) => rank >= min_weapon_rank,
+                (Some(_), None) => false, //the weapon was deleted
+            };
+            if !meets_weapon_rank {
+                continue;
+            }
+            let gained = match_metric_value(achievement.metric, participant);
+            if gained == 0 {
+                continue;
+            }
+
+            let progress = ACHIEVEMENT_PROGRESS_STORAGE
+                .with(|service| service.borrow().get(&key).unwrap_or(0))
+                .saturating_add(gained);
+            if progress >= achievement.threshold {
+                ACHIEVEMENT_PROGRESS_STORAGE.with(|service| service.borrow_mut().remove(&key));
+                PLAYER_ACHIEVEMENT_STORAGE.with(|service| service.borrow_mut().insert(key, unlocked_at));
+                unlocks.push(AchievementUnlock {
+                    player_id,
+                    achievement_id: achievement.id,
+                    unlocked_at,
+                });
+            } else {
+                ACHIEVEMENT_PROGRESS_STORAGE.with(|service| service.borrow_mut().insert(key, progress));
+            }
+        }
+    }
+    Ok(unlocks)
+}
+
+// helper function to read the statistic an achievement or quest counts from one participant
+fn match_metric_value(metric: MatchMetric, participant: &Participant) -> u64 {
+    match metric {
+        MatchMetric::MatchesPlayed => 1,
+        MatchMetric::Wins => u64::from(participant.outcome == MatchOutcome::Win),
+        MatchMetric::Kills => participant.kills,
+        MatchMetric::Assists => participant.assists,
+        MatchMetric::Score => participant.score,
+        MatchMetric::Damage => participant.damage,
+    }
+}
+
+//function to define a quest template, it fills a free slot of the current rotation of its cadence
+//or joins the following rotations
+#[ic_cdk::update]
+fn create_quest_template(quest_payload: QuestTemplatePayload) -> Result<QuestTemplate, Error> {
+    require_role(Role::Admin)?;
+    is_valid_quest_template_payload(&quest_payload)?;
+
+    let id = QUEST_ID_COUNTER
+        .with(|counter| {
+            let current_value = *counter.borrow().get();
+            counter.borrow_mut().set(current_value + 1)
+        })
+        .expect("cannot increment id counter");
+
+    let quest = QuestTemplate {
         id,
-        player_id: leaderboard_payload.player_id,
-        score: leaderboard_payload.score,
-        level: leaderboard_payload.level,
-        rank: leaderboard_payload.rank,
+        name: quest_payload.name,
+        description: quest_payload.description,
+        cadence: quest_payload.cadence,
+        metric: quest_payload.metric,
+        target: quest_payload.target,
+        reward: quest_payload.reward,
     };
+    QUEST_TEMPLATE_STORAGE.with(|service| service.borrow_mut().insert_record(id, &quest))?;
 
-    do_insert_leaderboard(&updated_leaderboard);
-    Ok(updated_leaderboard)   
+    let mut rotation = current_quest_rotation(quest.cadence)?;
+    if rotation.quest_ids.len() < quest.cadence.active_quest_count() {
+        rotation.quest_ids.push(id);
+        do_insert_quest_rotation(&rotation)?;
+    }
+    Ok(quest)
 }
 
-// get leaderboard by id
+//function to delete a quest template, it leaves the current rotation along with every player's progress
+#[ic_cdk::update]
+fn delete_quest_template(id: u64) -> Result<(), Error> {
+    require_role(Role::Admin)?;
+    let quest = get_quest_template(id)?;
+    QUEST_TEMPLATE_STORAGE.with(|service| service.borrow_mut().remove(&id));
+    QUEST_PROGRESS_STORAGE.with(|service| service.borrow_mut().remove_related_id(id));
+
+    let mut rotation = current_quest_rotation(quest.cadence)?;
+    rotation.quest_ids.retain(|quest_id| *quest_id != id);
+    do_insert_quest_rotation(&rotation)
+}
+
+// get quest template by id
 #[ic_cdk::query]
-fn get_leaderboard(id: u64) -> Result<Leaderboard, Error> {
-    LEADERBOARD_STORAGE.with(|service| {
+fn get_quest_template(id: u64) -> Result<QuestTemplate, Error> {
+    QUEST_TEMPLATE_STORAGE.with(|service| {
         service
-            .borrow_mut()
-            .get(&id)
+            .borrow()
+            .get_record(&id)?
             .ok_or(Error::NotFound {
-                msg: format!("leaderboard with id={} not found", id),
+                msg: format!("quest template with id={} not found", id),
             })
     })
 }
 
-// function to get all leaderboards for different game played
-#[ic_cdk::query]
-fn get_all_leaderboards() -> Result<Vec<Leaderboard>, Error> {
-    let leaderboard_mapping: Vec<(u64, Leaderboard)> =
-        LEADERBOARD_STORAGE.with(|service| service.borrow().iter().collect());
-    let leaderboard: Vec<Leaderboard> = leaderboard_mapping
-        .into_iter()
-        .map(|(_, leaderboard)| leaderboard)
-        .collect();
+// function to get all quest templates
+#[ic_cdk::query]
+fn get_all_quest_templates() -> Result<Vec<QuestTemplate>, Error> {
+    let quests: Vec<QuestTemplate> =
+        QUEST_TEMPLATE_STORAGE.with(|service| service.borrow().records())?;
+
+    if !quests.is_empty() {
+        Ok(quests)
+    } else {
+        Err(Error::NotFound {
+            msg: "No quest templates found ".to_string(),
+        })
+    }
+}
+
+// get the current rotation of every quest cadence
+#[ic_cdk::query]
+fn get_active_quests() -> Result<Vec<QuestRotation>, Error> {
+    QuestCadence::ALL
+        .into_iter()
+        .map(active_quest_rotation)
+        .collect()
+}
+
+// get the last failure of the quest rotation timers that have not recovered since
+#[ic_cdk::query]
+fn get_quest_rotation_failures() -> Result<Vec<QuestRotationFailure>, Error> {
+    require_role(Role::Admin)?;
+    QUEST_ROTATION_FAILURE_STORAGE.with(|service| service.borrow().records())
+}
+
+// get the active quests with a player's progress on them
+#[ic_cdk::query]
+fn get_player_quests(player_id: u64) -> Result<Vec<PlayerQuest>, Error> {
+    get_player_profile(player_id)?;
+    let mut player_quests = Vec::new();
+    for cadence in QuestCadence::ALL {
+        let rotation = active_quest_rotation(cadence)?;
+        for quest_id in rotation.quest_ids.iter() {
+            let Some(quest) = QUEST_TEMPLATE_STORAGE.with(|service| service.borrow().get_record(quest_id))? else {
+                continue;
+            };
+            let progress = current_quest_progress(player_id, *quest_id, rotation.period)?;
+            player_quests.push(PlayerQuest {
+                quest,
+                progress: progress.as_ref().map_or(0, |progress| progress.progress),
+                completed_at: progress.and_then(|progress| progress.completed_at),
+                ends_at: rotation.ends_at,
+            });
+        }
+    }
+    Ok(player_quests)
+}
+
+// helper function to arm a timer that rotates the quests of a cadence when its period ends. Timers
+// are cleared by upgrades, so init and post_upgrade arm them again from the stored rotations
+fn schedule_quest_rotation(cadence: QuestCadence) {
+    let delay = match current_quest_rotation(cadence) {
+        Ok(rotation) => {
+            QUEST_ROTATION_FAILURE_STORAGE.with(|service| service.borrow_mut().remove(&cadence.key()));
+            Duration::from_nanos(rotation.ends_at.saturating_sub(env::time()))
+        }
+        Err(error) => {
+            let failure = QuestRotationFailure {
+                cadence,
+                message: timer_error_message(&error),
+                failed_at: env::time(),
+            };
+            //nothing is left to record the error in if the failure itself cannot be stored
+            let _ = QUEST_ROTATION_FAILURE_STORAGE
+                .with(|service| service.borrow_mut().insert_record(cadence.key(), &failure));
+            QUEST_ROTATION_RETRY
+        }
+    };
+    ic_cdk_timers::set_timer(delay, move || schedule_quest_rotation(cadence));
+}
+
+// helper function to get the rotation of the current period, rotating first when the period ended
+// before its timer fired
+fn current_quest_rotation(cadence: QuestCadence) -> Result<QuestRotation, Error> {
+    let rotation = QUEST_ROTATION_STORAGE.with(|service| service.borrow().get_record(&cadence.key()))?;
+    match rotation {
+        Some(rotation) if env::time() < rotation.ends_at => Ok(rotation),
+        _ => {
+            let rotation = next_quest_rotation(cadence)?;
+            do_insert_quest_rotation(&rotation)?;
+            Ok(rotation)
+        }
+    }
+}
+
+// helper function to get the rotation of the current period without storing it, for queries. A period
+// that ended before its timer fired is computed the same way the timer will store it
+fn active_quest_rotation(cadence: QuestCadence) -> Result<QuestRotation, Error> {
+    let rotation = QUEST_ROTATION_STORAGE.with(|service| service.borrow().get_record(&cadence.key()))?;
+    match rotation {
+        Some(rotation) if env::time() < rotation.ends_at => Ok(rotation),
+        _ => next_quest_rotation(cadence),
+    }
+}
+
+// helper function to choose the active quests of a cadence for the current period. Templates take
+// turns in id order so that every template comes up regularly
+fn next_quest_rotation(cadence: QuestCadence) -> Result<QuestRotation, Error> {
+    let period = cadence.period_at(env::time());
+    let templates: Vec<QuestTemplate> = QUEST_TEMPLATE_STORAGE
+        .with(|service| service.borrow().records())?
+        .into_iter()
+        .filter(|quest: &QuestTemplate| quest.cadence == cadence)
+        .collect();
+    let quest_ids = if templates.is_empty() {
+        Vec::new()
+    } else {
+        let count = cadence.active_quest_count();
+        let first = (period as usize).wrapping_mul(count) % templates.len();
+        (0..count.min(templates.len()))
+            .map(|offset| templates[(first + offset) % templates.len()].id)
+            .collect()
+    };
+
+    Ok(QuestRotation {
+        cadence,
+        period,
+        quest_ids,
+        started_at: cadence.period_start(period),
+        ends_at: cadence.period_start(period + 1),
+    })
+}
+
+// helper function to insert quest rotation
+fn do_insert_quest_rotation(rotation: &QuestRotation) -> Result<(), Error> {
+    QUEST_ROTATION_STORAGE.with(|service| {
+        service
+            .borrow_mut()
+            .insert_record(rotation.cadence.key(), rotation)
+    })
+}
+
+// helper function to get a player's progress on a quest during a period
+fn current_quest_progress(player_id: u64, quest_id: u64, period: u64) -> Result<Option<QuestProgress>, Error> {
+    let progress = QUEST_PROGRESS_STORAGE.with(|service| service.borrow().get_record(&(player_id, quest_id)))?;
+    Ok(progress.filter(|progress| progress.period == period))
+}
+
+// helper function to add a completed match to its participants' progress on the active quests and
+// grant the rewards of the quests it completes
+fn do_track_quests(match_profile: &Match) -> Result<Vec<QuestCompletion>, Error> {
+    let completed_at = env::time();
+    let mut completions = Vec::new();
+    for cadence in QuestCadence::ALL {
+        let rotation = current_quest_rotation(cadence)?;
+        for quest_id in rotation.quest_ids.iter() {
+            let Some(quest) = QUEST_TEMPLATE_STORAGE.with(|service| service.borrow().get_record(quest_id))? else {
+                continue;
+            };
+            for participant in match_profile.participants.iter() {
+                let player_id = participant.player_id;
+                if !PLAYER_PROFILE_STORAGE.with(|service| service.borrow().contains_key(&player_id)) {
+                    continue;
+                }
+                let gained = match_metric_value(quest.metric, participant);
+                let mut progress = current_quest_progress(player_id, quest.id, rotation.period)?
+                    .unwrap_or(QuestProgress {
+                        player_id,
+                        quest_id: quest.id,
+                        period: rotation.period,
+                        progress: 0,
+                        completed_at: None,
+                    });
+                if gained == 0 || progress.completed_at.is_some() {
+                    continue;
+                }
 
-    if !leaderboard.is_empty() {
-        Ok(leaderboard)
-    } else {
-        Err(Error::NotFound {
-            msg: "No leaderboards found ".to_string(),
-        })
+                progress.progress = progress.progress.saturating_add(gained);
+                if progress.progress >= quest.target {
+                    progress.completed_at = Some(completed_at);
+                    //quests are completed while a match is recorded, so a deleted reward weapon is
+                    //left out instead of failing the match
+                    let reward = Reward {
+                        weapon_id: quest.reward.weapon_id.filter(|weapon_id| {
+                            WEAPON_PROFILE_STORAGE.with(|service| service.borrow().contains_key(weapon_id))
+                        }),
+                        ..quest.reward.clone()
+                    };
+                    do_grant_reward(player_id, &reward, TransactionKind::QuestReward { quest_id: quest.id })?;
+                    completions.push(QuestCompletion {
+                        player_id,
+                        quest_id: quest.id,
+                        reward: quest.reward.clone(),
+                        completed_at,
+                    });
+                }
+                QUEST_PROGRESS_STORAGE.with(|service| {
+                    service
+                        .borrow_mut()
+                        .insert_record((player_id, quest.id), &progress)
+                })?;
+            }
+        }
     }
+    Ok(completions)
 }
 
-//function to delete leaderboard
-#[ic_cdk::update]
-fn delete_leaderboard(id: u64) -> Result<(), Error> {
-    LEADERBOARD_STORAGE.with(|service| {
-        service
-            .borrow_mut()
-            .remove(&id)
+// helper function to grant a reward, the currency is credited with a transaction of the given kind.
+// A reward weapon the player already owns is paid out at its price instead, so claiming it is never
+// worth nothing
+fn do_grant_reward(player_id: u64, reward: &Reward, kind: TransactionKind) -> Result<(), Error> {
+    let mut currency = reward.currency;
+    let mut note = None;
+    let mut granted_weapon_id = None;
+    if let Some(weapon_id) = reward.weapon_id {
+        let weapon = WEAPON_PROFILE_STORAGE
+            .with(|service| service.borrow().get_record(&weapon_id))?
             .ok_or(Error::NotFound {
-                msg: format!("leaderboard with id={} not found", id),
+                msg: format!("reward weapon with id={} no longer exists", weapon_id),
+            })?;
+        if player_owns_weapon(player_id, weapon_id) {
+            currency = currency.checked_add(weapon.price).ok_or(Error::InvalidTransaction {
+                msg: format!("balance of player with id={} would overflow", player_id),
+            })?;
+            note = Some(format!("weapon with id={} was already owned, its price is paid out instead", weapon_id));
+        } else {
+            granted_weapon_id = Some(weapon_id);
+        }
+    }
+    if currency > 0 {
+        do_credit(player_id, kind, currency, note)?;
+    }
+    if let Some(weapon_id) = granted_weapon_id {
+        PLAYER_WEAPON_STORAGE.with(|service| service.borrow_mut().insert((player_id, weapon_id), ()));
+    }
+    Ok(())
+}
+
+// helper function to update the rating of every participant of a recorded match. Participants are
+// rated against every member of the other teams, matches with a single team are rated against a
+// reference opponent with the initial rating
+fn do_rate_match(match_profile: &Match) -> Result<(), Error> {
+    let config = get_rating_config()?;
+    //participants whose profiles were deleted are neither rated nor rated against, like in do_update_standing
+    let mut rated_participants: Vec<(&Participant, PlayerRating)> = Vec::new();
+    for participant in match_profile.participants.iter() {
+        if PLAYER_PROFILE_STORAGE.with(|service| service.borrow().contains_key(&participant.player_id)) {
+            rated_participants.push((participant, get_player_rating(participant.player_id)?));
+        }
+    }
+
+    for (participant, player_rating) in rated_participants.iter() {
+        let mut outcomes: Vec<rating::Outcome> = rated_participants
+            .iter()
+            .filter(|(opponent, _)| opponent.team != participant.team)
+            .map(|(opponent, opponent_rating)| rating::Outcome {
+                opponent_rating: opponent_rating.rating,
+                opponent_deviation: opponent_rating.deviation,
+                score: rating::pairwise_score(participant.outcome, opponent.outcome),
             })
-    })?;
+            .collect();
+        if outcomes.is_empty() {
+            outcomes.push(rating::Outcome {
+                opponent_rating: config.initial_rating,
+                opponent_deviation: config.initial_deviation,
+                score: rating::pairwise_score(participant.outcome, MatchOutcome::Draw),
+            });
+        }
+        let rated = rating::rate(&config, player_rating, &outcomes);
+
+        let change = RatingChange {
+            player_id: participant.player_id,
+            match_id: match_profile.id,
+            rating_before: player_rating.rating,
+            rating_after: rated.rating,
+            deviation_after: rated.deviation,
+            timestamp: env::time(),
+        };
+        RATING_STORAGE.with(|service| service.borrow_mut().insert_record(rated.player_id, &rated))?;
+        RATING_HISTORY_STORAGE.with(|service| {
+            service
+                .borrow_mut()
+                .insert_record((change.player_id, change.match_id), &change)
+        })?;
+    }
     Ok(())
 }
 
-// sort leaderboard by score in descending order
-#[ic_cdk::query]
-fn sort_leaderboard_by_score() -> Result<Vec<Leaderboard>, Error> {
-    let leaderboard_mapping: Vec<(u64, Leaderboard)> =
-        LEADERBOARD_STORAGE.with(|service| service.borrow().iter().collect());
-    let mut leaderboard: Vec<Leaderboard> = leaderboard_mapping
-        .into_iter()
-        .map(|(_, leaderboard)| leaderboard)
-        .collect();
+// helper function to clamp a requested page size to 1..=MAX_PAGE_SIZE
+fn page_size(limit: u64) -> usize {
+    limit.clamp(1, MAX_PAGE_SIZE) as usize
+}
 
-    if !leaderboard.is_empty() {
-        leaderboard.sort_by(|a, b| b.score.cmp(&a.score));
-        Ok(leaderboard)
+// helper function to keep a player's rank unless a moderator sets the requested one
+fn moderated_rank(requested_rank: u64, current_rank: u64) -> u64 {
+    if has_role(&env::caller(), Role::Moderator) {
+        requested_rank
     } else {
-        Err(Error::NotFound {
-            msg: "No leaderboards found ".to_string(),
+        current_rank
+    }
+}
+
+// Helper function to ensure the caller owns the player profile or is an admin
+fn is_player_owner_or_admin(player_profile: &PlayerProfile) -> Result<(), Error> {
+    let caller = env::caller();
+    if caller == player_profile.owner || has_role(&caller, Role::Admin) {
+        Ok(())
+    } else {
+        Err(Error::Unauthorized {
+            msg: format!("caller is not allowed to modify player with id={}", player_profile.id),
+        })
+    }
+}
+
+// Helper function to ensure the caller owns the player, for actions that need the owner's consent
+fn require_player_owner(player_profile: &PlayerProfile) -> Result<(), Error> {
+    if env::caller() == player_profile.owner {
+        Ok(())
+    } else {
+        Err(Error::Unauthorized {
+            msg: format!("only the owner of player with id={} can do this", player_profile.id),
         })
     }
 }
@@ -656,7 +5194,6 @@ fn sort_leaderboard_by_score() -> Result<Vec<Leaderboard>, Error> {
 fn is_valid_player_payload(player_profile_payload: &PlayerProfilePayload) -> Result<(), Error>{
     if player_profile_payload.name.trim().is_empty()
     || player_profile_payload.score == 0
-    || player_profile_payload.level == 0
     || player_profile_payload.rank == 0
 {
     return Err(Error::InvalidPlayerPayload {
@@ -692,28 +5229,120 @@ else{
 }
 // Helper function to ensure the input payload does not contain default values
 fn is_valid_match_payload(match_payload: &MatchProfilePayload) -> Result<(), Error>{
-    if match_payload.score == 0
-    || match_payload.level == 0
-    || match_payload.rank == 0
-    || match_payload.time == 0
-{
-    return Err(Error::InvalidMatchPayload {
-        msg: format!("Match cannot be initialized with default values"),
-        payload: match_payload.clone() 
-    });
+    let invalid = |msg: &str| Error::InvalidMatchPayload {
+        msg: msg.to_string(),
+        payload: match_payload.clone(),
+    };
+    if match_payload.participants.is_empty()
+        || match_payload.level == 0
+        || match_payload.rank == 0
+        || match_payload.time == 0
+    {
+        return Err(invalid("Match cannot be initialized with default values"));
+    }
+
+    let mut team_outcomes: Vec<(u64, MatchOutcome)> = Vec::new();
+    for (index, participant) in match_payload.participants.iter().enumerate() {
+        if match_payload.participants[..index]
+            .iter()
+            .any(|other| other.player_id == participant.player_id)
+        {
+            return Err(invalid(&format!(
+                "Player with id={} appears more than once in the match",
+                participant.player_id
+            )));
+        }
+        match team_outcomes.iter().find(|(team, _)| *team == participant.team) {
+            Some((_, outcome)) if *outcome != participant.outcome => {
+                return Err(invalid(&format!(
+                    "Participants of team {} have different outcomes",
+                    participant.team
+                )));
+            }
+            Some(_) => {}
+            None => team_outcomes.push((participant.team, participant.outcome)),
+        }
+
+        if !PLAYER_PROFILE_STORAGE.with(|service| service.borrow().contains_key(&participant.player_id)) {
+            return Err(Error::NotFound {
+                msg: format!("Player with id={} does not exist.", participant.player_id),
+            });
+        }
+        if !WEAPON_PROFILE_STORAGE.with(|service| service.borrow().contains_key(&participant.weapon_id)) {
+            return Err(Error::NotFound {
+                msg: format!("Weapon with id={} does not exist.", participant.weapon_id),
+            });
+        }
+    }
+    Ok(())
 }
-let is_valid_player_id = PLAYER_PROFILE_STORAGE.with(|service| {
-    service
-        .borrow()
-        .contains_key(&match_payload.player_id)
-});
-if !is_valid_player_id {
-    return Err(Error::NotFound { msg: format!("Player with id={} does not exist.", match_payload.player_id) })
+
+// helper function to check that corrected results keep everything the match already awarded: the
+// match time and each participant's team, weapon, score, kills, assists, damage and outcome
+fn is_award_preserving_correction(disputed_match: &Match, match_payload: &MatchProfilePayload) -> Result<(), Error> {
+    let awarded = |participants: &[Participant]| {
+        let mut awarded: Vec<_> = participants
+            .iter()
+            .map(|participant| {
+                (
+                    participant.player_id,
+                    participant.team,
+                    participant.weapon_id,
+                    participant.score,
+                    participant.kills,
+                    participant.assists,
+                    participant.damage,
+                    participant.outcome,
+                )
+            })
+            .collect();
+        awarded.sort();
+        awarded
+    };
+    if disputed_match.time != match_payload.time
+        || awarded(&disputed_match.participants) != awarded(&match_payload.participants)
+    {
+        return Err(Error::InvalidMatchPayload {
+            msg: format!(
+                "corrected results would change the awards of match with id={}, only deaths, level and rank can be corrected",
+                disputed_match.id
+            ),
+            payload: match_payload.clone(),
+        });
+    }
+    Ok(())
 }
-else{
-Ok(())
+
+// Helper function to ensure a dispute reason or resolution note fits in the match record
+fn is_valid_dispute_note(note: &str) -> Result<(), Error> {
+    if note.len() > MAX_DISPUTE_NOTE_LEN {
+        return Err(Error::RequirementNotMet {
+            msg: format!("dispute notes cannot be longer than {} bytes", MAX_DISPUTE_NOTE_LEN),
+        });
+    }
+    Ok(())
 }
+
+// Helper function to ensure every participant uses a weapon of their active loadout
+fn is_valid_match_loadouts(match_payload: &MatchProfilePayload) -> Result<(), Error> {
+    for participant in match_payload.participants.iter() {
+        let loadout_id = active_loadout_id(participant.player_id).ok_or(Error::NotFound {
+            msg: format!("player with id={} has no active loadout", participant.player_id),
+        })?;
+        let loadout = get_loadout(loadout_id)?;
+        if !loadout.weapon_ids().any(|weapon_id| weapon_id == participant.weapon_id) {
+            return Err(Error::InvalidMatchPayload {
+                msg: format!(
+                    "Weapon with id={} is not in the active loadout of player with id={}",
+                    participant.weapon_id, participant.player_id
+                ),
+                payload: match_payload.clone(),
+            });
+        }
+    }
+    Ok(())
 }
+
 // Helper function to ensure the input payload does not contain default values
 fn is_valid_leaderboard_payload(leaderboard_payload: &LeaderboardPayload) -> Result<(), Error>{
     if  leaderboard_payload.score == 0
@@ -739,5 +5368,809 @@ Ok(())
 }
 
 
+// Helper function to ensure a trade offer is between two players, exchanges something and expires
+fn is_valid_trade_offer_payload(trade_payload: &TradeOfferPayload) -> Result<(), Error> {
+    let invalid = |msg: &str| Error::InvalidTradeOfferPayload {
+        msg: msg.to_string(),
+        payload: trade_payload.clone(),
+    };
+    if trade_payload.from_player_id == trade_payload.to_player_id {
+        return Err(invalid("players cannot trade with themselves"));
+    }
+    if trade_payload.offered_weapon_ids.is_empty()
+        && trade_payload.offered_currency == 0
+        && trade_payload.requested_weapon_ids.is_empty()
+        && trade_payload.requested_currency == 0
+    {
+        return Err(invalid("trade offer must exchange at least one weapon or some currency"));
+    }
+    if trade_payload.expires_in_seconds == 0 || trade_payload.expires_in_seconds > MAX_TRADE_OFFER_SECONDS {
+        return Err(invalid("trade offer must expire within 7 days"));
+    }
+    for weapon_ids in [&trade_payload.offered_weapon_ids, &trade_payload.requested_weapon_ids] {
+        let unique: BTreeSet<&u64> = weapon_ids.iter().collect();
+        if unique.len() != weapon_ids.len() {
+            return Err(invalid("trade offer lists a weapon more than once"));
+        }
+    }
+    Ok(())
+}
+
+// Helper function to ensure an achievement has a name and can be reached
+fn is_valid_achievement_payload(achievement_payload: &AchievementPayload) -> Result<(), Error> {
+    if achievement_payload.name.trim().is_empty() || achievement_payload.threshold == 0 {
+        return Err(Error::InvalidAchievementPayload {
+            msg: "achievement needs a name and a positive threshold".to_string(),
+            payload: Box::new(achievement_payload.clone()),
+        });
+    }
+    Ok(())
+}
+
+// Helper function to ensure a quest has a name, can be completed and rewards something that exists
+fn is_valid_quest_template_payload(quest_payload: &QuestTemplatePayload) -> Result<(), Error> {
+    if quest_payload.name.trim().is_empty()
+        || quest_payload.target == 0
+        || (quest_payload.reward.currency == 0 && quest_payload.reward.weapon_id.is_none())
+    {
+        return Err(Error::InvalidQuestTemplatePayload {
+            msg: "quest needs a name, a positive target and a reward".to_string(),
+            payload: Box::new(quest_payload.clone()),
+        });
+    }
+    if let Some(weapon_id) = quest_payload.reward.weapon_id {
+        get_weapon(weapon_id)?;
+    }
+    Ok(())
+}
+
+// Helper function to ensure a season has a name, ends in the future and overlaps no other season
+fn is_valid_season_payload(season_payload: &SeasonPayload) -> Result<(), Error> {
+    let invalid = |msg: &str| Error::InvalidSeasonPayload {
+        msg: msg.to_string(),
+        payload: season_payload.clone(),
+    };
+    if season_payload.name.trim().is_empty() {
+        return Err(invalid("season needs a name"));
+    }
+    if season_payload.starts_at >= season_payload.ends_at || season_payload.ends_at <= env::time() {
+        return Err(invalid("season must end after it starts and in the future"));
+    }
+    let seasons: Vec<Season> = SEASON_STORAGE.with(|service| service.borrow().records())?;
+    let overlaps = seasons.iter().any(|season| {
+        let ends_at = season.ended_at.unwrap_or(season.ends_at);
+        season_payload.starts_at < ends_at && season.starts_at < season_payload.ends_at
+    });
+    if overlaps {
+        return Err(invalid("season overlaps another season"));
+    }
+    Ok(())
+}
+
+// Helper function to ensure a battle pass has tiers ordered by the XP they need, each with a reward
+fn is_valid_battle_pass_payload(battle_pass_payload: &BattlePassPayload) -> Result<(), Error> {
+    let invalid = |msg: &str| Error::InvalidBattlePassPayload {
+        msg: msg.to_string(),
+        payload: Box::new(battle_pass_payload.clone()),
+    };
+    let tiers = &battle_pass_payload.tiers;
+    if tiers.is_empty() || tiers.len() > MAX_BATTLE_PASS_TIERS {
+        return Err(invalid(&format!("battle pass needs 1 to {} tiers", MAX_BATTLE_PASS_TIERS)));
+    }
+    if tiers.windows(2).any(|pair| pair[0].xp_required >= pair[1].xp_required) {
+        return Err(invalid("tiers must need strictly increasing XP"));
+    }
+    for tier in tiers.iter() {
+        let rewards = [&tier.free_reward, &tier.premium_reward];
+        if rewards.iter().all(|reward| reward.is_none()) {
+            return Err(invalid("every tier needs a free or premium reward"));
+        }
+        for reward in rewards.into_iter().flatten() {
+            if reward.currency == 0 && reward.weapon_id.is_none() {
+                return Err(invalid("rewards need currency or a weapon"));
+            }
+            if let Some(weapon_id) = reward.weapon_id {
+                get_weapon(weapon_id)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Helper function to ensure a loadout has a name and its slots hold distinct weapons the player owns
+fn is_valid_loadout_payload(loadout_payload: &LoadoutPayload) -> Result<(), Error> {
+    let invalid = |msg: String| Error::InvalidLoadoutPayload {
+        msg,
+        payload: loadout_payload.clone(),
+    };
+    if loadout_payload.name.trim().is_empty() {
+        return Err(invalid("loadout needs a name".to_string()));
+    }
+    let weapon_ids: Vec<u64> = [loadout_payload.primary, loadout_payload.secondary, loadout_payload.melee]
+        .into_iter()
+        .flatten()
+        .collect();
+    for (index, weapon_id) in weapon_ids.iter().enumerate() {
+        if weapon_ids[..index].contains(weapon_id) {
+            return Err(invalid(format!("weapon with id={} is in more than one slot", weapon_id)));
+        }
+        if !player_owns_weapon(loadout_payload.player_id, *weapon_id) {
+            return Err(invalid(format!(
+                "player with id={} does not own weapon with id={}",
+                loadout_payload.player_id, weapon_id
+            )));
+        }
+    }
+    Ok(())
+}
+
+// Helper function to ensure an attachment has a name and modifiers that change its weapon
+fn is_valid_attachment_payload(attachment_payload: &AttachmentPayload) -> Result<(), Error> {
+    if attachment_payload.name.trim().is_empty() {
+        return Err(Error::InvalidAttachmentPayload {
+            msg: "attachment needs a name".to_string(),
+            payload: attachment_payload.clone(),
+        });
+    }
+    stats::validate_modifiers(&attachment_payload.modifiers).map_err(|msg| {
+        Error::InvalidAttachmentPayload {
+            msg,
+            payload: attachment_payload.clone(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Nat;
+    use std::future::Future;
+    use std::task::{Context, Poll, Waker};
+
+    //ledger stand-in that answers at once, running a hook in place of the messages that could
+    //interleave while a real ledger call is awaited. Like the ICRC ledger it executes a transfer sent
+    //again with the same created_at_time and memo once, and answers the repeat with the first block
+    struct TestLedger {
+        transfer_from: Result<u64, icrc::LedgerError>,
+        during_transfer: RefCell<Option<Box<dyn FnOnce()>>>,
+        fee: u64,
+        payments: RefCell<BTreeMap<(u64, Vec<u8>), u64>>,
+        refunds: RefCell<Vec<(Principal, u64, u64)>>,
+        refund_blocks: RefCell<BTreeMap<(u64, Vec<u8>), u64>>,
+        //transfers still to be executed without their answer reaching the caller
+        lost_answers: std::cell::Cell<u32>,
+    }
+
+    impl TestLedger {
+        fn accepting() -> Self {
+            TestLedger {
+                transfer_from: Ok(17),
+                during_transfer: RefCell::new(None),
+                fee: 10,
+                payments: RefCell::new(BTreeMap::new()),
+                refunds: RefCell::new(Vec::new()),
+                refund_blocks: RefCell::new(BTreeMap::new()),
+                lost_answers: std::cell::Cell::new(0),
+            }
+        }
+
+        fn during_transfer(self, hook: impl FnOnce() + 'static) -> Self {
+            *self.during_transfer.borrow_mut() = Some(Box::new(hook));
+            self
+        }
+
+        fn answer(&self, block_index: u64) -> Result<Nat, icrc::LedgerError> {
+            if self.lost_answers.get() > 0 {
+                self.lost_answers.set(self.lost_answers.get() - 1);
+                return Err(icrc::LedgerError::Unknown("call rejected after execution".to_string()));
+            }
+            Ok(Nat::from(block_index))
+        }
+    }
+
+    impl icrc::Ledger for TestLedger {
+        fn id(&self) -> Principal {
+            Principal::from_slice(&[9])
+        }
+
+        async fn transfer_from(
+            &self,
+            _from: icrc::Account,
+            _to: icrc::Account,
+            _amount: u64,
+            created_at_time: u64,
+            memo: Vec<u8>,
+        ) -> Result<Nat, icrc::LedgerError> {
+            if let Some(hook) = self.during_transfer.borrow_mut().take() {
+                hook();
+            }
+            if let Some(block_index) = self.payments.borrow().get(&(created_at_time, memo.clone())) {
+                return Ok(Nat::from(*block_index));
+            }
+            let block_index = self.transfer_from.clone()?;
+            self.payments.borrow_mut().insert((created_at_time, memo), block_index);
+            self.answer(block_index)
+        }
+
+        async fn fee(&self) -> Result<u64, String> {
+            Ok(self.fee)
+        }
+
+        async fn transfer(
+            &self,
+            to: icrc::Account,
+            amount: u64,
+            fee: u64,
+            created_at_time: u64,
+            memo: Vec<u8>,
+        ) -> Result<Nat, icrc::LedgerError> {
+            if let Some(block_index) = self.refund_blocks.borrow().get(&(created_at_time, memo.clone())) {
+                return Ok(Nat::from(*block_index));
+            }
+            let block_index = 18 + self.refunds.borrow().len() as u64;
+            self.refunds.borrow_mut().push((to.owner, amount, fee));
+            self.refund_blocks.borrow_mut().insert((created_at_time, memo), block_index);
+            self.answer(block_index)
+        }
+    }
+
+    // runs a future whose ledger calls complete immediately
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let mut context = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+        }
+    }
+
+    fn setup() -> (PlayerProfile, Weapon) {
+        let player = PlayerProfile {
+            name: "Ghost".to_string(),
+            id: 1,
+            score: 10,
+            level: 5,
+            rank: 5,
+            owner: Principal::from_slice(&[1]),
+        };
+        let weapon = Weapon {
+            name: "Rifle".to_string(),
+            id: 2,
+            damage: 30,
+            ammo: 30,
+            range: 400,
+            fire_rate: 10,
+            reload_time: 2,
+            accuracy: 80,
+            price: 500,
+            level: 1,
+            rank: 1,
+        };
+        do_insert_player(&player).unwrap();
+        do_insert_weapon(&weapon).unwrap();
+        (player, weapon)
+    }
+
+    fn canister_account() -> icrc::Account {
+        icrc::Account {
+            owner: Principal::from_slice(&[8]),
+            subaccount: None,
+        }
+    }
+
+    fn buy(ledger: &TestLedger, player: &PlayerProfile, weapon: &Weapon) -> Result<TokenPurchase, Error> {
+        block_on(do_purchase_weapon_with_tokens(ledger, player, weapon, 1_000, canister_account()))
+    }
+
+    #[test]
+    fn token_purchase_grants_the_weapon() {
+        let (player, weapon) = setup();
+        env::set_time(42);
+        let purchase = buy(&TestLedger::accepting(), &player, &weapon).unwrap();
+        assert_eq!(purchase.status, TokenPurchaseStatus::Completed);
+        assert_eq!(purchase.block_index, Some(Nat::from(17u64)));
+        assert_eq!((purchase.amount, purchase.timestamp), (1_000, 42));
+        assert!(player_owns_weapon(player.id, weapon.id));
+        assert_eq!(get_player_token_purchases(player.id).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn rejected_transfer_records_nothing() {
+        let (player, weapon) = setup();
+        let ledger = TestLedger {
+            transfer_from: Err(icrc::LedgerError::Rejected("insufficient allowance".to_string())),
+            ..TestLedger::accepting()
+        };
+        assert!(matches!(buy(&ledger, &player, &weapon), Err(Error::PaymentFailed { .. })));
+        assert!(!player_owns_weapon(player.id, weapon.id));
+        assert!(get_player_token_purchases(player.id).unwrap().is_empty());
+        assert!(PENDING_TOKEN_PURCHASES.with(|pending| pending.borrow().is_empty()));
+    }
+
+    #[test]
+    fn soft_currency_purchase_is_blocked_while_paying() {
+        let (player, weapon) = setup();
+        let (blocked_player, blocked_weapon) = (player.clone(), weapon.clone());
+        let ledger = TestLedger::accepting().during_transfer(move || {
+            assert!(is_valid_purchase(&blocked_player, &blocked_weapon).is_err());
+        });
+        let purchase = buy(&ledger, &player, &weapon).unwrap();
+        assert_eq!(purchase.status, TokenPurchaseStatus::Completed);
+        assert!(is_valid_purchase(&player, &weapon).is_err());
+    }
+
+    #[test]
+    fn weapon_received_while_paying_is_refundable() {
+        let (player, weapon) = setup();
+        let ledger = TestLedger::accepting().during_transfer(|| {
+            PLAYER_WEAPON_STORAGE.with(|service| service.borrow_mut().insert((1, 2), ()));
+        });
+        let purchase = buy(&ledger, &player, &weapon).unwrap();
+        assert!(matches!(purchase.status, TokenPurchaseStatus::Refundable { .. }));
+
+        let refunded = block_on(do_refund_token_purchase(&ledger, purchase)).unwrap();
+        assert_eq!(
+            refunded.status,
+            TokenPurchaseStatus::Refunded {
+                amount: 990,
+                block_index: Nat::from(18u64)
+            }
+        );
+        assert_eq!(*ledger.refunds.borrow(), vec![(player.owner, 990, 10)]);
+        assert_eq!(get_token_purchase(refunded.id).unwrap().status, refunded.status);
+        assert!(block_on(do_refund_token_purchase(&ledger, refunded)).is_err());
+    }
+
+    #[test]
+    fn deletions_while_paying_are_refundable() {
+        let (player, weapon) = setup();
+        let ledger = TestLedger::accepting().during_transfer(|| {
+            WEAPON_PROFILE_STORAGE.with(|service| service.borrow_mut().remove(&2));
+        });
+        let purchase = buy(&ledger, &player, &weapon).unwrap();
+        assert!(matches!(purchase.status, TokenPurchaseStatus::Refundable { .. }));
+        assert!(!player_owns_weapon(player.id, weapon.id));
+
+        do_insert_weapon(&weapon).unwrap();
+        let ledger = TestLedger::accepting().during_transfer(|| {
+            PLAYER_PROFILE_STORAGE.with(|service| service.borrow_mut().remove(&1));
+        });
+        let purchase = buy(&ledger, &player, &weapon).unwrap();
+        assert!(matches!(purchase.status, TokenPurchaseStatus::Refundable { .. }));
+        assert!(PLAYER_TOKEN_PURCHASE_STORAGE.with(|service| !service.borrow().contains_key(&(1, purchase.id))));
+    }
+
+    #[test]
+    fn unanswered_payment_is_charged_once() {
+        let (player, weapon) = setup();
+        let ledger = TestLedger::accepting();
+        ledger.lost_answers.set(1);
+        assert!(matches!(buy(&ledger, &player, &weapon), Err(Error::PaymentFailed { .. })));
+        let pending = get_player_token_purchases(player.id).unwrap().remove(0);
+        assert_eq!(pending.status, TokenPurchaseStatus::Pending);
+        assert!(!player_owns_weapon(player.id, weapon.id));
+
+        env::set_time(99);
+        let purchase = block_on(send_token_purchase(&ledger, pending, canister_account())).unwrap();
+        assert_eq!(purchase.status, TokenPurchaseStatus::Completed);
+        assert_eq!(purchase.block_index, Some(Nat::from(17u64)));
+        assert_eq!(ledger.payments.borrow().len(), 1);
+        assert!(player_owns_weapon(player.id, weapon.id));
+    }
+
+    #[test]
+    fn refund_rejected_after_execution_is_sent_once() {
+        let (player, weapon) = setup();
+        let ledger = TestLedger::accepting().during_transfer(|| {
+            PLAYER_WEAPON_STORAGE.with(|service| service.borrow_mut().insert((1, 2), ()));
+        });
+        let purchase = buy(&ledger, &player, &weapon).unwrap();
+        ledger.lost_answers.set(1);
+        assert!(matches!(
+            block_on(do_refund_token_purchase(&ledger, purchase)),
+            Err(Error::PaymentFailed { .. })
+        ));
+        let purchase = get_player_token_purchases(player.id).unwrap().remove(0);
+        assert!(matches!(purchase.status, TokenPurchaseStatus::Refundable { .. }));
+        assert!(purchase.refund.is_some());
+
+        env::set_time(99);
+        let refunded = block_on(do_refund_token_purchase(&ledger, purchase)).unwrap();
+        assert_eq!(
+            refunded.status,
+            TokenPurchaseStatus::Refunded {
+                amount: 990,
+                block_index: Nat::from(18u64)
+            }
+        );
+        assert_eq!(*ledger.refunds.borrow(), vec![(player.owner, 990, 10)]);
+    }
+
+    #[test]
+    fn completed_purchase_cannot_be_refunded() {
+        let (player, weapon) = setup();
+        let ledger = TestLedger::accepting();
+        let purchase = buy(&ledger, &player, &weapon).unwrap();
+        assert!(block_on(do_refund_token_purchase(&ledger, purchase)).is_err());
+        assert!(ledger.refunds.borrow().is_empty());
+    }
+
+    fn grant(principal: Principal, role: Role) {
+        do_insert_roles(&RoleAssignment {
+            principal,
+            roles: vec![role],
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn only_owners_and_admins_modify_profiles() {
+        let (player, _) = setup();
+        let (moderator, admin) = (Principal::from_slice(&[6]), Principal::from_slice(&[7]));
+        grant(moderator, Role::Moderator);
+        grant(admin, Role::Admin);
+        env::set_caller(moderator);
+        assert!(matches!(delete_player_profile(player.id), Err(Error::Unauthorized { .. })));
+        env::set_caller(player.owner);
+        assert!(is_player_owner_or_admin(&player).is_ok());
+        env::set_caller(admin);
+        assert!(is_player_owner_or_admin(&player).is_ok());
+    }
+
+    #[test]
+    fn only_owners_spend_a_player_balance() {
+        let (player, weapon) = setup();
+        let admin = Principal::from_slice(&[7]);
+        grant(admin, Role::Admin);
+        env::set_caller(admin);
+        assert!(matches!(purchase_weapon(player.id, weapon.id), Err(Error::Unauthorized { .. })));
+        env::set_caller(player.owner);
+        assert!(matches!(purchase_weapon(player.id, weapon.id), Err(Error::InsufficientFunds { .. })));
+    }
+
+    #[test]
+    fn only_owners_buy_and_claim_battle_passes() {
+        let (player, _) = setup();
+        let admin = Principal::from_slice(&[7]);
+        grant(admin, Role::Admin);
+        env::set_caller(admin);
+        assert!(matches!(purchase_premium_pass(0, player.id), Err(Error::Unauthorized { .. })));
+        assert!(matches!(
+            claim_battle_pass_reward(0, player.id, 1, BattlePassTrack::Free),
+            Err(Error::Unauthorized { .. })
+        ));
+        env::set_caller(player.owner);
+        assert!(matches!(purchase_premium_pass(0, player.id), Err(Error::NotFound { .. })));
+    }
+
+    #[test]
+    fn owned_reward_weapons_are_paid_out() {
+        let (player, weapon) = setup();
+        let reward = Reward {
+            currency: 100,
+            weapon_id: Some(weapon.id),
+        };
+        let kind = TransactionKind::QuestReward { quest_id: 0 };
+        do_grant_reward(player.id, &reward, kind.clone()).unwrap();
+        assert!(player_owns_weapon(player.id, weapon.id));
+        assert_eq!(player_balance(player.id), 100);
+
+        do_grant_reward(player.id, &reward, kind).unwrap();
+        assert_eq!(player_balance(player.id), 100 + 100 + weapon.price);
+        let transactions = get_player_transactions(player.id).unwrap();
+        assert_eq!(transactions.len(), 2);
+        assert!(transactions[1].note.is_some());
+    }
+
+    #[test]
+    fn weapon_queries_filter_sort_and_page() {
+        let (_, rifle) = setup();
+        WEAPON_PROFILE_STORAGE.with(|service| service.borrow_mut().remove(&rifle.id));
+        let prices = [300, 100, 200, 100, 300, 100, 200, 400];
+        let damages = [20, 40, 40, 40, 10, 50, 50, 60];
+        for (id, (price, damage)) in prices.into_iter().zip(damages).enumerate() {
+            do_insert_weapon(&Weapon {
+                id: id as u64,
+                price,
+                damage,
+                ..rifle.clone()
+            })
+            .unwrap();
+        }
+        let ids = |page: &Page<u64, Weapon>| page.items.iter().map(|weapon| weapon.id).collect::<Vec<u64>>();
+
+        let filter = WeaponFilter {
+            min_damage: Some(40),
+            max_price: Some(200),
+            ..WeaponFilter::default()
+        };
+        assert_eq!(ids(&query_weapons(filter, None, None, 10).unwrap()), vec![1, 2, 3, 5, 6]);
+
+        let filter = WeaponFilter {
+            min_price: Some(100),
+            max_price: Some(300),
+            ..WeaponFilter::default()
+        };
+        let sort = WeaponSort {
+            primary: WeaponSortKey {
+                field: WeaponSortField::Price,
+                direction: SortDirection::Ascending,
+            },
+            secondary: Some(WeaponSortKey {
+                field: WeaponSortField::Damage,
+                direction: SortDirection::Descending,
+            }),
+        };
+        let mut paged = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = query_weapons(filter.clone(), Some(sort.clone()), cursor, 2).unwrap();
+            assert!(page.items.len() <= 2);
+            paged.extend(ids(&page));
+            cursor = page.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        //weapons 1 and 3 tie on both keys and stay in id order across the page boundary
+        assert_eq!(paged, vec![5, 1, 3, 6, 2, 0, 4]);
+    }
+
+    #[test]
+    fn weapon_outliers_need_a_spread() {
+        let (_, rifle) = setup();
+        let record = |weapon_id: u64, picks: u64, wins: u64| {
+            do_insert_weapon(&Weapon {
+                id: weapon_id,
+                ..rifle.clone()
+            })
+            .unwrap();
+            let telemetry = WeaponTelemetry {
+                weapon_id,
+                picks,
+                wins,
+                total_score: 10 * picks,
+                total_match_time: 5 * picks,
+            };
+            WEAPON_TELEMETRY_STORAGE.with(|service| service.borrow_mut().insert_record(weapon_id, &telemetry)).unwrap();
+        };
+        record(2, 20, 10);
+        assert!(get_weapon_outliers(None).unwrap().is_empty());
+        for weapon_id in 3..7 {
+            record(weapon_id, 20, 10);
+        }
+        assert!(get_weapon_outliers(None).unwrap().is_empty());
+
+        record(7, 20, 20);
+        record(8, 1, 0);
+        let outliers = get_weapon_outliers(None).unwrap();
+        assert_eq!(outliers.len(), 1);
+        assert_eq!((outliers[0].weapon_id, outliers[0].metric), (7, BalanceMetric::WinRate));
+        assert!((outliers[0].z_score - 5f64.sqrt()).abs() < 1e-9);
+    }
+
+    fn weapon_payload(damage: u64) -> WeaponProfilePayload {
+        WeaponProfilePayload {
+            name: "Carbine".to_string(),
+            damage,
+            ammo: 30,
+            range: 300,
+            fire_rate: 8,
+            reload_time: 2,
+            accuracy: 70,
+            price: 400,
+            level: 1,
+            rank: 1,
+        }
+    }
+
+    // designer session with two weapons changed by one balance patch
+    fn patched_weapons() -> (Weapon, Weapon, BalancePatch) {
+        let designer = Principal::from_slice(&[5]);
+        grant(designer, Role::Designer);
+        env::set_caller(designer);
+        let first = create_weapon(weapon_payload(30)).unwrap();
+        let second = create_weapon(weapon_payload(30)).unwrap();
+        let patch = create_balance_patch(BalancePatchPayload {
+            name: "buff".to_string(),
+            notes: String::new(),
+        })
+        .unwrap();
+        update_weapon_profile(first.id, weapon_payload(40), None, Some(patch.id)).unwrap();
+        update_weapon_profile(second.id, weapon_payload(40), None, Some(patch.id)).unwrap();
+        (first, second, patch)
+    }
+
+    #[test]
+    fn rollback_refuses_weapons_revised_after_the_patch() {
+        let (first, second, patch) = patched_weapons();
+        update_weapon_profile(first.id, weapon_payload(45), None, None).unwrap();
+        assert!(matches!(rollback_balance_patch(patch.id, None), Err(Error::RequirementNotMet { .. })));
+        assert_eq!(get_weapon(first.id).unwrap().damage, 45);
+        assert_eq!(get_weapon(second.id).unwrap().damage, 40);
+        assert!(get_balance_patch(patch.id).unwrap().rolled_back_at.is_none());
+    }
+
+    #[test]
+    fn rollback_skips_weapons_deleted_after_the_patch() {
+        let (first, second, patch) = patched_weapons();
+        delete_weapon(second.id).unwrap();
+        let restored = rollback_balance_patch(patch.id, None).unwrap();
+        assert_eq!(restored.len(), 1);
+        assert_eq!((restored[0].id, restored[0].damage), (first.id, 30));
+        assert_eq!(get_weapon(first.id).unwrap().damage, 30);
+        assert!(get_balance_patch(patch.id).unwrap().rolled_back_at.is_some());
+        assert_eq!(get_weapon_revisions(second.id).unwrap().len(), 2);
+        assert_eq!(get_balance_patch_changes(patch.id).unwrap().len(), 2);
+    }
+
+    // a listing of the weapon of player 1 and two funded bidders, players 3 and 4
+    fn listed_weapon() -> (PlayerProfile, Weapon, Listing) {
+        let (seller, weapon) = setup();
+        PLAYER_WEAPON_STORAGE.with(|service| service.borrow_mut().insert((seller.id, weapon.id), ()));
+        for id in [3u8, 4] {
+            do_insert_player(&PlayerProfile {
+                id: id as u64,
+                owner: Principal::from_slice(&[id]),
+                ..seller.clone()
+            })
+            .unwrap();
+            do_credit(id as u64, TransactionKind::Grant, 1_000, None).unwrap();
+        }
+        env::set_caller(seller.owner);
+        let listing = create_listing(ListingPayload {
+            seller_player_id: seller.id,
+            weapon_id: weapon.id,
+            price: 1_000,
+        })
+        .unwrap();
+        (seller, weapon, listing)
+    }
+
+    #[test]
+    fn accepted_bids_pay_the_seller_less_the_fee() {
+        let (seller, weapon, listing) = listed_weapon();
+        env::set_caller(Principal::from_slice(&[3]));
+        place_bid(listing.id, 3, 400).unwrap();
+        assert_eq!(player_balance(3), 600);
+        env::set_caller(Principal::from_slice(&[4]));
+        assert!(place_bid(listing.id, 4, 400).is_err());
+        place_bid(listing.id, 4, 500).unwrap();
+        assert_eq!((player_balance(3), player_balance(4)), (1_000, 500));
+
+        env::set_caller(seller.owner);
+        let sold = accept_bid(listing.id).unwrap();
+        assert_eq!(sold.status, ListingStatus::Sold);
+        assert_eq!((sold.buyer_player_id, sold.sale_price, sold.fee), (Some(4), Some(500), Some(25)));
+        assert_eq!((player_balance(seller.id), player_balance(4)), (475, 500));
+        assert_eq!(get_market_fees_collected(), 25);
+        assert!(player_owns_weapon(4, weapon.id));
+        assert!(!player_owns_weapon(seller.id, weapon.id));
+        assert!(active_listings().unwrap().is_empty());
+    }
+
+    #[test]
+    fn deleted_weapons_cancel_their_listings_and_refund_bids() {
+        let (_, weapon, listing) = listed_weapon();
+        env::set_caller(Principal::from_slice(&[3]));
+        place_bid(listing.id, 3, 400).unwrap();
+
+        let designer = Principal::from_slice(&[5]);
+        grant(designer, Role::Designer);
+        env::set_caller(designer);
+        delete_weapon(weapon.id).unwrap();
+        assert_eq!(get_listing(listing.id).unwrap().status, ListingStatus::Cancelled);
+        assert_eq!(player_balance(3), 1_000);
+        assert!(active_listings().unwrap().is_empty());
+        assert!(LISTED_WEAPON_STORAGE.with(|service| service.borrow().is_empty()));
+    }
+
+    #[test]
+    fn dispute_corrections_cannot_change_awards() {
+        let (player, weapon) = setup();
+        PLAYER_WEAPON_STORAGE.with(|service| service.borrow_mut().insert((player.id, weapon.id), ()));
+        do_create_loadout(Loadout {
+            id: 0,
+            player_id: player.id,
+            name: DEFAULT_LOADOUT_NAME.to_string(),
+            primary: Some(weapon.id),
+            secondary: None,
+            melee: None,
+        })
+        .unwrap();
+        let win = Participant {
+            player_id: player.id,
+            team: 1,
+            weapon_id: weapon.id,
+            score: 40,
+            kills: 4,
+            deaths: 2,
+            assists: 1,
+            damage: 300,
+            outcome: MatchOutcome::Win,
+        };
+        let payload = |participant: Participant| MatchProfilePayload {
+            participants: vec![participant],
+            level: 1,
+            rank: 1,
+            time: 10,
+        };
+        let match_profile = do_create_match(payload(win.clone()), MatchStatus::Completed).unwrap();
+        do_record_completed_match(match_profile).unwrap();
+        let xp = player_xp(player.id);
+        let moderator = Principal::from_slice(&[7]);
+        grant(moderator, Role::Moderator);
+        env::set_caller(player.owner);
+        let disputed = dispute_match(0, "we lost".to_string()).unwrap();
+
+        env::set_caller(moderator);
+        let loss = Participant {
+            outcome: MatchOutcome::Loss,
+            ..win.clone()
+        };
+        assert!(matches!(
+            resolve_match_dispute(disputed.id, Some(payload(loss)), "corrected".to_string()),
+            Err(Error::InvalidMatchPayload { .. })
+        ));
+        let unchanged = get_match(disputed.id).unwrap();
+        assert_eq!(unchanged.status, MatchStatus::Disputed);
+        assert_eq!(unchanged.participants[0].outcome, MatchOutcome::Win);
+        assert_eq!(player_xp(player.id), xp);
+
+        let deaths = Participant { deaths: 3, ..win };
+        let resolved = resolve_match_dispute(disputed.id, Some(payload(deaths)), "corrected".to_string()).unwrap();
+        assert_eq!(resolved.status, MatchStatus::Completed);
+        assert_eq!(resolved.participants[0].deaths, 3);
+        assert_eq!(player_xp(player.id), xp);
+    }
+
+    #[test]
+    fn trades_check_the_requirements_of_received_weapons() {
+        let (player, weapon) = setup();
+        let rookie = PlayerProfile {
+            id: 3,
+            level: 1,
+            rank: 1,
+            owner: Principal::from_slice(&[3]),
+            ..player.clone()
+        };
+        let elite = Weapon {
+            id: 4,
+            level: 10,
+            ..weapon.clone()
+        };
+        do_insert_player(&rookie).unwrap();
+        do_insert_weapon(&elite).unwrap();
+        PLAYER_WEAPON_STORAGE.with(|service| {
+            let mut inventory = service.borrow_mut();
+            inventory.insert((player.id, weapon.id), ());
+            inventory.insert((player.id, elite.id), ());
+        });
+        let offer = |offered_weapon_ids: Vec<u64>, requested_weapon_ids: Vec<u64>| TradeOfferPayload {
+            from_player_id: rookie.id,
+            to_player_id: player.id,
+            offered_weapon_ids,
+            offered_currency: 0,
+            requested_weapon_ids,
+            requested_currency: 0,
+            expires_in_seconds: 60,
+        };
+
+        env::set_caller(rookie.owner);
+        assert!(matches!(
+            create_trade_offer(offer(vec![], vec![elite.id])),
+            Err(Error::RequirementNotMet { .. })
+        ));
+        let trade_offer = create_trade_offer(offer(vec![], vec![weapon.id])).unwrap();
+
+        do_insert_weapon(&Weapon { level: 3, ..weapon.clone() }).unwrap();
+        env::set_caller(player.owner);
+        assert!(matches!(accept_trade_offer(trade_offer.id), Err(Error::RequirementNotMet { .. })));
+        assert!(player_owns_weapon(player.id, weapon.id));
+        assert_eq!(get_trade_offer(trade_offer.id).unwrap().status, TradeStatus::Open);
+    }
+}
+
 // Export the candid interface
 ic_cdk::export_candid!();
\ No newline at end of file
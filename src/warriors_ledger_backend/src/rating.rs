@@ -0,0 +1,241 @@
+use crate::types::{MatchOutcome, PlayerRating, RatingAlgorithm, RatingConfig};
+use std::cmp::Ordering;
+use std::f64::consts::PI;
+
+//Glicko-2 works on a scale centred on 1500 with this many rating points per unit
+const GLICKO2_SCALE: f64 = 173.7178;
+//convergence tolerance of the Glicko-2 volatility iteration
+const GLICKO2_EPSILON: f64 = 0.000001;
+
+//result of a match against one opponent, score is 1.0 for a win, 0.5 for a draw and 0.0 for a loss
+pub struct Outcome {
+    pub opponent_rating: f64,
+    pub opponent_deviation: f64,
+    pub score: f64,
+}
+
+// score of a participant against one opponent, based on how their outcomes compare
+pub fn pairwise_score(outcome: MatchOutcome, opponent_outcome: MatchOutcome) -> f64 {
+    match outcome.cmp(&opponent_outcome) {
+        Ordering::Greater => 1.0,
+        Ordering::Equal => 0.5,
+        Ordering::Less => 0.0,
+    }
+}
+
+// rating of a player that has not been rated yet
+pub fn initial_rating(config: &RatingConfig, player_id: u64) -> PlayerRating {
+    PlayerRating {
+        player_id,
+        rating: config.initial_rating,
+        deviation: config.initial_deviation,
+        volatility: config.initial_volatility,
+        matches_rated: 0,
+    }
+}
+
+// pull a rating towards the initial rating between seasons, keeping carryover_basis_points of the
+// distance, and raise its deviation so that the first matches of the new season move it faster
+pub fn soft_reset(
+    config: &RatingConfig,
+    player: &PlayerRating,
+    carryover_basis_points: u64,
+    reset_deviation: f64,
+) -> PlayerRating {
+    let carryover = carryover_basis_points as f64 / 10_000.0;
+    PlayerRating {
+        rating: config.initial_rating + (player.rating - config.initial_rating) * carryover,
+        deviation: player
+            .deviation
+            .max(reset_deviation.min(config.initial_deviation)),
+        ..player.clone()
+    }
+}
+
+// check that the parameters of a rating config can be used to rate matches
+pub fn validate_config(config: &RatingConfig) -> Result<(), String> {
+    let algorithm_parameter = match config.algorithm {
+        RatingAlgorithm::Elo { k_factor } => k_factor,
+        RatingAlgorithm::Glicko2 { tau } => tau,
+    };
+    if !is_positive(algorithm_parameter) {
+        return Err("the algorithm parameter must be a positive number".to_string());
+    }
+    if !(config.initial_rating.is_finite() && config.initial_rating >= 0.0) {
+        return Err("the initial rating must not be negative".to_string());
+    }
+    if !is_positive(config.initial_deviation) || !is_positive(config.initial_volatility) {
+        return Err("the initial deviation and volatility must be positive numbers".to_string());
+    }
+    Ok(())
+}
+
+fn is_positive(value: f64) -> bool {
+    value.is_finite() && value > 0.0
+}
+
+// rate one match, made of one outcome per opponent, with the configured algorithm
+pub fn rate(config: &RatingConfig, player: &PlayerRating, outcomes: &[Outcome]) -> PlayerRating {
+    let mut rated = match config.algorithm {
+        RatingAlgorithm::Elo { k_factor } => rate_elo(player, outcomes, k_factor),
+        RatingAlgorithm::Glicko2 { tau } => rate_glicko2(player, outcomes, tau),
+    };
+    rated.matches_rated += 1;
+    rated
+}
+
+fn rate_elo(player: &PlayerRating, outcomes: &[Outcome], k_factor: f64) -> PlayerRating {
+    let change: f64 = outcomes
+        .iter()
+        .map(|outcome| {
+            let expected =
+                1.0 / (1.0 + 10f64.powf((outcome.opponent_rating - player.rating) / 400.0));
+            k_factor * (outcome.score - expected)
+        })
+        .sum();
+    PlayerRating {
+        rating: player.rating + change,
+        ..player.clone()
+    }
+}
+
+//Glicko-2 as described by Glickman, treating every match as its own rating period
+fn rate_glicko2(player: &PlayerRating, outcomes: &[Outcome], tau: f64) -> PlayerRating {
+    if outcomes.is_empty() {
+        return player.clone();
+    }
+
+    let mu = (player.rating - 1500.0) / GLICKO2_SCALE;
+    let phi = player.deviation / GLICKO2_SCALE;
+    let sigma = player.volatility;
+
+    //estimated variance and improvement of the rating based on the outcomes only
+    let mut inverse_variance = 0.0;
+    let mut improvement_sum = 0.0;
+    for outcome in outcomes {
+        let opponent_mu = (outcome.opponent_rating - 1500.0) / GLICKO2_SCALE;
+        let g = glicko2_g(outcome.opponent_deviation / GLICKO2_SCALE);
+        let expected = 1.0 / (1.0 + (-g * (mu - opponent_mu)).exp());
+        inverse_variance += g * g * expected * (1.0 - expected);
+        improvement_sum += g * (outcome.score - expected);
+    }
+    let variance = 1.0 / inverse_variance;
+    //opponents so far apart or so uncertain that the outcome was certain tell nothing about the
+    //player, who is rated as if they had not played: only the deviation grows
+    if !variance.is_finite() {
+        return PlayerRating {
+            deviation: GLICKO2_SCALE * (phi * phi + sigma * sigma).sqrt(),
+            ..player.clone()
+        };
+    }
+    let delta = variance * improvement_sum;
+
+    let new_sigma = glicko2_volatility(phi, sigma, variance, delta, tau);
+    let pre_period_phi = (phi * phi + new_sigma * new_sigma).sqrt();
+    let new_phi = 1.0 / (1.0 / (pre_period_phi * pre_period_phi) + 1.0 / variance).sqrt();
+    let new_mu = mu + new_phi * new_phi * improvement_sum;
+
+    PlayerRating {
+        rating: GLICKO2_SCALE * new_mu + 1500.0,
+        deviation: GLICKO2_SCALE * new_phi,
+        volatility: new_sigma,
+        ..player.clone()
+    }
+}
+
+fn glicko2_g(phi: f64) -> f64 {
+    1.0 / (1.0 + 3.0 * phi * phi / (PI * PI)).sqrt()
+}
+
+//solve for the new volatility with the Illinois algorithm
+fn glicko2_volatility(phi: f64, sigma: f64, variance: f64, delta: f64, tau: f64) -> f64 {
+    let a = (sigma * sigma).ln();
+    let f = |x: f64| {
+        let ex = x.exp();
+        let denominator = phi * phi + variance + ex;
+        ex * (delta * delta - phi * phi - variance - ex) / (2.0 * denominator * denominator)
+            - (x - a) / (tau * tau)
+    };
+
+    let mut lower = a;
+    let mut upper = if delta * delta > phi * phi + variance {
+        (delta * delta - phi * phi - variance).ln()
+    } else {
+        let mut k = 1.0;
+        while f(a - k * tau) < 0.0 {
+            k += 1.0;
+        }
+        a - k * tau
+    };
+
+    let mut f_lower = f(lower);
+    let mut f_upper = f(upper);
+    while (upper - lower).abs() > GLICKO2_EPSILON {
+        let candidate = lower + (lower - upper) * f_lower / (f_upper - f_lower);
+        let f_candidate = f(candidate);
+        if f_candidate * f_upper <= 0.0 {
+            lower = upper;
+            f_lower = f_upper;
+        } else {
+            f_lower /= 2.0;
+        }
+        upper = candidate;
+        f_upper = f_candidate;
+    }
+    (lower / 2.0).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player(rating: f64, deviation: f64, volatility: f64) -> PlayerRating {
+        PlayerRating {
+            player_id: 0,
+            rating,
+            deviation,
+            volatility,
+            matches_rated: 0,
+        }
+    }
+
+    fn outcome(opponent_rating: f64, opponent_deviation: f64, score: f64) -> Outcome {
+        Outcome {
+            opponent_rating,
+            opponent_deviation,
+            score,
+        }
+    }
+
+    #[test]
+    fn glicko2_matches_glickmans_example() {
+        let outcomes = [outcome(1400.0, 30.0, 1.0), outcome(1550.0, 100.0, 0.0), outcome(1700.0, 300.0, 0.0)];
+        let rated = rate_glicko2(&player(1500.0, 200.0, 0.06), &outcomes, 0.5);
+        assert!((rated.rating - 1464.06).abs() < 0.01, "rating {}", rated.rating);
+        assert!((rated.deviation - 151.52).abs() < 0.01, "deviation {}", rated.deviation);
+        assert!((rated.volatility - 0.05999).abs() < 0.00001, "volatility {}", rated.volatility);
+    }
+
+    #[test]
+    fn glicko2_ignores_outcomes_without_information() {
+        let rated = rate_glicko2(&player(1500.0, 200.0, 0.06), &[outcome(1_000_000.0, 30.0, 0.0)], 0.5);
+        assert_eq!(rated.rating, 1500.0);
+        assert!(rated.deviation.is_finite() && rated.deviation > 200.0);
+        assert_eq!(rated.volatility, 0.06);
+    }
+
+    #[test]
+    fn elo_draw_between_equals_changes_nothing() {
+        let rated = rate_elo(&player(1500.0, 350.0, 0.06), &[outcome(1500.0, 350.0, 0.5)], 32.0);
+        assert_eq!(rated.rating, 1500.0);
+    }
+
+    #[test]
+    fn elo_draw_moves_both_players_by_the_same_amount() {
+        let (strong, weak) = (player(1700.0, 350.0, 0.06), player(1500.0, 350.0, 0.06));
+        let strong_rated = rate_elo(&strong, &[outcome(weak.rating, weak.deviation, 0.5)], 32.0);
+        let weak_rated = rate_elo(&weak, &[outcome(strong.rating, strong.deviation, 0.5)], 32.0);
+        assert!(strong_rated.rating < strong.rating);
+        assert!(((strong.rating - strong_rated.rating) - (weak_rated.rating - weak.rating)).abs() < 1e-9);
+    }
+}
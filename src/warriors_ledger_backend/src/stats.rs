@@ -0,0 +1,308 @@
+use crate::types::{StatModifiers, Weapon, WeaponAnalytics, WeaponFieldChange, WeaponStats};
+
+//modifiers are in basis points of the base stat, 10000 doubles a stat and -10000 takes it to zero
+const BASIS_POINTS: i64 = 10_000;
+
+//accuracy is a percentage, modifiers cannot take it past 100
+const MAX_ACCURACY: u64 = 100;
+
+//range in meters at which a weapon keeps half of its DPS in the effective range score
+const HALF_SCORE_RANGE: f64 = 50.0;
+
+// stats of a weapon without attachments
+pub fn base_stats(weapon: &Weapon) -> WeaponStats {
+    WeaponStats {
+        damage: weapon.damage,
+        ammo: weapon.ammo,
+        range: weapon.range,
+        fire_rate: weapon.fire_rate,
+        reload_time: weapon.reload_time,
+        accuracy: weapon.accuracy,
+    }
+}
+
+// check that the modifiers of an attachment change at least one stat and take none below zero
+pub fn validate_modifiers(modifiers: &StatModifiers) -> Result<(), String> {
+    let values = modifier_values(modifiers);
+    if values.iter().all(|value| *value == 0) {
+        return Err("an attachment must modify at least one stat".to_string());
+    }
+    if values.iter().any(|value| *value < -BASIS_POINTS) {
+        return Err("a modifier cannot remove more than 10000 basis points".to_string());
+    }
+    Ok(())
+}
+
+// stats of a weapon with attachments, the modifiers of a stat add up before they are applied
+pub fn apply_modifiers(weapon: &Weapon, modifiers: &[StatModifiers]) -> WeaponStats {
+    let total = modifiers.iter().fold(StatModifiers::default(), |total, modifier| StatModifiers {
+        damage: total.damage.saturating_add(modifier.damage),
+        ammo: total.ammo.saturating_add(modifier.ammo),
+        range: total.range.saturating_add(modifier.range),
+        fire_rate: total.fire_rate.saturating_add(modifier.fire_rate),
+        reload_time: total.reload_time.saturating_add(modifier.reload_time),
+        accuracy: total.accuracy.saturating_add(modifier.accuracy),
+    });
+    WeaponStats {
+        damage: modify(weapon.damage, total.damage),
+        ammo: modify(weapon.ammo, total.ammo),
+        range: modify(weapon.range, total.range),
+        fire_rate: modify(weapon.fire_rate, total.fire_rate),
+        reload_time: modify(weapon.reload_time, total.reload_time),
+        accuracy: modify(weapon.accuracy, total.accuracy).min(MAX_ACCURACY),
+    }
+}
+
+// damage per second while the magazine lasts
+pub fn burst_dps(stats: &WeaponStats) -> f64 {
+    stats.damage as f64 * stats.fire_rate as f64
+}
+
+// damage per second over full magazine cycles: emptying the magazine, then reloading
+pub fn sustained_dps(stats: &WeaponStats) -> f64 {
+    if stats.ammo == 0 || stats.fire_rate == 0 {
+        return 0.0;
+    }
+    let cycle_damage = stats.damage as f64 * stats.ammo as f64;
+    let cycle_seconds = stats.ammo as f64 / stats.fire_rate as f64 + stats.reload_time as f64;
+    cycle_damage / cycle_seconds
+}
+
+// sustained damage per second of the shots expected to hit
+pub fn accuracy_weighted_dps(stats: &WeaponStats) -> f64 {
+    sustained_dps(stats) * hit_chance(stats)
+}
+
+// accuracy-weighted DPS scaled by range, with diminishing returns so that range alone cannot make
+// up for a weapon that deals no damage
+pub fn effective_range_score(stats: &WeaponStats) -> f64 {
+    let range = stats.range as f64;
+    accuracy_weighted_dps(stats) * range / (range + HALF_SCORE_RANGE)
+}
+
+// expected number of shots, misses included, to take down the health pool, None if the weapon
+// cannot deal damage
+pub fn shots_to_kill(stats: &WeaponStats, health: u64) -> Option<u64> {
+    if stats.damage == 0 || stats.ammo == 0 || stats.fire_rate == 0 || stats.accuracy == 0 {
+        return None;
+    }
+    let hits = health.div_ceil(stats.damage);
+    let accuracy = stats.accuracy.min(MAX_ACCURACY);
+    Some((hits.saturating_mul(MAX_ACCURACY)).div_ceil(accuracy))
+}
+
+// seconds from the first shot to the shot that takes down the health pool, reloading whenever the
+// magazine is empty
+pub fn time_to_kill(stats: &WeaponStats, health: u64) -> Option<f64> {
+    let shots = shots_to_kill(stats, health)?;
+    let later_shots = shots.saturating_sub(1);
+    let reloads = later_shots / stats.ammo;
+    Some(later_shots as f64 / stats.fire_rate as f64 + reloads as f64 * stats.reload_time as f64)
+}
+
+// every derived metric of a weapon with the given stats
+pub fn analyze(weapon: &Weapon, stats: WeaponStats, health: u64) -> WeaponAnalytics {
+    WeaponAnalytics {
+        weapon_id: weapon.id,
+        name: weapon.name.clone(),
+        burst_dps: burst_dps(&stats),
+        sustained_dps: sustained_dps(&stats),
+        accuracy_weighted_dps: accuracy_weighted_dps(&stats),
+        effective_range_score: effective_range_score(&stats),
+        shots_to_kill: shots_to_kill(&stats, health),
+        time_to_kill: time_to_kill(&stats, health),
+        stats,
+    }
+}
+
+fn hit_chance(stats: &WeaponStats) -> f64 {
+    stats.accuracy.min(MAX_ACCURACY) as f64 / MAX_ACCURACY as f64
+}
+
+fn modifier_values(modifiers: &StatModifiers) -> [i64; 6] {
+    [
+        modifiers.damage,
+        modifiers.ammo,
+        modifiers.range,
+        modifiers.fire_rate,
+        modifiers.reload_time,
+        modifiers.accuracy,
+    ]
+}
+
+fn modify(value: u64, basis_points: i64) -> u64 {
+    let factor = BASIS_POINTS.saturating_add(basis_points).max(0) as u128;
+    (value as u128 * factor / BASIS_POINTS as u128).min(u64::MAX as u128) as u64
+}
+
+// fields that differ between two versions of a weapon, in the order they are declared
+pub fn weapon_changes(from: &Weapon, to: &Weapon) -> Vec<WeaponFieldChange> {
+    let mut changes = Vec::new();
+    if from.name != to.name {
+        changes.push(WeaponFieldChange {
+            field: "name".to_string(),
+            from: from.name.clone(),
+            to: to.name.clone(),
+        });
+    }
+    let stats = [
+        ("damage", from.damage, to.damage),
+        ("ammo", from.ammo, to.ammo),
+        ("range", from.range, to.range),
+        ("fire_rate", from.fire_rate, to.fire_rate),
+        ("reload_time", from.reload_time, to.reload_time),
+        ("accuracy", from.accuracy, to.accuracy),
+        ("price", from.price, to.price),
+        ("level", from.level, to.level),
+        ("rank", from.rank, to.rank),
+    ];
+    for (field, from, to) in stats {
+        if from != to {
+            changes.push(WeaponFieldChange {
+                field: field.to_string(),
+                from: from.to_string(),
+                to: to.to_string(),
+            });
+        }
+    }
+    changes
+}
+
+// mean and population standard deviation of a set of values, None when it is empty
+pub fn mean_and_deviation(values: &[f64]) -> Option<(f64, f64)> {
+    if values.is_empty() {
+        return None;
+    }
+    let count = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / count;
+    let variance = values.iter().map(|value| (value - mean) * (value - mean)).sum::<f64>() / count;
+    Some((mean, variance.sqrt()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rifle() -> Weapon {
+        Weapon {
+            name: "Rifle".to_string(),
+            id: 1,
+            damage: 30,
+            ammo: 30,
+            range: 100,
+            fire_rate: 10,
+            reload_time: 2,
+            accuracy: 80,
+            price: 500,
+            level: 1,
+            rank: 1,
+        }
+    }
+
+    #[test]
+    fn metrics_of_a_weapon() {
+        let stats = base_stats(&rifle());
+        assert_eq!(sustained_dps(&stats), 180.0);
+        assert_eq!(accuracy_weighted_dps(&stats), 144.0);
+        assert_eq!(effective_range_score(&stats), 96.0);
+        assert_eq!(shots_to_kill(&stats, 100), Some(5));
+        assert_eq!(time_to_kill(&stats, 100), Some(0.4));
+    }
+
+    #[test]
+    fn time_to_kill_counts_reloads() {
+        let stats = WeaponStats {
+            ammo: 2,
+            ..base_stats(&rifle())
+        };
+        assert_eq!(time_to_kill(&stats, 100), Some(4.4));
+    }
+
+    #[test]
+    fn weapons_without_ammo_or_fire_rate_deal_no_damage() {
+        for stats in [
+            WeaponStats {
+                ammo: 0,
+                ..base_stats(&rifle())
+            },
+            WeaponStats {
+                fire_rate: 0,
+                ..base_stats(&rifle())
+            },
+        ] {
+            assert_eq!(sustained_dps(&stats), 0.0);
+            assert_eq!(effective_range_score(&stats), 0.0);
+            assert_eq!(shots_to_kill(&stats, 100), None);
+            assert_eq!(time_to_kill(&stats, 100), None);
+        }
+    }
+
+    #[test]
+    fn weapons_without_accuracy_never_kill() {
+        let stats = WeaponStats {
+            accuracy: 0,
+            ..base_stats(&rifle())
+        };
+        assert_eq!(sustained_dps(&stats), 180.0);
+        assert_eq!(accuracy_weighted_dps(&stats), 0.0);
+        assert_eq!(effective_range_score(&stats), 0.0);
+        assert_eq!(shots_to_kill(&stats, 100), None);
+        assert_eq!(time_to_kill(&stats, 100), None);
+    }
+
+    #[test]
+    fn accuracy_above_100_counts_as_100() {
+        let stats = WeaponStats {
+            accuracy: 150,
+            ..base_stats(&rifle())
+        };
+        assert_eq!(accuracy_weighted_dps(&stats), 180.0);
+        assert_eq!(shots_to_kill(&stats, 100), Some(4));
+    }
+
+    #[test]
+    fn modify_clamps_basis_points() {
+        assert_eq!(modify(100, 2_500), 125);
+        assert_eq!(modify(3, 5_000), 4);
+        assert_eq!(modify(100, -10_000), 0);
+        assert_eq!(modify(100, -20_000), 0);
+        assert_eq!(modify(u64::MAX, 10_000), u64::MAX);
+    }
+
+    #[test]
+    fn modifiers_add_up_before_they_are_applied() {
+        let modifier = StatModifiers {
+            damage: 2_500,
+            accuracy: 5_000,
+            ..StatModifiers::default()
+        };
+        let stats = apply_modifiers(&rifle(), &[modifier.clone(), modifier]);
+        assert_eq!(stats.damage, 45);
+        assert_eq!(stats.accuracy, MAX_ACCURACY);
+        assert_eq!(stats.ammo, 30);
+        assert_eq!(apply_modifiers(&rifle(), &[]).accuracy, 80);
+    }
+
+    #[test]
+    fn modifiers_must_change_a_stat_without_removing_more_than_all_of_it() {
+        assert!(validate_modifiers(&StatModifiers::default()).is_err());
+        let removes_all = StatModifiers {
+            range: -10_000,
+            ..StatModifiers::default()
+        };
+        assert!(validate_modifiers(&removes_all).is_ok());
+        let removes_more = StatModifiers {
+            range: -10_001,
+            ..StatModifiers::default()
+        };
+        assert!(validate_modifiers(&removes_more).is_err());
+    }
+
+    #[test]
+    fn mean_and_deviation_of_values() {
+        assert_eq!(mean_and_deviation(&[]), None);
+        assert_eq!(mean_and_deviation(&[3.0]), Some((3.0, 0.0)));
+        assert_eq!(mean_and_deviation(&[0.5, 0.5, 0.5]), Some((0.5, 0.0)));
+        assert_eq!(mean_and_deviation(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]), Some((5.0, 2.0)));
+    }
+}
@@ -1,8 +1,13 @@
 
-use candid::{Decode, Encode};
+use candid::{CandidType, Decode, Encode, Principal};
 use ic_stable_structures::memory_manager::VirtualMemory;
-use ic_stable_structures::{BoundedStorable, Cell, DefaultMemoryImpl, Storable};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Cell, DefaultMemoryImpl, StableBTreeMap, Storable};
+use serde::de::DeserializeOwned;
 use std::borrow::Cow;
+use std::cmp::{Ordering, Reverse};
+use std::marker::PhantomData;
+use std::ops::Deref;
 
 pub type Memory = VirtualMemory<DefaultMemoryImpl>;
 pub type IdCell = Cell<u64, Memory>;
@@ -15,8 +20,7 @@ pub struct PlayerProfile {
     pub score: u64,
     pub level: u64,
     pub rank: u64,
-    pub weapons: Vec<Weapon>,      //List of all weapons owned by player
-    pub match_history: Vec<Match>, //List of all matches played by player
+    pub owner: Principal, //principal that created the profile
 }
 
 //struct to store weapon profile
@@ -35,96 +39,1848 @@ pub struct Weapon {
     pub rank: u64,
 }
 
+//kind of attachment, a weapon holds at most one attachment of each kind
+#[derive(candid::CandidType, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AttachmentKind {
+    Scope,
+    Magazine,
+    Barrel,
+}
+
+//changes an attachment makes to weapon stats, in basis points of the base stat
+#[derive(candid::CandidType, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct StatModifiers {
+    pub damage: i64,
+    pub ammo: i64,
+    pub range: i64,
+    pub fire_rate: i64,
+    pub reload_time: i64,
+    pub accuracy: i64,
+}
+
+//struct to store an attachment of the catalog
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct Attachment {
+    pub id: u64,
+    pub name: String,
+    pub kind: AttachmentKind,
+    pub modifiers: StatModifiers,
+    pub price: u64,
+}
+
+//attachment owned by a player, with the weapon it is attached to
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct PlayerAttachment {
+    pub attachment: Attachment,
+    pub weapon_id: Option<u64>,
+}
+
+//combat stats of a weapon
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct WeaponStats {
+    pub damage: u64,
+    pub ammo: u64,
+    pub range: u64,
+    pub fire_rate: u64,
+    pub reload_time: u64,
+    pub accuracy: u64,
+}
+
+//stats of a player's weapon before and after the modifiers of its attachments
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct EffectiveWeaponStats {
+    pub player_id: u64,
+    pub weapon_id: u64,
+    pub attachment_ids: Vec<u64>,
+    pub base: WeaponStats,
+    pub effective: WeaponStats,
+}
+
+//criteria of a weapon query, every criterion that is set must match and bounds are inclusive
+#[derive(candid::CandidType, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct WeaponFilter {
+    pub name: Option<String>, //case-insensitive part of the weapon name
+    pub min_damage: Option<u64>,
+    pub max_damage: Option<u64>,
+    pub min_price: Option<u64>,
+    pub max_price: Option<u64>,
+    pub min_level: Option<u64>,
+    pub max_level: Option<u64>,
+    pub min_rank: Option<u64>,
+    pub max_rank: Option<u64>,
+    pub min_accuracy: Option<u64>,
+    pub max_accuracy: Option<u64>,
+}
+
+//weapon field a weapon query can sort by
+#[derive(candid::CandidType, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WeaponSortField {
+    Id,
+    Name,
+    Damage,
+    Ammo,
+    Range,
+    FireRate,
+    ReloadTime,
+    Accuracy,
+    Price,
+    Level,
+    Rank,
+}
+
+impl WeaponSortField {
+    // order of two weapons by this field, names are compared without case
+    pub fn compare(self, a: &Weapon, b: &Weapon) -> Ordering {
+        match self {
+            WeaponSortField::Id => a.id.cmp(&b.id),
+            WeaponSortField::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+            WeaponSortField::Damage => a.damage.cmp(&b.damage),
+            WeaponSortField::Ammo => a.ammo.cmp(&b.ammo),
+            WeaponSortField::Range => a.range.cmp(&b.range),
+            WeaponSortField::FireRate => a.fire_rate.cmp(&b.fire_rate),
+            WeaponSortField::ReloadTime => a.reload_time.cmp(&b.reload_time),
+            WeaponSortField::Accuracy => a.accuracy.cmp(&b.accuracy),
+            WeaponSortField::Price => a.price.cmp(&b.price),
+            WeaponSortField::Level => a.level.cmp(&b.level),
+            WeaponSortField::Rank => a.rank.cmp(&b.rank),
+        }
+    }
+}
+
+//direction of one key of a sort
+#[derive(candid::CandidType, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SortDirection {
+    Ascending,
+    Descending,
+}
+
+//field and direction of one key of a weapon sort
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct WeaponSortKey {
+    pub field: WeaponSortField,
+    pub direction: SortDirection,
+}
+
+impl WeaponSortKey {
+    // order of two weapons by this key
+    pub fn compare(&self, a: &Weapon, b: &Weapon) -> Ordering {
+        let ordering = self.field.compare(a, b);
+        match self.direction {
+            SortDirection::Ascending => ordering,
+            SortDirection::Descending => ordering.reverse(),
+        }
+    }
+}
+
+//order of the results of a weapon query, weapons that tie on every key are ordered by id
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct WeaponSort {
+    pub primary: WeaponSortKey,
+    pub secondary: Option<WeaponSortKey>,
+}
+
+//metric derived from weapon stats that weapons can be ranked by
+#[derive(candid::CandidType, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WeaponMetric {
+    Damage,              //raw damage per shot
+    BurstDps,            //damage per second while the magazine lasts
+    SustainedDps,        //damage per second including reloads
+    AccuracyWeightedDps, //sustained DPS scaled by accuracy
+    EffectiveRange,      //accuracy-weighted DPS scaled by range
+    TimeToKill,          //lower is better
+}
+
+//metrics derived from the stats of a weapon, DPS in damage per second and times in seconds
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct WeaponAnalytics {
+    pub weapon_id: u64,
+    pub name: String,
+    pub stats: WeaponStats,
+    pub burst_dps: f64,
+    pub sustained_dps: f64,
+    pub accuracy_weighted_dps: f64,
+    pub effective_range_score: f64, //accuracy-weighted DPS times range / (range + 50 meters)
+    pub shots_to_kill: Option<u64>, //expected shots including misses, None if the weapon cannot kill
+    pub time_to_kill: Option<f64>,
+}
+
+//weapon analytics settings chosen by designers
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct WeaponAnalyticsConfig {
+    pub target_health: u64, //health pool that time to kill is measured against
+}
+
+impl Default for WeaponAnalyticsConfig {
+    fn default() -> Self {
+        WeaponAnalyticsConfig { target_health: 100 }
+    }
+}
+
+//running totals of the completed matches a weapon was used in, kept up to date as matches are recorded
+#[derive(candid::CandidType, Clone, Default, Serialize, Deserialize)]
+pub struct WeaponTelemetry {
+    pub weapon_id: u64,
+    pub picks: u64, //participants that used the weapon
+    pub wins: u64,
+    pub total_score: u64,
+    pub total_match_time: u64, //minutes
+}
+
+//balance figures of a weapon derived from its telemetry, rates are fractions between 0 and 1
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct WeaponBalance {
+    pub weapon_id: u64,
+    pub name: String,
+    pub picks: u64,
+    pub pick_rate: f64, //share of all participants that used the weapon
+    pub win_rate: f64,
+    pub average_score: f64,
+    pub average_match_time: f64, //minutes
+}
+
+//balance figure that weapons are compared on when looking for outliers
+#[derive(candid::CandidType, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BalanceMetric {
+    PickRate,
+    WinRate,
+    AverageScore,
+    AverageMatchTime,
+}
+
+impl BalanceMetric {
+    pub const ALL: [BalanceMetric; 4] = [
+        BalanceMetric::PickRate,
+        BalanceMetric::WinRate,
+        BalanceMetric::AverageScore,
+        BalanceMetric::AverageMatchTime,
+    ];
+
+    // value of this figure for a weapon
+    pub fn value(self, balance: &WeaponBalance) -> f64 {
+        match self {
+            BalanceMetric::PickRate => balance.pick_rate,
+            BalanceMetric::WinRate => balance.win_rate,
+            BalanceMetric::AverageScore => balance.average_score,
+            BalanceMetric::AverageMatchTime => balance.average_match_time,
+        }
+    }
+}
+
+//weapon whose balance figure is far from the other weapons', z_score counts standard deviations
+//from the mean and is positive above it
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct WeaponOutlier {
+    pub weapon_id: u64,
+    pub name: String,
+    pub metric: BalanceMetric,
+    pub value: f64,
+    pub mean: f64,
+    pub z_score: f64,
+}
+
+//outlier detection settings chosen by designers
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct TelemetryConfig {
+    pub min_picks: u64, //weapons picked fewer times are left out of the comparison
+    pub outlier_z_score: f64, //smallest distance from the mean, in standard deviations, that is flagged
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        TelemetryConfig {
+            min_picks: 20,
+            outlier_z_score: 2.0,
+        }
+    }
+}
+
+//struct to store one version of a weapon, revision 1 is the weapon as created and every update adds the next
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct WeaponRevision {
+    pub weapon_id: u64,
+    pub revision: u64,
+    pub weapon: Weapon, //the weapon as it was after this change
+    pub changed_by: Principal,
+    pub changed_at: u64, //nanoseconds since the epoch
+    pub note: Option<String>,
+    pub patch_id: Option<u64>, //balance patch the change belongs to
+}
+
+//one field of a weapon that differs between two revisions, values are shown as text
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct WeaponFieldChange {
+    pub field: String,
+    pub from: String,
+    pub to: String,
+}
+
+//fields that changed between two revisions of a weapon
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct WeaponRevisionDiff {
+    pub weapon_id: u64,
+    pub from_revision: u64,
+    pub to_revision: u64,
+    pub changes: Vec<WeaponFieldChange>,
+}
+
+//struct to store a named group of weapon changes that are released and rolled back together
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct BalancePatch {
+    pub id: u64,
+    pub name: String,
+    pub notes: String,
+    pub created_by: Principal,
+    pub created_at: u64,             //nanoseconds since the epoch
+    pub rolled_back_at: Option<u64>, //a rolled back patch takes no more changes
+}
+
+//struct to store a named set of weapons a player takes into matches, every slot holds an owned weapon
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct Loadout {
+    pub id: u64,
+    pub player_id: u64,
+    pub name: String,
+    pub primary: Option<u64>,
+    pub secondary: Option<u64>,
+    pub melee: Option<u64>,
+}
+
+impl Loadout {
+    // weapons held by the slots of the loadout
+    pub fn weapon_ids(&self) -> impl Iterator<Item = u64> {
+        [self.primary, self.secondary, self.melee].into_iter().flatten()
+    }
+
+    // empty the slots holding a weapon, returns whether any slot held it
+    pub fn unequip(&mut self, weapon_id: u64) -> bool {
+        let mut unequipped = false;
+        for slot in [&mut self.primary, &mut self.secondary, &mut self.melee] {
+            if *slot == Some(weapon_id) {
+                *slot = None;
+                unequipped = true;
+            }
+        }
+        unequipped
+    }
+}
+
 //struct to store match profile
 #[derive(candid::CandidType, Clone, Serialize, Deserialize)]
 pub struct Match {
+    pub id: u64,
+    pub participants: Vec<Participant>,
+    pub level: u64,
+    pub rank: u64,
+    pub time: u64, //time taken to complete match in minutes
+    pub status: MatchStatus,
+    pub transitions: Vec<MatchTransition>, //every status change, oldest first
+}
+
+//lifecycle of a match, results count towards standings and ratings once the match is completed
+#[derive(candid::CandidType, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MatchStatus {
+    Scheduled,
+    InProgress,
+    Completed,
+    Disputed,
+}
+
+impl MatchStatus {
+    // whether a match in this status may move to the next one
+    pub fn can_transition_to(self, next: MatchStatus) -> bool {
+        matches!(
+            (self, next),
+            (MatchStatus::Scheduled, MatchStatus::InProgress)
+                | (MatchStatus::InProgress, MatchStatus::Completed)
+                | (MatchStatus::Completed, MatchStatus::Disputed)
+                | (MatchStatus::Disputed, MatchStatus::Completed)
+        )
+    }
+
+    // completed and disputed matches can no longer be edited through update_match
+    pub fn is_finalized(self) -> bool {
+        matches!(self, MatchStatus::Completed | MatchStatus::Disputed)
+    }
+}
+
+//struct to store one status change of a match
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct MatchTransition {
+    pub status: MatchStatus, //status the match moved to
+    pub timestamp: u64,      //nanoseconds since the epoch
+    pub caller: Principal,
+    pub note: Option<String>, //dispute reason or resolution note
+}
+
+//outcome of a match for one participant, teammates share the same outcome
+#[derive(candid::CandidType, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum MatchOutcome {
+    Loss,
+    Draw,
+    Win,
+}
+
+//struct to store one player's part in a match
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct Participant {
+    pub player_id: u64,
+    pub team: u64,
+    pub weapon_id: u64, //weapon used during the match
+    pub score: u64,
+    pub kills: u64,
+    pub deaths: u64,
+    pub assists: u64,
+    pub damage: u64, //damage dealt during the match
+    pub outcome: MatchOutcome,
+}
+
+//struct to store leaderboard
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct Leaderboard {
+    pub id: u64,
+    pub player_id: u64,
+    pub score: u64,
+    pub level: u64,
+    pub rank: u64,
+    pub season_id: Option<u64>, //season that was live when the entry was created
+}
+
+//running totals of a player's recorded matches, kept up to date as matches are recorded
+#[derive(candid::CandidType, Clone, Default, Serialize, Deserialize)]
+pub struct PlayerStanding {
+    pub player_id: u64,
+    pub total_score: u64,
+    pub matches_played: u64,
+    pub wins: u64,
+}
+
+//a player's place on the computed leaderboard
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct LeaderboardEntry {
+    pub position: u64, //1 for the best player
+    pub standing: PlayerStanding,
+}
+
+//orders standings by total score, then wins, both descending, then by player id
+pub type StandingKey = (Reverse<u64>, Reverse<u64>, u64);
+
+//algorithm used to update player ratings from match outcomes
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
+pub enum RatingAlgorithm {
+    Elo { k_factor: f64 },
+    Glicko2 { tau: f64 }, //tau constrains how fast volatility changes, usually 0.3 to 1.2
+}
+
+//rating settings chosen by admins
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct RatingConfig {
+    pub algorithm: RatingAlgorithm,
+    pub initial_rating: f64,
+    pub initial_deviation: f64,
+    pub initial_volatility: f64,
+}
+
+impl Default for RatingConfig {
+    fn default() -> Self {
+        RatingConfig {
+            algorithm: RatingAlgorithm::Glicko2 { tau: 0.5 },
+            initial_rating: 1500.0,
+            initial_deviation: 350.0,
+            initial_volatility: 0.06,
+        }
+    }
+}
+
+//skill rating of a player, deviation is the uncertainty of the rating
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct PlayerRating {
+    pub player_id: u64,
+    pub rating: f64,
+    pub deviation: f64,
+    pub volatility: f64,
+    pub matches_rated: u64,
+}
+
+//rating change caused by one recorded match
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct RatingChange {
+    pub player_id: u64,
+    pub match_id: u64,
+    pub rating_before: f64,
+    pub rating_after: f64,
+    pub deviation_after: f64,
+    pub timestamp: u64, //nanoseconds since the epoch
+}
+
+//struct to store a season, matches completed while it is live count towards its leaderboard
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct Season {
+    pub id: u64,
+    pub name: String,
+    pub starts_at: u64,        //nanoseconds since the epoch
+    pub ends_at: u64,          //nanoseconds since the epoch
+    pub ended_at: Option<u64>, //set when the season stops counting matches, before ends_at if ended early
+}
+
+impl Season {
+    // whether matches completed at a time count towards the season
+    pub fn is_live_at(&self, time: u64) -> bool {
+        self.ended_at.is_none() && self.starts_at <= time && time < self.ends_at
+    }
+}
+
+//progress of a season that is being ended over several timer ticks, removed once it has ended.
+//Standings are archived best first, then ratings are reset in player id order
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct SeasonEnd {
+    pub season_id: u64,
+    pub archived_players: u64, //standings archived so far, the next one gets position archived_players + 1
+    pub ratings_from: u64,     //player id the next batch of rating resets starts at
+    pub last_error: Option<String>, //why the last batch failed, it is retried after a delay
+}
+
+//season settings chosen by admins, applied to ratings when a season ends
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct SeasonConfig {
+    pub rating_carryover_basis_points: u64, //share of the distance to the initial rating that is kept
+    pub reset_deviation: f64, //deviations are raised to at least this, up to the initial deviation
+}
+
+impl Default for SeasonConfig {
+    fn default() -> Self {
+        SeasonConfig {
+            rating_carryover_basis_points: 5_000,
+            reset_deviation: 250.0,
+        }
+    }
+}
+
+//reason a player's soft-currency balance changed
+#[derive(candid::CandidType, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransactionKind {
+    Grant,                                          //credited by an admin
+    Purchase { weapon_id: u64 },                    //debited by purchase_weapon
+    Refund { transaction_id: u64, weapon_id: u64 }, //credits back the purchase with transaction_id
+    TradeEscrow { trade_id: u64 },                  //offered currency held while the trade is open
+    TradeRelease { trade_id: u64 },                 //escrow returned when the trade is cancelled or expires
+    TradeSent { trade_id: u64 },                    //requested currency paid by the accepting player
+    TradeReceived { trade_id: u64 },                //currency received from the other side of a trade
+    MarketBid { listing_id: u64 },                  //bid held in escrow until it is outbid or accepted
+    MarketBidRefund { listing_id: u64 },            //bid returned when outbid or the listing closes
+    MarketPurchase { listing_id: u64 },             //listing bought at its price
+    MarketSale { listing_id: u64, fee: u64 },       //sale proceeds after the marketplace fee
+    QuestReward { quest_id: u64 },                  //reward of a completed quest
+    PremiumPass { season_id: u64 },                 //premium battle pass of a season
+    BattlePassReward { season_id: u64, tier: u64 }, //reward of a claimed battle pass tier
+    AttachmentPurchase { attachment_id: u64 },      //debited by purchase_attachment
+}
+
+//struct to store one change of a player's soft-currency balance
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct Transaction {
+    pub id: u64,
+    pub player_id: u64,
+    pub kind: TransactionKind,
+    pub amount: u64,
+    pub balance_after: u64,
+    pub caller: Principal,
+    pub timestamp: u64, //nanoseconds since the epoch
+    pub note: Option<String>,
+}
+
+//lifecycle of a trade offer, only open offers can be accepted or cancelled
+#[derive(candid::CandidType, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TradeStatus {
+    Open,
+    Accepted,
+    Cancelled,
+    Expired,
+}
+
+//struct to store an offer from one player to another, the offered side is held in escrow while open
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct TradeOffer {
+    pub id: u64,
+    pub from_player_id: u64,
+    pub to_player_id: u64,
+    pub offered_weapon_ids: Vec<u64>,
+    pub offered_currency: u64,
+    pub requested_weapon_ids: Vec<u64>,
+    pub requested_currency: u64,
+    pub status: TradeStatus,
+    pub created_at: u64, //nanoseconds since the epoch
+    pub expires_at: u64, //nanoseconds since the epoch
+    pub closed_at: Option<u64>,
+}
+
+//lifecycle of a marketplace listing, only active listings can be bought or bid on
+#[derive(candid::CandidType, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ListingStatus {
+    Active,
+    Sold,
+    Cancelled,
+}
+
+//bid on a listing, the amount is held in escrow while it is the highest bid
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct Bid {
+    pub bidder_player_id: u64,
+    pub amount: u64,
+    pub placed_at: u64, //nanoseconds since the epoch
+}
+
+//struct to store a weapon offered for sale on the marketplace, the weapon is held while active
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct Listing {
+    pub id: u64,
+    pub seller_player_id: u64,
+    pub weapon_id: u64,
+    pub price: u64,
+    pub status: ListingStatus,
+    pub highest_bid: Option<Bid>,
+    pub buyer_player_id: Option<u64>,
+    pub sale_price: Option<u64>,
+    pub fee: Option<u64>,
+    pub created_at: u64, //nanoseconds since the epoch
+    pub closed_at: Option<u64>,
+}
+
+//marketplace settings chosen by admins, the fee is taken from the sale price
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct MarketConfig {
+    pub fee_basis_points: u64, //hundredths of a percent, 10000 takes the whole price
+}
+
+impl Default for MarketConfig {
+    fn default() -> Self {
+        MarketConfig {
+            fee_basis_points: 500,
+        }
+    }
+}
+
+//criteria of a listing search, every criterion that is set must match
+#[derive(candid::CandidType, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ListingFilter {
+    pub name: Option<String>, //case-insensitive part of the weapon name
+    pub min_price: Option<u64>,
+    pub max_price: Option<u64>,
+    pub min_level: Option<u64>,
+    pub max_level: Option<u64>,
+}
+
+//payment settings chosen by admins, weapons cannot be bought with tokens until a ledger is set
+#[derive(candid::CandidType, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PaymentConfig {
+    pub ledger: Option<Principal>, //ICRC-2 ledger canister that premium prices are charged on
+}
+
+//struct to store a weapon bought with ICRC tokens
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct TokenPurchase {
     pub id: u64,
     pub player_id: u64,
     pub weapon_id: u64,
+    pub buyer: Principal,
+    pub ledger: Principal,
+    pub amount: u64,
+    pub block_index: Option<candid::Nat>, //ledger block that records the transfer, None while pending
+    pub timestamp: u64,                   //nanoseconds since the epoch, sent as the transfer's created_at_time
+    pub memo: Vec<u8>,                    //sent with the transfer and the refund, the purchase id
+    pub refund: Option<TokenRefund>,      //refund sent to the ledger whose outcome is not known yet
+    pub status: TokenPurchaseStatus,
+}
+
+//arguments of a refund transfer, chosen once so that sending it again cannot refund twice
+#[derive(candid::CandidType, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TokenRefund {
+    pub created_at_time: u64,
+    pub fee: u64,
+}
+
+//state of a token purchase
+#[derive(candid::CandidType, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TokenPurchaseStatus {
+    Pending, //the ledger did not confirm the payment, it is sent again with the same arguments until it does
+    Completed, //the weapon was granted
+    //the weapon could not be granted because the player or weapon was deleted or the player already
+    //owned it by the time the ledger answered, the tokens are held until they are refunded
+    Refundable { reason: String },
+    Refunded { amount: u64, block_index: candid::Nat }, //amount sent back after the ledger fee
+}
+
+//XP awards and level curve chosen by admins
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct LevelingConfig {
+    pub level_thresholds: Vec<u64>, //total XP needed to reach level 2, 3, ..., strictly increasing
+    pub xp_per_score: u64,          //per point of match score
+    pub xp_per_win: u64,
+    pub xp_per_draw: u64,
+    pub xp_per_loss: u64,
+    pub xp_per_minute: u64, //per minute of match time
+}
+
+impl Default for LevelingConfig {
+    fn default() -> Self {
+        LevelingConfig {
+            level_thresholds: crate::leveling::default_level_thresholds(),
+            xp_per_score: 1,
+            xp_per_win: 100,
+            xp_per_draw: 50,
+            xp_per_loss: 25,
+            xp_per_minute: 5,
+        }
+    }
+}
+
+//XP and level of a player
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct PlayerXp {
+    pub player_id: u64,
+    pub xp: u64,
+    pub level: u64,
+    pub next_level_xp: Option<u64>, //None at the top of the level curve
+}
+
+//level reached by a player through a completed match
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct LevelUp {
+    pub player_id: u64,
+    pub previous_level: u64,
+    pub new_level: u64,
+    pub xp: u64,
+}
+
+//a completed match and the progress it unlocked, returned so the game client can show it
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct RecordedMatch {
+    pub match_profile: Match,
+    pub level_ups: Vec<LevelUp>,
+    pub unlocked_achievements: Vec<AchievementUnlock>,
+    pub completed_quests: Vec<QuestCompletion>,
+}
+
+//statistic of a participant that counts towards achievements and quests
+#[derive(candid::CandidType, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MatchMetric {
+    MatchesPlayed,
+    Wins,
+    Kills,
+    Assists,
+    Score,
+    Damage,
+}
+
+//struct to store an achievement, unlocked once a player's total of the metric over completed
+//matches reaches the threshold. With min_weapon_rank set, only matches played with a weapon of at
+//least that rank count
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct Achievement {
+    pub id: u64,
+    pub name: String,
+    pub description: String,
+    pub badge: Option<String>, //badge shown by the game client once unlocked
+    pub metric: MatchMetric,
+    pub threshold: u64,
+    pub min_weapon_rank: Option<u64>,
+    pub created_at: u64, //nanoseconds since the epoch
+}
+
+//achievement unlocked by a player
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct AchievementUnlock {
+    pub player_id: u64,
+    pub achievement_id: u64,
+    pub unlocked_at: u64, //nanoseconds since the epoch
+}
+
+//how often the active quests of a kind are rotated
+#[derive(candid::CandidType, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QuestCadence {
+    Daily,  //rotated at midnight UTC
+    Weekly, //rotated at midnight UTC between Sunday and Monday
+}
+
+//length of a day in nanoseconds, the unit of ic_cdk::api::time
+const DAY_NANOS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+impl QuestCadence {
+    pub const ALL: [QuestCadence; 2] = [QuestCadence::Daily, QuestCadence::Weekly];
+
+    //key of the cadence in stable maps
+    pub fn key(self) -> u64 {
+        match self {
+            QuestCadence::Daily => 0,
+            QuestCadence::Weekly => 1,
+        }
+    }
+
+    //number of quests active at once
+    pub fn active_quest_count(self) -> usize {
+        match self {
+            QuestCadence::Daily => 3,
+            QuestCadence::Weekly => 2,
+        }
+    }
+
+    //index of the period containing a time
+    pub fn period_at(self, time: u64) -> u64 {
+        (time + self.period_offset()) / self.period_length()
+    }
+
+    //time at which a period starts
+    pub fn period_start(self, period: u64) -> u64 {
+        (period * self.period_length()).saturating_sub(self.period_offset())
+    }
+
+    fn period_length(self) -> u64 {
+        match self {
+            QuestCadence::Daily => DAY_NANOS,
+            QuestCadence::Weekly => 7 * DAY_NANOS,
+        }
+    }
+
+    //the epoch was a Thursday, shifting weeks by 3 days makes them start on Mondays
+    fn period_offset(self) -> u64 {
+        match self {
+            QuestCadence::Daily => 0,
+            QuestCadence::Weekly => 3 * DAY_NANOS,
+        }
+    }
+}
+
+//currency and weapon granted by a completed quest or a claimed battle pass tier
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct Reward {
+    pub currency: u64,
+    pub weapon_id: Option<u64>, //paid out at its price if the player already owns it, quests skip it once deleted
+}
+
+//struct to store a quest template, completed once a player's total of the metric over the matches
+//completed while the quest is active reaches the target
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct QuestTemplate {
+    pub id: u64,
+    pub name: String,
+    pub description: String,
+    pub cadence: QuestCadence,
+    pub metric: MatchMetric,
+    pub target: u64,
+    pub reward: Reward,
+}
+
+//quests active during one period of a cadence
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct QuestRotation {
+    pub cadence: QuestCadence,
+    pub period: u64,
+    pub quest_ids: Vec<u64>,
+    pub started_at: u64, //nanoseconds since the epoch
+    pub ends_at: u64,    //nanoseconds since the epoch
+}
+
+//last failure of the timer rotating the quests of a cadence, removed once a rotation succeeds
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct QuestRotationFailure {
+    pub cadence: QuestCadence,
+    pub message: String,
+    pub failed_at: u64, //nanoseconds since the epoch, the rotation is retried a minute later
+}
+
+//struct to store a player's progress on a quest during one period
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct QuestProgress {
+    pub player_id: u64,
+    pub quest_id: u64,
+    pub period: u64,
+    pub progress: u64,
+    pub completed_at: Option<u64>,
+}
+
+//active quest with a player's progress on it
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct PlayerQuest {
+    pub quest: QuestTemplate,
+    pub progress: u64,
+    pub completed_at: Option<u64>,
+    pub ends_at: u64,
+}
+
+//quest completed by a player through a completed match, its reward has been granted
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct QuestCompletion {
+    pub player_id: u64,
+    pub quest_id: u64,
+    pub reward: Reward,
+    pub completed_at: u64,
+}
+
+//reward track of a battle pass, the premium track needs the premium pass of the season
+#[derive(candid::CandidType, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BattlePassTrack {
+    Free,
+    Premium,
+}
+
+//tier of a battle pass, unlocked once a player has earned enough XP during the season
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct BattlePassTier {
+    pub xp_required: u64,
+    pub free_reward: Option<Reward>,
+    pub premium_reward: Option<Reward>,
+}
+
+//struct to store the battle pass of a season, tiers are numbered from 1
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct BattlePass {
+    pub season_id: u64,
+    pub premium_price: u64, //soft currency
+    pub tiers: Vec<BattlePassTier>,
+}
+
+//struct to store a player's battle pass progress during a season
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct BattlePassProgress {
+    pub season_id: u64,
+    pub player_id: u64,
+    pub xp: u64, //XP earned from the matches completed during the season
+    pub premium: bool,
+    pub claimed_free_tiers: Vec<u64>,
+    pub claimed_premium_tiers: Vec<u64>,
+}
+
+//battle pass reward claimed by a player, it has been granted
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct BattlePassClaim {
+    pub season_id: u64,
+    pub player_id: u64,
+    pub tier: u64,
+    pub track: BattlePassTrack,
+    pub reward: Reward,
+    pub claimed_at: u64, //nanoseconds since the epoch
+}
+
+//share of the players that unlocked an achievement
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct AchievementStats {
+    pub achievement_id: u64,
+    pub name: String,
+    pub unlocked_count: u64,
+    pub unlock_percentage: f64,
+}
+
+//roles that can be granted to a principal, admins implicitly hold every role
+#[derive(candid::CandidType, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Role {
+    Admin,
+    Designer,   //manages the weapon catalog
+    GameServer, //records matches and leaderboard entries
+    Moderator,  //manages player profiles on behalf of their owners
+}
+
+//struct to store the roles granted to a principal
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct RoleAssignment {
+    pub principal: Principal,
+    pub roles: Vec<Role>,
+}
+
+//wrapper so that a principal can be used as a stable map key
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StorablePrincipal(pub Principal);
+
+//records written before the versioned envelope existed are bare Candid and start with this magic
+const LEGACY_CANDID_MAGIC: &[u8] = b"DIDL";
+
+//Implemented by every record kept in stable memory. Records are stored as a one byte
+//schema version followed by the Candid encoding of that version's layout, so that older
+//layouts can still be decoded and migrated after an upgrade.
+pub trait Versioned: CandidType + DeserializeOwned {
+    //name used in error messages
+    const NAME: &'static str;
+
+    //version written in front of newly stored records
+    const VERSION: u8;
+
+    //largest encoded size accepted when storing a record, including the version byte
+    const MAX_SIZE: u32;
+
+    //decode a record stored with an older version and convert it to the current layout
+    fn migrate(version: u8, bytes: &[u8]) -> Result<Self, candid::Error>;
+}
+
+// helper function to encode a record inside a versioned envelope
+pub fn encode_versioned<T: Versioned>(record: &T) -> Vec<u8> {
+    let mut bytes = vec![T::VERSION];
+    bytes.extend(Encode!(record).expect("cannot encode record"));
+    bytes
+}
+
+// helper function to decode a record from its versioned envelope, migrating older versions
+pub fn decode_versioned<T: Versioned>(bytes: &[u8]) -> Result<T, candid::Error> {
+    if bytes.starts_with(LEGACY_CANDID_MAGIC) {
+        T::migrate(0, bytes)
+    } else {
+        match bytes.split_first() {
+            Some((&version, payload)) if version == T::VERSION => Decode!(payload, T),
+            Some((&version, payload)) => T::migrate(version, payload),
+            None => Err(candid::Error::msg("empty record")),
+        }
+    }
+}
+
+// helper function to build the error returned for versions a type does not know about
+fn unsupported_version<T: Versioned>(version: u8) -> candid::Error {
+    candid::Error::msg(format!("unsupported {} version {}", T::NAME, version))
+}
+
+//player profile layout before owners were recorded
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct PlayerProfileV0 {
+    pub name: String,
+    pub id: u64,
     pub score: u64,
     pub level: u64,
     pub rank: u64,
-    pub time: u64, //time taken to complete match in minutes
+    pub weapons: Vec<Weapon>,
+    pub match_history: Vec<MatchV1>,
+}
+
+//player profile layout that embedded copies of owned weapons and played matches
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct PlayerProfileV1 {
+    pub name: String,
+    pub id: u64,
+    pub score: u64,
+    pub level: u64,
+    pub rank: u64,
+    pub owner: Principal,
+    pub weapons: Vec<Weapon>,
+    pub match_history: Vec<MatchV1>,
+}
+
+//profiles created before owners were recorded are owned by the management canister,
+//which never calls in, so only moderators can modify them
+impl From<PlayerProfileV0> for PlayerProfileV1 {
+    fn from(profile: PlayerProfileV0) -> Self {
+        PlayerProfileV1 {
+            name: profile.name,
+            id: profile.id,
+            score: profile.score,
+            level: profile.level,
+            rank: profile.rank,
+            owner: Principal::management_canister(),
+            weapons: profile.weapons,
+            match_history: profile.match_history,
+        }
+    }
+}
+
+//embedded weapons and matches move to the relation maps during the v1 to v2 migration
+impl From<PlayerProfileV1> for PlayerProfile {
+    fn from(profile: PlayerProfileV1) -> Self {
+        PlayerProfile {
+            name: profile.name,
+            id: profile.id,
+            score: profile.score,
+            level: profile.level,
+            rank: profile.rank,
+            owner: profile.owner,
+        }
+    }
+}
+
+//match layout with a single player
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct MatchV1 {
+    pub id: u64,
+    pub player_id: u64,
+    pub weapon_id: u64,
+    pub score: u64,
+    pub level: u64,
+    pub rank: u64,
+    pub time: u64,
     pub result: bool,
 }
 
-//struct to store leaderboard
-#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
-pub struct Leaderboard {
-    pub id: u64,
-    pub player_id: u64,
-    pub score: u64,
-    pub level: u64,
-    pub rank: u64,
+//match layout before the match lifecycle, every match was recorded once finished
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct MatchV2 {
+    pub id: u64,
+    pub participants: Vec<ParticipantV1>,
+    pub level: u64,
+    pub rank: u64,
+    pub time: u64,
+}
+
+//match layout before participants recorded the damage they dealt
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct MatchV3 {
+    pub id: u64,
+    pub participants: Vec<ParticipantV1>,
+    pub level: u64,
+    pub rank: u64,
+    pub time: u64,
+    pub status: MatchStatus,
+    pub transitions: Vec<MatchTransition>,
+}
+
+//participant layout before the damage dealt was recorded
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct ParticipantV1 {
+    pub player_id: u64,
+    pub team: u64,
+    pub weapon_id: u64,
+    pub score: u64,
+    pub kills: u64,
+    pub deaths: u64,
+    pub assists: u64,
+    pub outcome: MatchOutcome,
+}
+
+//token purchase layout before purchases could be refunded
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct TokenPurchaseV1 {
+    pub id: u64,
+    pub player_id: u64,
+    pub weapon_id: u64,
+    pub buyer: Principal,
+    pub ledger: Principal,
+    pub amount: u64,
+    pub block_index: candid::Nat,
+    pub timestamp: u64,
+}
+
+//purchases recorded before refunds existed always granted their weapon
+impl From<TokenPurchaseV1> for TokenPurchaseV2 {
+    fn from(purchase: TokenPurchaseV1) -> Self {
+        TokenPurchaseV2 {
+            id: purchase.id,
+            player_id: purchase.player_id,
+            weapon_id: purchase.weapon_id,
+            buyer: purchase.buyer,
+            ledger: purchase.ledger,
+            amount: purchase.amount,
+            block_index: purchase.block_index,
+            timestamp: purchase.timestamp,
+            status: TokenPurchaseStatus::Completed,
+        }
+    }
+}
+
+//token purchase layout before ledger transfers were deduplicated
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct TokenPurchaseV2 {
+    pub id: u64,
+    pub player_id: u64,
+    pub weapon_id: u64,
+    pub buyer: Principal,
+    pub ledger: Principal,
+    pub amount: u64,
+    pub block_index: candid::Nat,
+    pub timestamp: u64,
+    pub status: TokenPurchaseStatus,
+}
+
+//purchases recorded before deduplication were confirmed by the ledger and have no refund in flight
+impl From<TokenPurchaseV2> for TokenPurchase {
+    fn from(purchase: TokenPurchaseV2) -> Self {
+        TokenPurchase {
+            id: purchase.id,
+            player_id: purchase.player_id,
+            weapon_id: purchase.weapon_id,
+            buyer: purchase.buyer,
+            ledger: purchase.ledger,
+            amount: purchase.amount,
+            block_index: Some(purchase.block_index),
+            timestamp: purchase.timestamp,
+            memo: purchase.id.to_be_bytes().to_vec(),
+            refund: None,
+            status: purchase.status,
+        }
+    }
+}
+
+//leaderboard entry layout before seasons
+#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct LeaderboardV1 {
+    pub id: u64,
+    pub player_id: u64,
+    pub score: u64,
+    pub level: u64,
+    pub rank: u64,
+}
+
+//entries created before seasons existed belong to no season
+impl From<LeaderboardV1> for Leaderboard {
+    fn from(leaderboard: LeaderboardV1) -> Self {
+        Leaderboard {
+            id: leaderboard.id,
+            player_id: leaderboard.player_id,
+            score: leaderboard.score,
+            level: leaderboard.level,
+            rank: leaderboard.rank,
+            season_id: None,
+        }
+    }
+}
+
+//single player matches become matches with one participant on team 0
+impl From<MatchV1> for MatchV2 {
+    fn from(match_profile: MatchV1) -> Self {
+        MatchV2 {
+            id: match_profile.id,
+            participants: vec![ParticipantV1 {
+                player_id: match_profile.player_id,
+                team: 0,
+                weapon_id: match_profile.weapon_id,
+                score: match_profile.score,
+                kills: 0,
+                deaths: 0,
+                assists: 0,
+                outcome: if match_profile.result {
+                    MatchOutcome::Win
+                } else {
+                    MatchOutcome::Loss
+                },
+            }],
+            level: match_profile.level,
+            rank: match_profile.rank,
+            time: match_profile.time,
+        }
+    }
+}
+
+//matches recorded before the lifecycle existed are completed, their transition times are unknown
+impl From<MatchV2> for MatchV3 {
+    fn from(match_profile: MatchV2) -> Self {
+        MatchV3 {
+            id: match_profile.id,
+            participants: match_profile.participants,
+            level: match_profile.level,
+            rank: match_profile.rank,
+            time: match_profile.time,
+            status: MatchStatus::Completed,
+            transitions: Vec::new(),
+        }
+    }
+}
+
+//damage dealt in matches recorded before it was tracked is unknown and counted as zero
+impl From<MatchV3> for Match {
+    fn from(match_profile: MatchV3) -> Self {
+        Match {
+            id: match_profile.id,
+            participants: match_profile
+                .participants
+                .into_iter()
+                .map(|participant| Participant {
+                    player_id: participant.player_id,
+                    team: participant.team,
+                    weapon_id: participant.weapon_id,
+                    score: participant.score,
+                    kills: participant.kills,
+                    deaths: participant.deaths,
+                    assists: participant.assists,
+                    damage: 0,
+                    outcome: participant.outcome,
+                })
+                .collect(),
+            level: match_profile.level,
+            rank: match_profile.rank,
+            time: match_profile.time,
+            status: match_profile.status,
+            transitions: match_profile.transitions,
+        }
+    }
+}
+
+//Implement Versioned for every stored record
+impl Versioned for PlayerProfile {
+    const NAME: &'static str = "PlayerProfile";
+    const VERSION: u8 = 2;
+    const MAX_SIZE: u32 = 1024;
+
+    fn migrate(version: u8, bytes: &[u8]) -> Result<Self, candid::Error> {
+        match version {
+            0 => PlayerProfileV1::migrate(version, bytes).map(PlayerProfile::from),
+            1 => Decode!(bytes, PlayerProfileV1).map(PlayerProfile::from),
+            _ => Err(unsupported_version::<Self>(version)),
+        }
+    }
+}
+
+impl Versioned for PlayerProfileV1 {
+    const NAME: &'static str = "PlayerProfile";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 1024;
+
+    fn migrate(version: u8, bytes: &[u8]) -> Result<Self, candid::Error> {
+        match version {
+            0 => Decode!(bytes, PlayerProfileV0).map(PlayerProfileV1::from),
+            _ => Err(unsupported_version::<Self>(version)),
+        }
+    }
+}
+
+impl Versioned for Weapon {
+    const NAME: &'static str = "Weapon";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 1024;
+
+    fn migrate(version: u8, bytes: &[u8]) -> Result<Self, candid::Error> {
+        match version {
+            0 => Decode!(bytes, Weapon),
+            _ => Err(unsupported_version::<Self>(version)),
+        }
+    }
+}
+
+impl Versioned for Match {
+    const NAME: &'static str = "Match";
+    const VERSION: u8 = 4;
+    const MAX_SIZE: u32 = 8 * 1024;
+
+    fn migrate(version: u8, bytes: &[u8]) -> Result<Self, candid::Error> {
+        match version {
+            0 | 1 => Decode!(bytes, MatchV1)
+                .map(MatchV2::from)
+                .map(MatchV3::from)
+                .map(Match::from),
+            2 => Decode!(bytes, MatchV2).map(MatchV3::from).map(Match::from),
+            3 => Decode!(bytes, MatchV3).map(Match::from),
+            _ => Err(unsupported_version::<Self>(version)),
+        }
+    }
+}
+
+impl Versioned for Leaderboard {
+    const NAME: &'static str = "Leaderboard";
+    const VERSION: u8 = 2;
+    const MAX_SIZE: u32 = 1024;
+
+    fn migrate(version: u8, bytes: &[u8]) -> Result<Self, candid::Error> {
+        match version {
+            0 | 1 => Decode!(bytes, LeaderboardV1).map(Leaderboard::from),
+            _ => Err(unsupported_version::<Self>(version)),
+        }
+    }
+}
+
+impl Versioned for RoleAssignment {
+    const NAME: &'static str = "RoleAssignment";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 1024;
+
+    fn migrate(version: u8, bytes: &[u8]) -> Result<Self, candid::Error> {
+        match version {
+            0 => Decode!(bytes, RoleAssignment),
+            _ => Err(unsupported_version::<Self>(version)),
+        }
+    }
+}
+
+impl Versioned for PlayerStanding {
+    const NAME: &'static str = "PlayerStanding";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 256;
+
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
+    }
+}
+
+impl Versioned for LeaderboardEntry {
+    const NAME: &'static str = "LeaderboardEntry";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 256;
+
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
+    }
+}
+
+impl Versioned for RatingConfig {
+    const NAME: &'static str = "RatingConfig";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 256;
+
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
+    }
 }
 
-//Implement Storable and BoundedStorable for PlayerProfile
-impl Storable for PlayerProfile {
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+impl Versioned for PlayerRating {
+    const NAME: &'static str = "PlayerRating";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 256;
+
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
     }
+}
+
+impl Versioned for RatingChange {
+    const NAME: &'static str = "RatingChange";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 256;
 
-    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
     }
 }
 
-impl BoundedStorable for PlayerProfile {
+impl Versioned for Season {
+    const NAME: &'static str = "Season";
+    const VERSION: u8 = 1;
     const MAX_SIZE: u32 = 1024;
-    const IS_FIXED_SIZE: bool = false;
+
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
+    }
+}
+
+impl Versioned for SeasonEnd {
+    const NAME: &'static str = "SeasonEnd";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 2048;
+
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
+    }
+}
+
+impl Versioned for SeasonConfig {
+    const NAME: &'static str = "SeasonConfig";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 128;
+
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
+    }
+}
+
+impl Versioned for PaymentConfig {
+    const NAME: &'static str = "PaymentConfig";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 128;
+
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
+    }
+}
+
+impl Versioned for TokenPurchase {
+    const NAME: &'static str = "TokenPurchase";
+    const VERSION: u8 = 3;
+    const MAX_SIZE: u32 = 512;
+
+    fn migrate(version: u8, bytes: &[u8]) -> Result<Self, candid::Error> {
+        match version {
+            1 => Decode!(bytes, TokenPurchaseV1)
+                .map(TokenPurchaseV2::from)
+                .map(TokenPurchase::from),
+            2 => Decode!(bytes, TokenPurchaseV2).map(TokenPurchase::from),
+            _ => Err(unsupported_version::<Self>(version)),
+        }
+    }
+}
+
+impl Versioned for TradeOffer {
+    const NAME: &'static str = "TradeOffer";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 2 * 1024;
+
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
+    }
+}
+
+impl Versioned for Listing {
+    const NAME: &'static str = "Listing";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 512;
+
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
+    }
+}
+
+impl Versioned for MarketConfig {
+    const NAME: &'static str = "MarketConfig";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 128;
+
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
+    }
+}
+
+impl Versioned for LevelingConfig {
+    const NAME: &'static str = "LevelingConfig";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 4 * 1024;
+
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
+    }
+}
+
+impl Versioned for Achievement {
+    const NAME: &'static str = "Achievement";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 2 * 1024;
+
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
+    }
 }
 
-//Implement Storable and BoundedStorable for Weapon
-impl Storable for Weapon {
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+impl Versioned for QuestTemplate {
+    const NAME: &'static str = "QuestTemplate";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 2 * 1024;
+
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
     }
+}
 
-    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+impl Versioned for QuestRotation {
+    const NAME: &'static str = "QuestRotation";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 1024;
+
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
     }
 }
 
-impl BoundedStorable for Weapon {
+impl Versioned for QuestRotationFailure {
+    const NAME: &'static str = "QuestRotationFailure";
+    const VERSION: u8 = 1;
     const MAX_SIZE: u32 = 1024;
-    const IS_FIXED_SIZE: bool = false;
+
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
+    }
+}
+
+impl Versioned for QuestProgress {
+    const NAME: &'static str = "QuestProgress";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 256;
+
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
+    }
 }
 
-//Implement Storable and BoundedStorable for Match
+impl Versioned for BattlePass {
+    const NAME: &'static str = "BattlePass";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 16 * 1024;
 
-impl Storable for Match {
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
     }
+}
+
+impl Versioned for BattlePassProgress {
+    const NAME: &'static str = "BattlePassProgress";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 4 * 1024;
 
-    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
     }
 }
 
-impl BoundedStorable for Match {
+impl Versioned for Attachment {
+    const NAME: &'static str = "Attachment";
+    const VERSION: u8 = 1;
     const MAX_SIZE: u32 = 1024;
-    const IS_FIXED_SIZE: bool = false;
+
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
+    }
+}
+
+impl Versioned for WeaponAnalyticsConfig {
+    const NAME: &'static str = "WeaponAnalyticsConfig";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 128;
+
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
+    }
+}
+
+impl Versioned for WeaponTelemetry {
+    const NAME: &'static str = "WeaponTelemetry";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 256;
+
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
+    }
+}
+
+impl Versioned for TelemetryConfig {
+    const NAME: &'static str = "TelemetryConfig";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 128;
+
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
+    }
 }
 
-//Implement Storable and BoundedStorable for Leaderboard
-impl Storable for Leaderboard {
-    fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+impl Versioned for WeaponRevision {
+    const NAME: &'static str = "WeaponRevision";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 2048;
+
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
     }
+}
 
-    fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+impl Versioned for BalancePatch {
+    const NAME: &'static str = "BalancePatch";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 4096;
+
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
     }
 }
 
-impl BoundedStorable for Leaderboard {
+impl Versioned for Loadout {
+    const NAME: &'static str = "Loadout";
+    const VERSION: u8 = 1;
     const MAX_SIZE: u32 = 1024;
-    const IS_FIXED_SIZE: bool = false;
+
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
+    }
 }
 
-//weapon profile payload
+impl Versioned for Transaction {
+    const NAME: &'static str = "Transaction";
+    const VERSION: u8 = 1;
+    const MAX_SIZE: u32 = 1024;
+
+    fn migrate(version: u8, _bytes: &[u8]) -> Result<Self, candid::Error> {
+        Err(unsupported_version::<Self>(version))
+    }
+}
+
+//Implement Storable for StorablePrincipal
+impl Storable for StorablePrincipal {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.0.as_slice())
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        StorablePrincipal(Principal::from_slice(bytes.as_ref()))
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 29,
+        is_fixed_size: false,
+    };
+}
+
+//raw bytes of a record kept in stable memory, decoded on access so that a record which cannot be
+//decoded is reported as an error instead of trapping inside the map
+pub struct Stored<T> {
+    bytes: Vec<u8>,
+    record: PhantomData<T>,
+}
+
+impl<T: Versioned> Stored<T> {
+    // encode a record, rejecting it when it is larger than its type allows
+    pub fn new(record: &T) -> Result<Self, Error> {
+        let bytes = encode_versioned(record);
+        if bytes.len() > T::MAX_SIZE as usize {
+            return Err(Error::PayloadTooLarge {
+                msg: format!("{} cannot be stored", T::NAME),
+                size: bytes.len() as u64,
+                max_size: T::MAX_SIZE as u64,
+            });
+        }
+        Ok(Stored {
+            bytes,
+            record: PhantomData,
+        })
+    }
+
+    // decode the record, migrating it from an older version if needed
+    pub fn decode(&self) -> Result<T, Error> {
+        decode_versioned(&self.bytes).map_err(|err| Error::DecodeFailed {
+            msg: format!("cannot decode {}: {}", T::NAME, err),
+        })
+    }
+}
+
+impl<T> Stored<T> {
+    // read the same bytes as a record of another layout, for migrations of records that share a map
+    pub fn cast<U>(self) -> Stored<U> {
+        Stored {
+            bytes: self.bytes,
+            record: PhantomData,
+        }
+    }
+}
+
+//records are unbounded in stable memory, the size limit is enforced by Stored::new so it can be
+//raised later without corrupting existing maps
+impl<T> Storable for Stored<T> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.bytes)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Stored {
+            bytes: bytes.into_owned(),
+            record: PhantomData,
+        }
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+//read and write helpers for maps that hold Stored records
+pub trait RecordMap<K, T> {
+    fn get_record(&self, key: &K) -> Result<Option<T>, Error>;
+    fn insert_record(&mut self, key: K, record: &T) -> Result<(), Error>;
+    fn records(&self) -> Result<Vec<T>, Error>;
+    fn page(&self, cursor: Option<K>, limit: usize) -> Result<Page<K, T>, Error>;
+}
+
+impl<K, T> RecordMap<K, T> for StableBTreeMap<K, Stored<T>, Memory>
+where
+    K: Storable + Ord + Clone,
+    T: Versioned,
+{
+    fn get_record(&self, key: &K) -> Result<Option<T>, Error> {
+        self.get(key).map(|stored| stored.decode()).transpose()
+    }
+
+    fn insert_record(&mut self, key: K, record: &T) -> Result<(), Error> {
+        self.insert(key, Stored::new(record)?);
+        Ok(())
+    }
+
+    fn records(&self) -> Result<Vec<T>, Error> {
+        self.iter().map(|(_, stored)| stored.decode()).collect()
+    }
+
+    fn page(&self, cursor: Option<K>, limit: usize) -> Result<Page<K, T>, Error> {
+        let mut entries = match cursor {
+            Some(cursor) => self.range(cursor..),
+            None => self.iter(),
+        };
+        let items = entries
+            .by_ref()
+            .take(limit)
+            .map(|(_, stored)| stored.decode())
+            .collect::<Result<Vec<T>, Error>>()?;
+        Ok(Page {
+            items,
+            next_cursor: entries.next().map(|(key, _)| key),
+        })
+    }
+}
+
+//map keyed by (owner_id, related_id) pairs with a reverse index of (related_id, owner_id) pairs, so
+//that the entries pointing at a deleted record are found by range. Reads go to the entries through
+//Deref, writes go through the methods below to keep the index in step
+pub struct RelationMap<V: Storable> {
+    entries: StableBTreeMap<(u64, u64), V, Memory>,
+    related: StableBTreeMap<(u64, u64), (), Memory>,
+}
+
+impl<V: Storable> RelationMap<V> {
+    pub fn init(entries_memory: Memory, related_memory: Memory) -> Self {
+        RelationMap {
+            entries: StableBTreeMap::init(entries_memory),
+            related: StableBTreeMap::init(related_memory),
+        }
+    }
+
+    pub fn insert(&mut self, key: (u64, u64), value: V) -> Option<V> {
+        self.related.insert((key.1, key.0), ());
+        self.entries.insert(key, value)
+    }
+
+    pub fn remove(&mut self, key: &(u64, u64)) -> Option<V> {
+        self.related.remove(&(key.1, key.0));
+        self.entries.remove(key)
+    }
+
+    // ids of the owners with an entry pointing at a related id
+    pub fn owner_ids(&self, related_id: u64) -> Vec<u64> {
+        self.related
+            .range((related_id, 0)..=(related_id, u64::MAX))
+            .map(|((_, owner_id), _)| owner_id)
+            .collect()
+    }
+
+    // remove every entry of an owner
+    pub fn remove_owner(&mut self, owner_id: u64) {
+        let related_ids: Vec<u64> = self
+            .entries
+            .range((owner_id, 0)..=(owner_id, u64::MAX))
+            .map(|((_, related_id), _)| related_id)
+            .collect();
+        for related_id in related_ids {
+            self.remove(&(owner_id, related_id));
+        }
+    }
+
+    // remove every entry pointing at a related id
+    pub fn remove_related_id(&mut self, related_id: u64) {
+        for owner_id in self.owner_ids(related_id) {
+            self.remove(&(owner_id, related_id));
+        }
+    }
+
+    // build the reverse index of entries written before it existed
+    pub fn index_entries(&mut self) {
+        let keys: Vec<(u64, u64)> = self.entries.iter().map(|(key, _)| key).collect();
+        for (owner_id, related_id) in keys {
+            self.related.insert((related_id, owner_id), ());
+        }
+    }
+}
+
+impl<T: Versioned> RelationMap<Stored<T>> {
+    pub fn insert_record(&mut self, key: (u64, u64), record: &T) -> Result<(), Error> {
+        self.entries.insert_record(key, record)?;
+        self.related.insert((key.1, key.0), ());
+        Ok(())
+    }
+}
+
+impl<V: Storable> Deref for RelationMap<V> {
+    type Target = StableBTreeMap<(u64, u64), V, Memory>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entries
+    }
+}
+
+//one page of a paginated query, pass next_cursor back to read the following page
 #[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+pub struct Page<K, T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<K>, //None when this is the last page
+}
+
+//weapon profile payload
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
 pub struct WeaponProfilePayload {
     pub name: String,
     pub damage: u64,
@@ -138,27 +1894,106 @@ pub struct WeaponProfilePayload {
     pub rank: u64,
 }
 //player profile payload
-#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
 pub struct PlayerProfilePayload {
     pub name: String,
     pub score: u64,
-    pub level: u64,
     pub rank: u64,
 }
+//attachment payload
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct AttachmentPayload {
+    pub name: String,
+    pub kind: AttachmentKind,
+    pub modifiers: StatModifiers,
+    pub price: u64,
+}
+
+//loadout payload
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct LoadoutPayload {
+    pub player_id: u64,
+    pub name: String,
+    pub primary: Option<u64>,
+    pub secondary: Option<u64>,
+    pub melee: Option<u64>,
+}
+
 //match profile payload
-#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
 pub struct MatchProfilePayload {
-    pub player_id: u64,
-    pub weapon_id: u64,
-    pub score: u64,
+    pub participants: Vec<Participant>,
     pub level: u64,
     pub rank: u64,
     pub time: u64, //time taken to complete match in minutes
-    pub result: bool, // 
+}
+
+//trade offer payload, the offer expires expires_in_seconds after it is created
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct TradeOfferPayload {
+    pub from_player_id: u64,
+    pub to_player_id: u64,
+    pub offered_weapon_ids: Vec<u64>,
+    pub offered_currency: u64,
+    pub requested_weapon_ids: Vec<u64>,
+    pub requested_currency: u64,
+    pub expires_in_seconds: u64,
+}
+
+//marketplace listing payload
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct ListingPayload {
+    pub seller_player_id: u64,
+    pub weapon_id: u64,
+    pub price: u64,
+}
+
+//achievement payload
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct AchievementPayload {
+    pub name: String,
+    pub description: String,
+    pub badge: Option<String>,
+    pub metric: MatchMetric,
+    pub threshold: u64,
+    pub min_weapon_rank: Option<u64>,
+}
+
+//quest template payload
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct QuestTemplatePayload {
+    pub name: String,
+    pub description: String,
+    pub cadence: QuestCadence,
+    pub metric: MatchMetric,
+    pub target: u64,
+    pub reward: Reward,
+}
+
+//battle pass payload
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct BattlePassPayload {
+    pub premium_price: u64,
+    pub tiers: Vec<BattlePassTier>,
+}
+
+//season payload
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct SeasonPayload {
+    pub name: String,
+    pub starts_at: u64, //nanoseconds since the epoch
+    pub ends_at: u64,   //nanoseconds since the epoch
+}
+
+//balance patch payload
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
+pub struct BalancePatchPayload {
+    pub name: String,
+    pub notes: String,
 }
 
 //leaderboard payload
-#[derive(candid::CandidType, Clone, Serialize, Deserialize)]
+#[derive(candid::CandidType, Clone, Debug, Serialize, Deserialize)]
 pub struct LeaderboardPayload {
     pub player_id: u64,
     pub score: u64,
@@ -167,11 +2002,262 @@ pub struct LeaderboardPayload {
 }
 
 // Error type for the service
-#[derive(candid::CandidType, Deserialize, Serialize)]
+#[derive(candid::CandidType, Debug, Deserialize, Serialize)]
 pub enum  Error {
     NotFound { msg: String },
+    Unauthorized { msg: String },
+    InvalidRoleAssignment { msg: String },
+    PayloadTooLarge { msg: String, size: u64, max_size: u64 },
+    DecodeFailed { msg: String },
+    InvalidRatingConfig { msg: String, payload: RatingConfig },
+    InvalidMarketConfig { msg: String, payload: MarketConfig },
+    InvalidLevelingConfig { msg: String, payload: LevelingConfig },
+    InvalidWeaponAnalyticsConfig { msg: String, payload: WeaponAnalyticsConfig },
+    InvalidTelemetryConfig { msg: String, payload: TelemetryConfig },
+    InvalidSeasonConfig { msg: String, payload: SeasonConfig },
+    InvalidMatchTransition { msg: String, status: MatchStatus },
+    InsufficientFunds { msg: String, balance: u64, price: u64 },
+    RequirementNotMet { msg: String },
+    AlreadyClaimed { msg: String },
+    InvalidTransaction { msg: String },
+    PaymentFailed { msg: String },
     InvalidPlayerPayload{msg: String, payload: PlayerProfilePayload},
     InvalidWeaponPayload{msg: String, payload: WeaponProfilePayload},
     InvalidMatchPayload{msg: String, payload: MatchProfilePayload},
-    InvalidLeaderboardPayload{msg: String, payload: LeaderboardPayload}
-}
\ No newline at end of file
+    InvalidLoadoutPayload{msg: String, payload: LoadoutPayload},
+    InvalidAttachmentPayload{msg: String, payload: AttachmentPayload},
+    InvalidLeaderboardPayload{msg: String, payload: LeaderboardPayload},
+    InvalidSeasonPayload{msg: String, payload: SeasonPayload},
+    InvalidBalancePatchPayload{msg: String, payload: BalancePatchPayload},
+    InvalidBattlePassPayload{msg: String, payload: Box<BattlePassPayload>},
+    InvalidTradeOfferPayload{msg: String, payload: TradeOfferPayload},
+    InvalidListingPayload{msg: String, payload: ListingPayload},
+    InvalidAchievementPayload{msg: String, payload: Box<AchievementPayload>},
+    InvalidQuestTemplatePayload{msg: String, payload: Box<QuestTemplatePayload>}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // fixture of a record stored with an explicit version byte
+    fn versioned(version: u8, bytes: Vec<u8>) -> Vec<u8> {
+        let mut record = vec![version];
+        record.extend(bytes);
+        record
+    }
+
+    fn weapon() -> Weapon {
+        Weapon {
+            name: "Rifle".to_string(),
+            id: 3,
+            damage: 30,
+            ammo: 30,
+            range: 400,
+            fire_rate: 10,
+            reload_time: 2,
+            accuracy: 80,
+            price: 500,
+            level: 1,
+            rank: 1,
+        }
+    }
+
+    fn match_v1() -> MatchV1 {
+        MatchV1 {
+            id: 7,
+            player_id: 1,
+            weapon_id: 3,
+            score: 120,
+            level: 4,
+            rank: 2,
+            time: 15,
+            result: true,
+        }
+    }
+
+    fn participant_v1() -> ParticipantV1 {
+        ParticipantV1 {
+            player_id: 1,
+            team: 2,
+            weapon_id: 3,
+            score: 120,
+            kills: 5,
+            deaths: 1,
+            assists: 2,
+            outcome: MatchOutcome::Draw,
+        }
+    }
+
+    fn leaderboard_v1() -> LeaderboardV1 {
+        LeaderboardV1 {
+            id: 9,
+            player_id: 1,
+            score: 300,
+            level: 4,
+            rank: 2,
+        }
+    }
+
+    #[test]
+    fn decodes_legacy_player_profile() {
+        let profile = PlayerProfileV0 {
+            name: "Ghost".to_string(),
+            id: 1,
+            score: 10,
+            level: 2,
+            rank: 3,
+            weapons: vec![weapon()],
+            match_history: vec![match_v1()],
+        };
+        let decoded: PlayerProfile = decode_versioned(&Encode!(&profile).unwrap()).unwrap();
+        assert_eq!(decoded.name, "Ghost");
+        assert_eq!((decoded.id, decoded.score, decoded.level, decoded.rank), (1, 10, 2, 3));
+        assert_eq!(decoded.owner, Principal::management_canister());
+    }
+
+    #[test]
+    fn decodes_player_profile_v1() {
+        let owner = Principal::from_slice(&[1, 2, 3]);
+        let profile = PlayerProfileV1 {
+            name: "Ghost".to_string(),
+            id: 1,
+            score: 10,
+            level: 2,
+            rank: 3,
+            owner,
+            weapons: vec![weapon()],
+            match_history: Vec::new(),
+        };
+        let decoded: PlayerProfile = decode_versioned(&versioned(1, Encode!(&profile).unwrap())).unwrap();
+        assert_eq!(decoded.id, 1);
+        assert_eq!(decoded.owner, owner);
+    }
+
+    #[test]
+    fn decodes_current_player_profile() {
+        let profile = PlayerProfile {
+            name: "Ghost".to_string(),
+            id: 1,
+            score: 10,
+            level: 2,
+            rank: 3,
+            owner: Principal::anonymous(),
+        };
+        let decoded: PlayerProfile = decode_versioned(&encode_versioned(&profile)).unwrap();
+        assert_eq!(decoded.name, profile.name);
+        assert_eq!(decoded.owner, profile.owner);
+    }
+
+    #[test]
+    fn decodes_legacy_match() {
+        let decoded: Match = decode_versioned(&Encode!(&match_v1()).unwrap()).unwrap();
+        assert_eq!((decoded.id, decoded.level, decoded.rank, decoded.time), (7, 4, 2, 15));
+        assert_eq!(decoded.status, MatchStatus::Completed);
+        assert!(decoded.transitions.is_empty());
+        assert_eq!(decoded.participants.len(), 1);
+        let participant = &decoded.participants[0];
+        assert_eq!((participant.player_id, participant.team, participant.weapon_id), (1, 0, 3));
+        assert_eq!((participant.score, participant.damage), (120, 0));
+        assert_eq!(participant.outcome, MatchOutcome::Win);
+    }
+
+    #[test]
+    fn decodes_match_v1() {
+        let match_profile = MatchV1 {
+            result: false,
+            ..match_v1()
+        };
+        let decoded: Match = decode_versioned(&versioned(1, Encode!(&match_profile).unwrap())).unwrap();
+        assert_eq!(decoded.participants[0].outcome, MatchOutcome::Loss);
+        assert_eq!(decoded.status, MatchStatus::Completed);
+    }
+
+    #[test]
+    fn decodes_match_v2() {
+        let match_profile = MatchV2 {
+            id: 7,
+            participants: vec![participant_v1()],
+            level: 4,
+            rank: 2,
+            time: 15,
+        };
+        let decoded: Match = decode_versioned(&versioned(2, Encode!(&match_profile).unwrap())).unwrap();
+        assert_eq!(decoded.status, MatchStatus::Completed);
+        let participant = &decoded.participants[0];
+        assert_eq!((participant.team, participant.kills, participant.deaths, participant.assists), (2, 5, 1, 2));
+        assert_eq!(participant.damage, 0);
+        assert_eq!(participant.outcome, MatchOutcome::Draw);
+    }
+
+    #[test]
+    fn decodes_match_v3() {
+        let match_profile = MatchV3 {
+            id: 7,
+            participants: vec![participant_v1()],
+            level: 4,
+            rank: 2,
+            time: 15,
+            status: MatchStatus::Disputed,
+            transitions: vec![MatchTransition {
+                status: MatchStatus::Disputed,
+                timestamp: 42,
+                caller: Principal::anonymous(),
+                note: Some("wrong score".to_string()),
+            }],
+        };
+        let decoded: Match = decode_versioned(&versioned(3, Encode!(&match_profile).unwrap())).unwrap();
+        assert_eq!(decoded.status, MatchStatus::Disputed);
+        assert_eq!(decoded.transitions.len(), 1);
+        assert_eq!(decoded.transitions[0].note.as_deref(), Some("wrong score"));
+        assert_eq!(decoded.participants[0].damage, 0);
+    }
+
+    #[test]
+    fn decodes_legacy_leaderboard() {
+        let decoded: Leaderboard = decode_versioned(&Encode!(&leaderboard_v1()).unwrap()).unwrap();
+        assert_eq!((decoded.id, decoded.player_id, decoded.score), (9, 1, 300));
+        assert_eq!(decoded.season_id, None);
+    }
+
+    #[test]
+    fn decodes_leaderboard_v1() {
+        let decoded: Leaderboard = decode_versioned(&versioned(1, Encode!(&leaderboard_v1()).unwrap())).unwrap();
+        assert_eq!((decoded.level, decoded.rank), (4, 2));
+        assert_eq!(decoded.season_id, None);
+    }
+
+    #[test]
+    fn decodes_legacy_weapon() {
+        let decoded: Weapon = decode_versioned(&Encode!(&weapon()).unwrap()).unwrap();
+        assert_eq!(decoded.name, "Rifle");
+        assert_eq!(decoded.damage, 30);
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let bytes = versioned(99, Encode!(&leaderboard_v1()).unwrap());
+        let error = decode_versioned::<Leaderboard>(&bytes).err().unwrap();
+        assert!(error.to_string().contains("unsupported Leaderboard version 99"));
+        assert!(decode_versioned::<Match>(&versioned(99, Encode!(&match_v1()).unwrap())).is_err());
+        assert!(decode_versioned::<PlayerProfile>(&[]).is_err());
+    }
+
+    #[test]
+    fn relation_map_finds_entries_by_related_id() {
+        use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+        let memory_manager = MemoryManager::init(DefaultMemoryImpl::default());
+        let mut relations: RelationMap<()> =
+            RelationMap::init(memory_manager.get(MemoryId::new(0)), memory_manager.get(MemoryId::new(1)));
+        for key in [(1, 7), (2, 7), (2, 8), (3, 9)] {
+            relations.insert(key, ());
+        }
+
+        assert_eq!(relations.owner_ids(7), vec![1, 2]);
+        relations.remove_related_id(7);
+        assert_eq!(relations.iter().map(|(key, _)| key).collect::<Vec<_>>(), vec![(2, 8), (3, 9)]);
+        relations.remove_owner(2);
+        assert!(relations.owner_ids(8).is_empty());
+        assert_eq!(relations.owner_ids(9), vec![3]);
+    }
+}